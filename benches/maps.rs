@@ -0,0 +1,54 @@
+//! Times scripts dominated by map lookups and updates on small maps with
+//! short keys, the shape most script maps have.
+//!
+//! Run with `cargo bench --bench maps`.
+
+use std::time::Instant;
+
+const BENCHES: &[(&str, &str)] = &[
+    (
+        "read fields",
+        "let p = { name: \"x\", x: 1, y: 2, z: 3 };\n\
+         let total = 0;\n\
+         for (i in range(0, 100000)) { total = total + p.x + p.y + p.z; }",
+    ),
+    (
+        "update fields",
+        "let p = { x: 0, y: 0 };\n\
+         for (i in range(0, 100000)) { p.x = p.x + 1; p.y = i; }",
+    ),
+    (
+        "index by string key",
+        "let m = { alpha: 1, beta: 2, gamma: 3, delta: 4 };\n\
+         let keys = [\"alpha\", \"beta\", \"gamma\", \"delta\"];\n\
+         let total = 0;\n\
+         for (i in range(0, 100000)) { total = total + m[keys[i - i / 4 * 4]]; }",
+    ),
+    (
+        "build maps",
+        "let all = range(0, 20000);\n\
+         for (i in range(0, 20000)) { all[i] = { id: i, name: \"n\", ok: true }; }",
+    ),
+    (
+        "count words",
+        "let words = [\"the\", \"quick\", \"brown\", \"fox\", \"jumps\", \"over\", \"the\", \"lazy\", \"dog\"];\n\
+         let counts = {};\n\
+         for (i in range(0, 10000)) {\n\
+           for (w in words) { counts[w] = get(counts, w, 0) + 1; }\n\
+         }",
+    ),
+];
+
+fn main() {
+    for (name, source) in BENCHES {
+        let start = Instant::now();
+        if let Err(error) = ruilian::run(source) {
+            panic!("benchmark '{}' failed: {:?}", name, error);
+        }
+        println!(
+            "{:<24} {:>10.2} ms",
+            name,
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+}
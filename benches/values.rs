@@ -0,0 +1,57 @@
+//! Times scripts that read, copy and pass around large arrays, maps and
+//! strings, where the cost of copying values dominates.
+//!
+//! Run with `cargo bench --bench values`.
+
+use std::time::Instant;
+
+const BENCHES: &[(&str, &str)] = &[
+    (
+        "read large array",
+        "let xs = range(0, 10000); let total = 0;\n\
+         for (i in range(0, 2000)) { total = total + len(xs); }",
+    ),
+    (
+        "index large array",
+        "let xs = range(0, 10000); let total = 0;\n\
+         for (i in range(0, 20000)) { total = total + xs[5]; }",
+    ),
+    (
+        "pass array to function",
+        "let xs = range(0, 10000);\n\
+         fn first(a) { return a[0]; }\n\
+         let total = 0;\n\
+         for (i in range(0, 2000)) { total = total + first(xs); }",
+    ),
+    (
+        "read map field",
+        "let m = { name: \"x\", items: range(0, 5000) };\n\
+         let total = 0;\n\
+         for (i in range(0, 2000)) { total = total + len(m.items); }",
+    ),
+    (
+        "copy then modify",
+        "let xs = range(0, 10000);\n\
+         for (i in range(0, 200)) { let ys = xs; ys[0] = i; }",
+    ),
+    (
+        "read long string",
+        "let s = \"\"; for (i in range(0, 2000)) { s = s + \"abcde\"; }\n\
+         let total = 0;\n\
+         for (i in range(0, 2000)) { let t = s; total = total + 1; }",
+    ),
+];
+
+fn main() {
+    for (name, source) in BENCHES {
+        let start = Instant::now();
+        if let Err(error) = ruilian::run(source) {
+            panic!("benchmark '{}' failed: {:?}", name, error);
+        }
+        println!(
+            "{:<24} {:>10.2} ms",
+            name,
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+}
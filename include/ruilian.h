@@ -0,0 +1,77 @@
+/*
+ * C interface to the RuiLian interpreter. See src/capi.rs for the
+ * ownership rules: functions returning RuilianValue * or char * give the
+ * result to the caller, to free with ruilian_value_free or
+ * ruilian_string_free; all other pointers are borrowed for the call.
+ */
+#ifndef RUILIAN_H
+#define RUILIAN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RuilianInterpreter RuilianInterpreter;
+typedef struct RuilianValue RuilianValue;
+
+typedef enum RuilianType {
+    RUILIAN_NIL = 0,
+    RUILIAN_BOOL = 1,
+    RUILIAN_INT = 2,
+    RUILIAN_FLOAT = 3,
+    RUILIAN_STRING = 4,
+    RUILIAN_ARRAY = 5,
+    RUILIAN_MAP = 6,
+    RUILIAN_FUNCTION = 7,
+    /* Big integers and userdata. */
+    RUILIAN_OTHER = 8
+} RuilianType;
+
+/* Returns a new value owned by the interpreter, or NULL to raise an error. */
+typedef RuilianValue *(*RuilianFn)(void *user_data, const RuilianValue *const *args, size_t argc);
+
+/* Interpreters */
+RuilianInterpreter *ruilian_new(void);
+void ruilian_free(RuilianInterpreter *interpreter);
+/* 0 on success, storing the last expression's value in *result if non-NULL;
+ * 1 on failure, with the message in ruilian_last_error. */
+int ruilian_eval(RuilianInterpreter *interpreter, const char *source, RuilianValue **result);
+const char *ruilian_last_error(const RuilianInterpreter *interpreter);
+RuilianValue *ruilian_get_global(const RuilianInterpreter *interpreter, const char *name);
+void ruilian_set_global(RuilianInterpreter *interpreter, const char *name, const RuilianValue *value);
+void ruilian_register_fn(RuilianInterpreter *interpreter, const char *name, RuilianFn function, void *user_data);
+
+/* Building values */
+RuilianValue *ruilian_value_nil(void);
+RuilianValue *ruilian_value_bool(bool b);
+RuilianValue *ruilian_value_int(int64_t n);
+RuilianValue *ruilian_value_float(double f);
+RuilianValue *ruilian_value_string(const char *text);
+RuilianValue *ruilian_value_array(void);
+RuilianValue *ruilian_value_map(void);
+/* 0 on success, 1 if the target is not an array (map), or 2 if it is a
+   frozen one. */
+int ruilian_value_push(RuilianValue *array, const RuilianValue *item);
+int ruilian_value_set(RuilianValue *map, const char *key, const RuilianValue *item);
+void ruilian_value_free(RuilianValue *value);
+
+/* Reading values */
+int ruilian_value_type(const RuilianValue *value);
+bool ruilian_value_as_int(const RuilianValue *value, int64_t *out);
+bool ruilian_value_as_float(const RuilianValue *value, double *out);
+bool ruilian_value_as_bool(const RuilianValue *value, bool *out);
+char *ruilian_value_to_string(const RuilianValue *value);
+int64_t ruilian_value_len(const RuilianValue *value);
+RuilianValue *ruilian_value_at(const RuilianValue *value, size_t index);
+RuilianValue *ruilian_value_get(const RuilianValue *value, const char *key);
+void ruilian_string_free(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
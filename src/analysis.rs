@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use crate::ast::{Expr, ExprKind, Program, Span, Stmt, StmtKind};
+use crate::diagnostic::Diagnostic;
+use crate::lint::Linter;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::symbol::Symbol;
+use crate::tokens::Token;
+use crate::{resolver, typecheck};
+
+/// Everything that can be found wrong with `source` without running it,
+/// sorted by position: scan and parse errors, or, once it scans and parses
+/// cleanly, the resolver's errors, type errors, `warnings` and what `linter` reports.
+/// The scanner skips what it cannot read, so the parser still gets to
+/// report the syntax errors in the rest of the file.
+pub fn check(source: &str, linter: &Linter) -> Vec<Diagnostic> {
+    let mut scanner = Scanner::new(source);
+    let mut diagnostics = Vec::new();
+    let mut tokens = Vec::new();
+    loop {
+        match scanner.next_token() {
+            Ok(token) => {
+                let eof = token.token == Token::EOF;
+                tokens.push(token);
+                if eof {
+                    break;
+                }
+            }
+            Err(error) => diagnostics.push(Diagnostic::from(&error)),
+        }
+    }
+    match Parser::new(tokens).parse() {
+        Err(errors) => diagnostics.extend(errors.iter().map(Diagnostic::from)),
+        Ok(_) if !diagnostics.is_empty() => {}
+        Ok(mut program) => {
+            diagnostics.extend(resolver::resolve(&mut program));
+            diagnostics.extend(typecheck::check(&program));
+            diagnostics.extend(warnings(&program));
+            diagnostics.extend(linter.check(&program));
+        }
+    }
+    diagnostics.sort_by_key(|d| d.span);
+    diagnostics
+}
+
+/// Static checks that warn about likely mistakes without stopping the script:
+/// unused variables and parameters, and code after an unconditional return.
+pub fn warnings(program: &Program) -> Vec<Diagnostic> {
+    let mut pass = WarningPass::default();
+    pass.open_scope();
+    pass.block(&program.statements);
+    pass.close_scope();
+    pass.diagnostics.sort_by_key(|d| d.span);
+    pass.diagnostics
+}
+
+struct Binding {
+    span: Span,
+    used: bool,
+    what: &'static str,
+}
+
+/// A function, test or benchmark body waiting to be checked once its enclosing scope is
+/// complete, so names the body uses but which are declared later (such as a
+/// helper function defined further down) still resolve.
+struct PendingFunction<'a> {
+    params: &'a [Symbol],
+    /// Where each parameter is written, for its unused warning.
+    param_spans: &'a [Span],
+    body: &'a [Stmt],
+    span: Span,
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    bindings: HashMap<String, Binding>,
+    pending: Vec<PendingFunction<'a>>,
+}
+
+#[derive(Default)]
+struct WarningPass<'a> {
+    scopes: Vec<Scope<'a>>,
+    /// The index in `scopes` of the innermost function body being checked.
+    body: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> WarningPass<'a> {
+    fn open_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    fn close_scope(&mut self) {
+        while let Some(function) = self.scopes.last_mut().and_then(|s| s.pending.pop()) {
+            let outer = std::mem::replace(&mut self.body, self.scopes.len());
+            self.open_scope();
+            for (param, &span) in function.params.iter().zip(function.param_spans) {
+                self.declare(param, span, "parameter");
+            }
+            self.block(function.body);
+            self.close_scope();
+            self.body = outer;
+        }
+        let scope = self.scopes.pop().expect("scope stack underflow");
+        for (name, binding) in scope.bindings {
+            self.report_unused(&name, &binding);
+        }
+    }
+
+    fn declare(&mut self, name: &str, span: Span, what: &'static str) {
+        let binding = Binding {
+            span,
+            used: false,
+            what,
+        };
+        let scope = self.scopes.last_mut().expect("no open scope");
+        if let Some(previous) = scope.bindings.insert(name.to_string(), binding) {
+            self.report_unused(name, &previous);
+        }
+    }
+
+    fn report_unused(&mut self, name: &str, binding: &Binding) {
+        if !binding.used && !name.starts_with('_') {
+            self.diagnostics.push(
+                Diagnostic::warning(
+                    format!("Unused {} '{}'", binding.what, name),
+                    Some(binding.span),
+                )
+                .with_code("W0001"),
+            );
+        }
+    }
+
+    fn mark_used(&mut self, name: &str) {
+        for (index, scope) in self.scopes.iter_mut().enumerate().rev() {
+            if let Some(binding) = scope.bindings.get_mut(name) {
+                binding.used = true;
+                // Outside the body, the binding may be declared after the
+                // function and so hide an outer one only once it is.
+                if index >= self.body {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &'a [Stmt]) {
+        let mut returned = false;
+        let mut warned = false;
+        for stmt in statements {
+            if returned && !warned {
+                self.diagnostics.push(
+                    Diagnostic::warning("Unreachable code", Some(stmt.span)).with_code("W0002"),
+                );
+                warned = true;
+            }
+            self.statement(stmt);
+            returned |= always_returns(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.expression(expr),
+            StmtKind::Let { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.declare(name, stmt.span, "variable");
+            }
+            StmtKind::Block(statements) => {
+                self.open_scope();
+                self.block(statements);
+                self.close_scope();
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.open_scope();
+                self.declare(variable, stmt.span, "variable");
+                self.statement(body);
+                self.close_scope();
+            }
+            StmtKind::Function {
+                name,
+                params,
+                param_spans,
+                body,
+            } => {
+                let scope = self.scopes.last_mut().expect("no open scope");
+                scope.bindings.insert(
+                    name.to_string(),
+                    Binding {
+                        span: stmt.span,
+                        used: true,
+                        what: "function",
+                    },
+                );
+                scope.pending.push(PendingFunction {
+                    params,
+                    param_spans,
+                    body,
+                    span: stmt.span,
+                });
+            }
+            StmtKind::Return { value } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StmtKind::Test { body, .. } | StmtKind::Bench { body, .. } => {
+                let scope = self.scopes.last_mut().expect("no open scope");
+                scope.pending.push(PendingFunction {
+                    params: &[],
+                    param_spans: &[],
+                    body,
+                    span: stmt.span,
+                });
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Number(_)
+            | ExprKind::Float(_)
+            | ExprKind::String(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Nil => {}
+            ExprKind::Variable { name, .. } => self.mark_used(name),
+            ExprKind::Assign { value, .. } => self.expression(value),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Unary { right, .. } => self.expression(right),
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Array(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            ExprKind::Map(pairs) => {
+                for (_, value) in pairs {
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::IndexAssign {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            ExprKind::Dot { object, .. } => self.expression(object),
+            ExprKind::DotAssign { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            ExprKind::Lambda {
+                params,
+                param_spans,
+                body,
+            } => {
+                let scope = self.scopes.last_mut().expect("no open scope");
+                scope.pending.push(PendingFunction {
+                    params,
+                    param_spans,
+                    body,
+                    span: expr.span,
+                });
+            }
+        }
+    }
+}
+
+/// Whether control can never continue past `stmt`.
+fn always_returns(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Return { .. } => true,
+        StmtKind::Block(statements) => statements.iter().any(always_returns),
+        StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
+    }
+}
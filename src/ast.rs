@@ -1,10 +1,50 @@
+use std::rc::Rc;
+
+use crate::symbol::Symbol;
+
+/// Byte range into the source text, as produced by the scanner.
+pub type Span = (usize, usize);
+
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+/// Where a variable lives, filled in by the resolver. The parser leaves
+/// every variable `Unresolved`, which the interpreter looks up by name
+/// through the scope chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Slot {
+    #[default]
+    Unresolved,
+    /// A top-level binding, looked up by name in the global scope.
+    Global,
+    /// Slot `index` of the scope `depth` levels out from the current one.
+    Local { depth: usize, index: usize },
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Number(i64),
-    Map(Vec<(String, Expr)>),
+    Float(f64),
+    Map(Vec<(Symbol, Expr)>),
     String(String),
-    Variable(String),
-    Assign(String, Box<Expr>),
+    Variable {
+        name: Symbol,
+        slot: Slot,
+    },
+    Assign {
+        name: Symbol,
+        slot: Slot,
+        value: Box<Expr>,
+    },
     Binary {
         left: Box<Expr>,
         operator: BinOp,
@@ -24,6 +64,7 @@ pub enum Expr {
         arguments: Vec<Expr>,
     },
     Boolean(bool),
+    Nil,
     Array(Vec<Expr>),
     Index {
         object: Box<Expr>,
@@ -37,14 +78,21 @@ pub enum Expr {
     Dot {
         // Dot notation: obj.field
         object: Box<Expr>,
-        field: String,
+        field: Symbol,
     },
     DotAssign {
         // Dot assignment: obj.field = value
         object: Box<Expr>,
-        field: String,
+        field: Symbol,
         value: Box<Expr>,
     },
+    Lambda {
+        // Anonymous function: fn(params) { body }
+        params: Rc<[Symbol]>,
+        /// Where each of `params` is written, in the same order.
+        param_spans: Rc<[Span]>,
+        body: Rc<Vec<Stmt>>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -74,10 +122,16 @@ pub enum UnaryOp {
 }
 
 #[derive(Debug, Clone)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
     Expr(Expr),
     Let {
-        name: String,
+        name: Symbol,
         initializer: Option<Expr>,
     },
     Print(Expr),
@@ -93,19 +147,37 @@ pub enum Stmt {
     },
     For {
         // <-- ADD THIS!
-        variable: String,
+        variable: Symbol,
         iterable: Box<Expr>,
         body: Box<Stmt>,
     },
+    /// The parameters and body are shared with every function value made
+    /// from this declaration.
     Function {
-        name: String,
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        name: Symbol,
+        params: Rc<[Symbol]>,
+        /// Where each of `params` is written, in the same order.
+        param_spans: Rc<[Span]>,
+        body: Rc<Vec<Stmt>>,
     },
     Return {
         value: Option<Expr>,
     },
+    /// `test "name" { ... }`, skipped by an ordinary run and run on its own
+    /// by `ruilian test`.
+    Test {
+        name: String,
+        body: Rc<Vec<Stmt>>,
+    },
+    /// `bench "name" { ... }`, skipped by an ordinary run and timed by
+    /// `ruilian bench`.
+    Bench {
+        name: String,
+        body: Rc<Vec<Stmt>>,
+    },
 }
+
+#[derive(Debug, Clone)]
 pub struct Program {
     pub statements: Vec<Stmt>,
 }
@@ -0,0 +1,92 @@
+//! Timing the `bench "name" { ... }` blocks of a script, for `ruilian bench`.
+
+use std::time::{Duration, Instant};
+
+use crate::ast::{Program, Span, Stmt, StmtKind};
+use crate::error::RuntimeError;
+use crate::interpreter::Interpreter;
+
+/// How long `ruilian bench` times each benchmark for, after warming up for
+/// a fifth as long.
+pub const DEFAULT_TIME: Duration = Duration::from_secs(1);
+
+/// How one benchmark went.
+#[derive(Debug)]
+pub struct Measurement {
+    pub name: String,
+    /// The whole `bench` block.
+    pub span: Span,
+    /// The timed runs, or the error that stopped the benchmark.
+    pub result: Result<Timing, RuntimeError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    /// How many times the body ran while timed.
+    pub iterations: u64,
+    pub elapsed: Duration,
+}
+
+impl Timing {
+    pub fn ns_per_op(&self) -> f64 {
+        self.elapsed.as_nanos() as f64 / self.iterations as f64
+    }
+}
+
+/// Runs the top level of `program`, then times each benchmark at its top
+/// level in order, passing `report` the measurement of each as it
+/// finishes. A benchmark first runs for a fifth of `time` to warm up and
+/// to estimate how many runs fit in `time`, then that many runs are timed
+/// together. Every benchmark starts from the globals the top level left,
+/// but its runs share them, so a body that grows a global slows itself
+/// down. If the top level fails, its error is returned and nothing is
+/// timed.
+///
+/// What the script prints is discarded, to keep it out of the timings.
+pub fn run_benches(
+    interpreter: &mut Interpreter,
+    program: &Program,
+    time: Duration,
+    mut report: impl FnMut(Measurement),
+) -> Result<(), RuntimeError> {
+    interpreter.set_output_callback(|_| {});
+    interpreter.interpret(program)?;
+    let snapshot = interpreter.snapshot();
+    for stmt in &program.statements {
+        let StmtKind::Bench { name, body } = &stmt.kind else {
+            continue;
+        };
+        interpreter.restore(&snapshot);
+        report(Measurement {
+            name: name.clone(),
+            span: stmt.span,
+            result: measure(interpreter, body, time),
+        });
+    }
+    Ok(())
+}
+
+fn measure(
+    interpreter: &mut Interpreter,
+    body: &[Stmt],
+    time: Duration,
+) -> Result<Timing, RuntimeError> {
+    let warm_up = time / 5;
+    let start = Instant::now();
+    let mut runs: u64 = 0;
+    while runs == 0 || start.elapsed() < warm_up {
+        interpreter.run_block(body)?;
+        runs += 1;
+    }
+    let per_run = start.elapsed().as_nanos() / u128::from(runs);
+    let iterations = (time.as_nanos() / per_run.max(1)).clamp(1, u128::from(u64::MAX)) as u64;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        interpreter.run_block(body)?;
+    }
+    Ok(Timing {
+        iterations,
+        elapsed: start.elapsed(),
+    })
+}
@@ -0,0 +1,242 @@
+//! Arbitrary-precision integers for `Overflow::Promote`, where int results
+//! that do not fit in an `i64` continue as big integers instead of failing.
+//!
+//! Only what the operators need is implemented: `+ - * /` (division
+//! truncates toward zero, like ints), comparison, and decimal formatting.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Sign and magnitude, the magnitude stored as base-2^32 limbs, least
+/// significant first, with no trailing zero limbs. Zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn from_i64(n: i64) -> Self {
+        let mut magnitude = n.unsigned_abs();
+        let mut limbs = Vec::new();
+        while magnitude != 0 {
+            limbs.push(magnitude as u32);
+            magnitude >>= 32;
+        }
+        BigInt {
+            negative: n < 0,
+            magnitude: limbs,
+        }
+    }
+
+    /// Bytes held by the magnitude's limbs.
+    pub fn heap_size(&self) -> usize {
+        self.magnitude.len() * std::mem::size_of::<u32>()
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let mut magnitude: u64 = 0;
+        for &limb in self.magnitude.iter().rev() {
+            magnitude = (magnitude << 32) | limb as u64;
+        }
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(
+                self.negative,
+                add_magnitudes(&self.magnitude, &other.magnitude),
+            );
+        }
+        match compare_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(
+                other.negative,
+                sub_magnitudes(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::new(
+                self.negative,
+                sub_magnitudes(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.negated())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut limbs = vec![0u32; self.magnitude.len() + other.magnitude.len()];
+        for (i, &a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.magnitude.iter().enumerate() {
+                let total = limbs[i + j] as u64 + a as u64 * b as u64 + carry;
+                limbs[i + j] = total as u32;
+                carry = total >> 32;
+            }
+            limbs[i + other.magnitude.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != other.negative, limbs)
+    }
+
+    /// Quotient truncated toward zero. The caller rules out a zero divisor.
+    pub fn div(&self, other: &BigInt) -> BigInt {
+        assert!(!other.is_zero(), "BigInt division by zero");
+        let mut quotient = vec![0u32; self.magnitude.len()];
+        let mut remainder: Vec<u32> = Vec::new();
+        // Schoolbook long division one bit at a time; slow but simple, and
+        // division of huge numbers is rare in scripts.
+        for i in (0..self.magnitude.len() * 32).rev() {
+            shift_left_one(&mut remainder);
+            if self.magnitude[i / 32] >> (i % 32) & 1 == 1 {
+                if remainder.is_empty() {
+                    remainder.push(1);
+                } else {
+                    remainder[0] |= 1;
+                }
+            }
+            if compare_magnitudes(&remainder, &other.magnitude) != Ordering::Less {
+                remainder = sub_magnitudes(&remainder, &other.magnitude);
+                quotient[i / 32] |= 1 << (i % 32);
+            }
+        }
+        BigInt::new(self.negative != other.negative, quotient)
+    }
+
+    pub fn negated(&self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Peel off base-10^9 chunks, least significant first.
+        let mut chunks = Vec::new();
+        let mut limbs = self.magnitude.clone();
+        while !limbs.is_empty() {
+            let mut remainder = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let current = (remainder << 32) | *limb as u64;
+                *limb = (current / 1_000_000_000) as u32;
+                remainder = current % 1_000_000_000;
+            }
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+            chunks.push(remainder as u32);
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let total = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        limbs.push(total as u32);
+        carry = total >> 32;
+    }
+    if carry != 0 {
+        limbs.push(carry as u32);
+    }
+    limbs
+}
+
+/// `a - b` where `a >= b`.
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut difference = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if difference < 0 {
+            difference += 1 << 32;
+            borrow = 1;
+        }
+        limbs.push(difference as u32);
+    }
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    limbs
+}
+
+fn shift_left_one(limbs: &mut Vec<u32>) {
+    let mut carry = 0;
+    for limb in limbs.iter_mut() {
+        let next_carry = *limb >> 31;
+        *limb = (*limb << 1) | carry;
+        carry = next_carry;
+    }
+    if carry != 0 {
+        limbs.push(carry);
+    }
+}
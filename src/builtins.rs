@@ -0,0 +1,221 @@
+//! The table of functions a script can call by name without defining them.
+//!
+//! Each interpreter holds its own table. It starts out as the standard set,
+//! and the host can remove entries (say, the file and network builtins),
+//! expose a standard builtin under another name, or add functions of its
+//! own. A call reaches the table only when the script has not bound the
+//! name itself, so a script function or variable of the same name shadows
+//! the builtin, and a name missing from the table is an ordinary variable.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::environment::Value;
+use crate::error::RuntimeError;
+use crate::interpreter::{BUILTIN_NAMES, StandardFunction, standard_function};
+
+/// A group of standard builtins reaching outside the interpreter. Each is
+/// compiled in only with the cargo feature of the same name (all on by
+/// default), so a build without it cannot touch that resource at all; a
+/// host can also revoke one at run time with `Builtins::revoke`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Files and directories: the `fs` feature.
+    FileSystem,
+    /// HTTP requests: the `net` feature.
+    Network,
+    /// Subprocesses and environment variables: the `process` feature.
+    Process,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 3] = [
+        Capability::FileSystem,
+        Capability::Network,
+        Capability::Process,
+    ];
+
+    /// The standard builtins this capability covers.
+    pub fn builtins(self) -> &'static [&'static str] {
+        match self {
+            Capability::FileSystem => &[
+                "read_file",
+                "write_file",
+                "append_file",
+                "list_dir",
+                "exists",
+                "is_dir",
+                "mkdir",
+                "remove",
+            ],
+            Capability::Network => &["http_get", "http_post"],
+            Capability::Process => &["exec", "getenv", "setenv"],
+        }
+    }
+
+    /// Whether this build includes the capability's builtins.
+    pub fn is_compiled_in(self) -> bool {
+        match self {
+            Capability::FileSystem => cfg!(feature = "fs"),
+            Capability::Network => cfg!(feature = "net"),
+            Capability::Process => cfg!(feature = "process"),
+        }
+    }
+}
+
+/// Every standard builtin this build includes.
+pub(crate) fn standard_names() -> impl Iterator<Item = &'static str> {
+    let gated = Capability::ALL
+        .into_iter()
+        .filter(|capability| capability.is_compiled_in())
+        .flat_map(|capability| capability.builtins().iter().copied());
+    BUILTIN_NAMES.iter().copied().chain(gated)
+}
+
+pub(crate) fn is_standard(name: &str) -> bool {
+    standard_names().any(|standard| standard == name)
+}
+
+/// A function the host provides to scripts, called with the evaluated
+/// arguments.
+pub type NativeFunction = Rc<dyn Fn(&[Value]) -> Result<Value, RuntimeError>>;
+
+/// A pack of builtins shipped outside this crate, such as a database
+/// module. A plugin crate implements this and the host installs it with
+/// `Builtins::install` or `Interpreter::load_plugin`, so adding one needs no
+/// change here. A plugin should prefix its names (`db_query`) to stay clear
+/// of the standard builtins and of other plugins.
+pub trait Plugin {
+    /// Adds the plugin's functions to `builtins`.
+    fn register(&self, builtins: &mut Builtins);
+}
+
+/// A closure can serve as a small plugin.
+impl<F: Fn(&mut Builtins)> Plugin for F {
+    fn register(&self, builtins: &mut Builtins) {
+        self(builtins)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum Builtin {
+    /// One of the interpreter's own builtins: its standard name and the
+    /// function implementing it.
+    Standard(&'static str, StandardFunction),
+    Native(NativeFunction),
+}
+
+impl Builtin {
+    fn standard(name: &'static str) -> Self {
+        let function = standard_function(name).expect("standard builtin without a function");
+        Builtin::Standard(name, function)
+    }
+}
+
+#[derive(Clone)]
+pub struct Builtins {
+    entries: HashMap<String, Builtin>,
+}
+
+impl Default for Builtins {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl Builtins {
+    /// Every standard builtin under its usual name.
+    pub fn standard() -> Self {
+        Builtins {
+            entries: standard_names()
+                .map(|name| (name.to_string(), Builtin::standard(name)))
+                .collect(),
+        }
+    }
+
+    /// No builtins at all, not even `print`, for hosts that add exactly the
+    /// functions they want.
+    pub fn empty() -> Self {
+        Builtins {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Adds the standard builtin `standard` under `name`, returning false if
+    /// there is no such standard builtin.
+    pub fn add_standard(&mut self, name: &str, standard: &str) -> bool {
+        match standard_names().find(|&known| known == standard) {
+            Some(standard) => {
+                self.entries
+                    .insert(name.to_string(), Builtin::standard(standard));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes `function` callable from scripts as `name`, replacing any
+    /// builtin of that name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        self.entries
+            .insert(name.to_string(), Builtin::Native(Rc::new(function)));
+    }
+
+    /// Adds everything `plugin` provides.
+    pub fn install(&mut self, plugin: &dyn Plugin) {
+        plugin.register(self);
+    }
+
+    /// Removes every standard builtin `capability` covers, under whatever
+    /// name it was added.
+    pub fn revoke(&mut self, capability: Capability) {
+        let covered = capability.builtins();
+        self.entries.retain(|_, builtin| match builtin {
+            Builtin::Standard(name, _) => !covered.contains(name),
+            Builtin::Native(_) => true,
+        });
+    }
+
+    /// Removes the builtin `name`, returning whether there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    /// Moves the builtin `from` to the name `to`, returning false if there
+    /// is no builtin `from`.
+    pub fn rename(&mut self, from: &str, to: &str) -> bool {
+        match self.entries.remove(from) {
+            Some(builtin) => {
+                self.entries.insert(to.to_string(), builtin);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// The names scripts can call, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Builtin> {
+        self.entries.get(name)
+    }
+}
+
+impl fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&str> = self.names().collect();
+        names.sort_unstable();
+        f.debug_tuple("Builtins").field(&names).finish()
+    }
+}
@@ -0,0 +1,115 @@
+//! Parsed programs kept between runs, for hosts that execute the same
+//! scripts over and over (templates, plugins, request handlers) and would
+//! otherwise scan, parse and resolve each one every time.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::ast::Program;
+use crate::error::RunError;
+
+/// How often a `ProgramCache` found a program already parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// Programs keyed by a hash of their source. Each entry keeps its source
+/// too, so two scripts whose hashes collide are never confused. Once
+/// `capacity` programs are held, the least recently used one makes room for
+/// the next. Sources that fail to scan or parse are not cached.
+pub struct ProgramCache {
+    entries: HashMap<u64, Entry>,
+    capacity: usize,
+    /// Counts lookups, to order entries by last use.
+    clock: u64,
+    stats: CacheStats,
+}
+
+struct Entry {
+    source: Box<str>,
+    program: Rc<Program>,
+    last_used: u64,
+}
+
+impl ProgramCache {
+    /// A cache holding up to `capacity` programs (at least one).
+    pub fn new(capacity: usize) -> Self {
+        ProgramCache {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// The program for `source`, parsed now only if it is not cached.
+    pub fn get(&mut self, source: &str) -> Result<Rc<Program>, RunError> {
+        self.clock += 1;
+        let key = source_hash(source);
+        if let Some(entry) = self.entries.get_mut(&key)
+            && *entry.source == *source
+        {
+            entry.last_used = self.clock;
+            self.stats.hits += 1;
+            return Ok(Rc::clone(&entry.program));
+        }
+        self.stats.misses += 1;
+        let program = Rc::new(crate::compile(source)?);
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            self.evict();
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                source: source.into(),
+                program: Rc::clone(&program),
+                last_used: self.clock,
+            },
+        );
+        Ok(program)
+    }
+
+    /// Like `ruilian::run`, but reuses the parsed program for a source seen
+    /// before.
+    pub fn run(&mut self, source: &str) -> Result<(), RunError> {
+        let program = self.get(source)?;
+        crate::execute(&program)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(&key, _)| key);
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+fn source_hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,490 @@
+//! A C interface to the interpreter, declared in `include/ruilian.h`, for
+//! C, C++ and Python (ctypes, cffi) hosts. Link against the cdylib the
+//! crate builds.
+//!
+//! Interpreters and values are opaque heap objects. Every function
+//! returning `RuilianValue *` or `char *` hands ownership to the caller,
+//! who frees it with `ruilian_value_free` or `ruilian_string_free`; every
+//! other pointer argument is borrowed for the duration of the call. Strings
+//! are NUL-terminated UTF-8, with invalid sequences replaced. Pointers must
+//! be valid and, unless documented otherwise, non-null. An interpreter and
+//! the values taken from it must stay on one thread.
+
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+
+use crate::environment::Value;
+use crate::error::RuntimeError;
+use crate::interpreter::{Interpreter, value_to_string};
+
+pub struct RuilianInterpreter {
+    interpreter: Interpreter,
+    last_error: Option<CString>,
+}
+
+/// A script value. Transparent so the arguments passed to a registered
+/// function can be lent to C without copying them.
+#[repr(transparent)]
+pub struct RuilianValue(Value);
+
+/// Kinds reported by `ruilian_value_type`, matching `RuilianType` in the
+/// header.
+pub const RUILIAN_NIL: c_int = 0;
+pub const RUILIAN_BOOL: c_int = 1;
+pub const RUILIAN_INT: c_int = 2;
+pub const RUILIAN_FLOAT: c_int = 3;
+pub const RUILIAN_STRING: c_int = 4;
+pub const RUILIAN_ARRAY: c_int = 5;
+pub const RUILIAN_MAP: c_int = 6;
+pub const RUILIAN_FUNCTION: c_int = 7;
+/// Big integers and userdata.
+pub const RUILIAN_OTHER: c_int = 8;
+
+/// A host function for `ruilian_register_fn`. It receives the user data
+/// given at registration and the arguments, borrowed for the call, and
+/// returns the result, which the interpreter takes ownership of. Returning
+/// NULL raises a runtime error in the script.
+pub type RuilianFn = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    args: *const *const RuilianValue,
+    argc: usize,
+) -> *mut RuilianValue;
+
+unsafe fn str_arg<'a>(text: *const c_char) -> std::borrow::Cow<'a, str> {
+    // SAFETY: the caller passes a valid NUL-terminated string.
+    unsafe { CStr::from_ptr(text) }.to_string_lossy()
+}
+
+fn c_string(text: &str) -> CString {
+    CString::new(text.replace('\0', "\u{FFFD}")).unwrap_or_default()
+}
+
+fn boxed(value: Value) -> *mut RuilianValue {
+    Box::into_raw(Box::new(RuilianValue(value)))
+}
+
+/// The value with any frozen marker removed, since C sees no difference.
+unsafe fn value<'a>(value: *const RuilianValue) -> &'a Value {
+    // SAFETY: the caller passes a live value.
+    let mut value = &unsafe { &*value }.0;
+    while let Value::Frozen(inner) = value {
+        value = inner;
+    }
+    value
+}
+
+// ---- Interpreters ----
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_new() -> *mut RuilianInterpreter {
+    Box::into_raw(Box::new(RuilianInterpreter {
+        interpreter: Interpreter::new(),
+        last_error: None,
+    }))
+}
+
+/// Frees an interpreter. NULL is ignored.
+///
+/// # Safety
+///
+/// `interpreter` must be NULL or come from `ruilian_new` and not have been
+/// freed yet. Values taken from it stay valid; the interpreter must not be
+/// used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_free(interpreter: *mut RuilianInterpreter) {
+    if !interpreter.is_null() {
+        // SAFETY: the pointer came from `ruilian_new` and is given up here.
+        drop(unsafe { Box::from_raw(interpreter) });
+    }
+}
+
+/// Runs `source` in the interpreter's global scope, as successive calls
+/// share it. Returns 0 on success, storing the value of the script's last
+/// expression statement in `*result` unless `result` is NULL, or 1 on
+/// failure, leaving the message for `ruilian_last_error`.
+///
+/// # Safety
+///
+/// `interpreter` must be a live interpreter, `source` a NUL-terminated
+/// string, and `result` NULL or valid for writing a pointer. The caller
+/// owns the value stored in `*result`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_eval(
+    interpreter: *mut RuilianInterpreter,
+    source: *const c_char,
+    result: *mut *mut RuilianValue,
+) -> c_int {
+    // SAFETY: the caller passes a live interpreter and a valid string.
+    let (state, source) = unsafe { (&mut *interpreter, str_arg(source)) };
+    let outcome = crate::compile(&source)
+        .and_then(|program| Ok(state.interpreter.interpret_value(&program)?));
+    match outcome {
+        Ok(value) => {
+            state.last_error = None;
+            if !result.is_null() {
+                // SAFETY: a non-null `result` points to writable storage.
+                unsafe { *result = boxed(value) };
+            }
+            0
+        }
+        Err(error) => {
+            state.last_error = Some(c_string(&error.to_string()));
+            1
+        }
+    }
+}
+
+/// The message of the last failed `ruilian_eval`, or NULL if it succeeded.
+/// The string belongs to the interpreter and lasts until the next eval.
+///
+/// # Safety
+///
+/// `interpreter` must be a live interpreter. The string must not be freed
+/// or used after the next `ruilian_eval` or `ruilian_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_last_error(
+    interpreter: *const RuilianInterpreter,
+) -> *const c_char {
+    // SAFETY: the caller passes a live interpreter.
+    match &unsafe { &*interpreter }.last_error {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// A copy of the global `name`, or NULL if there is no such global.
+///
+/// # Safety
+///
+/// `interpreter` must be a live interpreter and `name` a NUL-terminated
+/// string. The caller owns the returned value.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_get_global(
+    interpreter: *const RuilianInterpreter,
+    name: *const c_char,
+) -> *mut RuilianValue {
+    // SAFETY: the caller passes a live interpreter and a valid string.
+    let (state, name) = unsafe { (&*interpreter, str_arg(name)) };
+    match state.interpreter.get_global(&name) {
+        Some(value) => boxed(value),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Binds the global `name` to a copy of `value`.
+///
+/// # Safety
+///
+/// `interpreter` must be a live interpreter, `name` a NUL-terminated
+/// string and `value` a live value, which stays the caller's.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_set_global(
+    interpreter: *mut RuilianInterpreter,
+    name: *const c_char,
+    value: *const RuilianValue,
+) {
+    // SAFETY: the caller passes a live interpreter, a valid string and a
+    // live value.
+    let (state, name, value) = unsafe { (&mut *interpreter, str_arg(name), &(*value).0) };
+    state.interpreter.set_global(&name, value.clone());
+}
+
+/// Makes `function` callable from scripts as `name`, passing it
+/// `user_data` on every call. The host keeps `user_data` alive for as long
+/// as the interpreter.
+///
+/// # Safety
+///
+/// `interpreter` must be a live interpreter, `name` a NUL-terminated
+/// string and `function` follow the contract of `RuilianFn`. `user_data`
+/// is passed through untouched and must stay valid for as long as the
+/// interpreter can call `function`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_register_fn(
+    interpreter: *mut RuilianInterpreter,
+    name: *const c_char,
+    function: RuilianFn,
+    user_data: *mut c_void,
+) {
+    // SAFETY: the caller passes a live interpreter and a valid string.
+    let (state, name) = unsafe { (&mut *interpreter, str_arg(name).into_owned()) };
+    let label = name.clone();
+    state
+        .interpreter
+        .builtins_mut()
+        .register(&name, move |args: &[Value]| {
+            let args: Vec<*const RuilianValue> = args
+                .iter()
+                .map(|arg| arg as *const Value as *const RuilianValue)
+                .collect();
+            // SAFETY: the host registered a function following `RuilianFn`;
+            // the arguments outlive the call.
+            let result = unsafe { function(user_data, args.as_ptr(), args.len()) };
+            if result.is_null() {
+                return Err(RuntimeError::new(format!("{}() failed", label)));
+            }
+            // SAFETY: a non-null result is a value the function gives up.
+            Ok(unsafe { Box::from_raw(result) }.0)
+        });
+}
+
+// ---- Building values ----
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_value_nil() -> *mut RuilianValue {
+    boxed(Value::Nil)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_value_bool(b: bool) -> *mut RuilianValue {
+    boxed(Value::Boolean(b))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_value_int(n: i64) -> *mut RuilianValue {
+    boxed(Value::Number(n))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_value_float(f: f64) -> *mut RuilianValue {
+    boxed(Value::Float(f))
+}
+
+/// # Safety
+///
+/// `text` must be a NUL-terminated string. The caller owns the returned
+/// value.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_string(text: *const c_char) -> *mut RuilianValue {
+    // SAFETY: the caller passes a valid string.
+    boxed(Value::string(unsafe { str_arg(text) }))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_value_array() -> *mut RuilianValue {
+    boxed(Value::array([]))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ruilian_value_map() -> *mut RuilianValue {
+    boxed(Value::map([]))
+}
+
+/// Appends a copy of `item` to `array`. Returns 0, 1 if `array` is not an
+/// array, or 2 if it is a frozen one, which `ruilian_value_type` still
+/// reports as `RUILIAN_ARRAY`.
+///
+/// # Safety
+///
+/// `array` must be a live value not borrowed elsewhere during the call,
+/// and `item` a live value, which stays the caller's.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_push(
+    array: *mut RuilianValue,
+    item: *const RuilianValue,
+) -> c_int {
+    // SAFETY: the caller passes two live values.
+    let (array, item) = unsafe { (&mut (*array).0, &(*item).0) };
+    match array {
+        Value::Array(items) => {
+            Rc::make_mut(items).push(item.clone());
+            0
+        }
+        Value::Frozen(inner) if matches!(**inner, Value::Array(_)) => 2,
+        _ => 1,
+    }
+}
+
+/// Sets `key` in `map` to a copy of `item`. Returns 0, 1 if `map` is not a
+/// map, or 2 if it is a frozen one, which `ruilian_value_type` still
+/// reports as `RUILIAN_MAP`.
+///
+/// # Safety
+///
+/// `map` must be a live value not borrowed elsewhere during the call, `key`
+/// a NUL-terminated string and `item` a live value, which stays the
+/// caller's.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_set(
+    map: *mut RuilianValue,
+    key: *const c_char,
+    item: *const RuilianValue,
+) -> c_int {
+    // SAFETY: the caller passes two live values and a valid string.
+    let (map, key, item) = unsafe { (&mut (*map).0, str_arg(key), &(*item).0) };
+    match map {
+        Value::Map(entries) => {
+            Rc::make_mut(entries).insert(key.into_owned(), item.clone());
+            0
+        }
+        Value::Frozen(inner) if matches!(**inner, Value::Map(_)) => 2,
+        _ => 1,
+    }
+}
+
+/// Frees a value. NULL is ignored.
+///
+/// # Safety
+///
+/// `value` must be NULL or a value this library handed out that has not
+/// been freed yet, and must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_free(value: *mut RuilianValue) {
+    if !value.is_null() {
+        // SAFETY: the value was handed out by this library and is given up.
+        drop(unsafe { Box::from_raw(value) });
+    }
+}
+
+// ---- Reading values ----
+
+/// # Safety
+///
+/// `value` must be a live value.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_type(value: *const RuilianValue) -> c_int {
+    // SAFETY: the caller passes a live value.
+    match unsafe { self::value(value) } {
+        Value::Nil => RUILIAN_NIL,
+        Value::Boolean(_) => RUILIAN_BOOL,
+        Value::Number(_) => RUILIAN_INT,
+        Value::Float(_) => RUILIAN_FLOAT,
+        Value::String(_) => RUILIAN_STRING,
+        Value::Array(_) => RUILIAN_ARRAY,
+        Value::Map(_) => RUILIAN_MAP,
+        Value::Function(_) => RUILIAN_FUNCTION,
+        _ => RUILIAN_OTHER,
+    }
+}
+
+/// Stores an int in `*out` and returns true, or returns false if the value
+/// is not an int.
+///
+/// # Safety
+///
+/// `value` must be a live value and `out` valid for writing an `i64`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_as_int(value: *const RuilianValue, out: *mut i64) -> bool {
+    // SAFETY: the caller passes a live value and writable storage.
+    match unsafe { self::value(value) } {
+        Value::Number(n) => {
+            unsafe { *out = *n };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Stores a float, or an int converted to one, in `*out` and returns true;
+/// returns false for any other value.
+///
+/// # Safety
+///
+/// `value` must be a live value and `out` valid for writing an `f64`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_as_float(value: *const RuilianValue, out: *mut f64) -> bool {
+    // SAFETY: the caller passes a live value and writable storage.
+    let f = match unsafe { self::value(value) } {
+        Value::Float(f) => *f,
+        Value::Number(n) => *n as f64,
+        _ => return false,
+    };
+    unsafe { *out = f };
+    true
+}
+
+/// # Safety
+///
+/// `value` must be a live value and `out` valid for writing a `bool`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_as_bool(value: *const RuilianValue, out: *mut bool) -> bool {
+    // SAFETY: the caller passes a live value and writable storage.
+    match unsafe { self::value(value) } {
+        Value::Boolean(b) => {
+            unsafe { *out = *b };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The value as `print` would show it; a string comes back unquoted.
+///
+/// # Safety
+///
+/// `value` must be a live value. The caller owns the returned string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_to_string(value: *const RuilianValue) -> *mut c_char {
+    // SAFETY: the caller passes a live value.
+    c_string(&value_to_string(unsafe { self::value(value) })).into_raw()
+}
+
+/// Characters in a string, items in an array or entries in a map; -1 for
+/// anything else.
+///
+/// # Safety
+///
+/// `value` must be a live value.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_len(value: *const RuilianValue) -> i64 {
+    // SAFETY: the caller passes a live value.
+    match unsafe { self::value(value) } {
+        Value::String(s) => s.chars().count() as i64,
+        Value::Array(items) => items.len() as i64,
+        Value::Map(entries) => entries.len() as i64,
+        _ => -1,
+    }
+}
+
+/// A copy of item `index` of an array, or NULL if it is out of range or the
+/// value is not an array.
+///
+/// # Safety
+///
+/// `value` must be a live value. The caller owns the returned value.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_at(
+    value: *const RuilianValue,
+    index: usize,
+) -> *mut RuilianValue {
+    // SAFETY: the caller passes a live value.
+    match unsafe { self::value(value) } {
+        Value::Array(items) => items.get(index).cloned().map_or(ptr::null_mut(), boxed),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// A copy of the entry `key` of a map, or NULL if it is missing or the
+/// value is not a map.
+///
+/// # Safety
+///
+/// `value` must be a live value and `key` a NUL-terminated string. The
+/// caller owns the returned value.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_value_get(
+    value: *const RuilianValue,
+    key: *const c_char,
+) -> *mut RuilianValue {
+    // SAFETY: the caller passes a live value and a valid string.
+    let (value, key) = unsafe { (self::value(value), str_arg(key)) };
+    match value {
+        Value::Map(entries) => entries.get(&*key).cloned().map_or(ptr::null_mut(), boxed),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Frees a string returned by this library. NULL is ignored.
+///
+/// # Safety
+///
+/// `text` must be NULL or a string this library handed out that has not
+/// been freed yet, and must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ruilian_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        // SAFETY: the string came from `CString::into_raw` here.
+        drop(unsafe { CString::from_raw(text) });
+    }
+}
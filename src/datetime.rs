@@ -0,0 +1,181 @@
+//! UTC calendar conversions for the `time_format` / `time_parse` builtins.
+//!
+//! Timestamps are whole seconds since the Unix epoch. Formats use a small
+//! strftime subset: `%Y %y %m %d %H %M %S %j %%`.
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Broken-down UTC time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    pub fn from_timestamp(ts: i64) -> Self {
+        let days = ts.div_euclid(SECONDS_PER_DAY);
+        let secs = ts.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (secs / 3600) as u32,
+            minute: (secs % 3600 / 60) as u32,
+            second: (secs % 60) as u32,
+        }
+    }
+
+    pub fn to_timestamp(self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+
+    fn day_of_year(self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1
+    }
+}
+
+pub fn format(ts: i64, fmt: &str) -> Result<String, String> {
+    let dt = DateTime::from_timestamp(ts);
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", dt.year)),
+            Some('y') => out.push_str(&format!("{:02}", dt.year.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{:02}", dt.month)),
+            Some('d') => out.push_str(&format!("{:02}", dt.day)),
+            Some('H') => out.push_str(&format!("{:02}", dt.hour)),
+            Some('M') => out.push_str(&format!("{:02}", dt.minute)),
+            Some('S') => out.push_str(&format!("{:02}", dt.second)),
+            Some('j') => out.push_str(&format!("{:03}", dt.day_of_year())),
+            Some('%') => out.push('%'),
+            Some(other) => return Err(format!("unsupported format directive '%{}'", other)),
+            None => return Err("format string ends with a lone '%'".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+/// Parses `input` against `fmt`, returning None when it doesn't match.
+/// Fields missing from the format default to 1970-01-01 00:00:00.
+pub fn parse(input: &str, fmt: &str) -> Result<Option<i64>, String> {
+    let mut dt = DateTime::from_timestamp(0);
+    let input: Vec<char> = input.chars().collect();
+    let mut pos = 0;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            if input.get(pos) != Some(&c) {
+                return Ok(None);
+            }
+            pos += 1;
+            continue;
+        }
+        let directive = chars
+            .next()
+            .ok_or_else(|| "format string ends with a lone '%'".to_string())?;
+        let width = match directive {
+            'Y' => 4,
+            'j' => 3,
+            'y' | 'm' | 'd' | 'H' | 'M' | 'S' => 2,
+            '%' => {
+                if input.get(pos) != Some(&'%') {
+                    return Ok(None);
+                }
+                pos += 1;
+                continue;
+            }
+            other => return Err(format!("unsupported format directive '%{}'", other)),
+        };
+        let Some(value) = take_digits(&input, &mut pos, width) else {
+            return Ok(None);
+        };
+        match directive {
+            'Y' => dt.year = value,
+            'y' => dt.year = 2000 + value,
+            'm' => dt.month = value as u32,
+            'd' => dt.day = value as u32,
+            'H' => dt.hour = value as u32,
+            'M' => dt.minute = value as u32,
+            'S' => dt.second = value as u32,
+            'j' => {
+                dt.month = 1;
+                dt.day = 1;
+                let start = dt.to_timestamp();
+                let parsed = DateTime::from_timestamp(start + (value - 1) * SECONDS_PER_DAY);
+                dt.month = parsed.month;
+                dt.day = parsed.day;
+            }
+            _ => unreachable!(),
+        }
+    }
+    if pos != input.len() || !is_valid(&dt) {
+        return Ok(None);
+    }
+    Ok(Some(dt.to_timestamp()))
+}
+
+fn take_digits(input: &[char], pos: &mut usize, width: usize) -> Option<i64> {
+    let end = *pos + width;
+    if end > input.len() || !input[*pos..end].iter().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits: String = input[*pos..end].iter().collect();
+    *pos = end;
+    digits.parse().ok()
+}
+
+fn is_valid(dt: &DateTime) -> bool {
+    (1..=12).contains(&dt.month)
+        && dt.day >= 1
+        && dt.day <= days_in_month(dt.year, dt.month)
+        && dt.hour < 24
+        && dt.minute < 60
+        && dt.second < 60
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Howard Hinnant's days_from_civil / civil_from_days algorithms.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,273 @@
+use std::fmt::Write;
+
+use crate::ast::Span;
+use crate::error::{ParseError, RunError, RuntimeError, ScanError};
+use crate::tokens::Position;
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Extra context printed under the snippet, e.g. one frame of a stack trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+/// A message about a location in the source, renderable as the offending
+/// line with the span underlined. Scan, parse and runtime errors all convert
+/// into one so every tool reports problems the same way.
+///
+/// Codes are stable identifiers for a class of problem: `E0001` scan errors,
+/// `E0002` parse errors, `E0003`/`E0004` undefined and not-yet-defined
+/// names found by the resolver, `E01xx` runtime errors (see
+/// `RuntimeErrorKind::code`), `E02xx` type-check errors, `W00xx` warnings
+/// and `W01xx` lint rules (see `Rule::code`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span)
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Option<Span>) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            span,
+        });
+        self
+    }
+
+    /// Renders the diagnostic against `source`, using ANSI colors when
+    /// `color` is set:
+    ///
+    /// ```text
+    /// error: Division by zero
+    ///  --> 2:10
+    ///   |
+    /// 2 |   return x / 0;
+    ///   |          ^^^^^
+    /// ```
+    pub fn render(&self, source: &str, color: bool) -> String {
+        self.render_in(None, source, color)
+    }
+
+    /// Like `render`, but names the file the source came from in the
+    /// location line (`--> script.rl:2:10`).
+    pub fn render_in(&self, path: Option<&str>, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+        let (label, accent) = match self.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+
+        let label = match self.code {
+            Some(code) => format!("{}[{}]", label, code),
+            None => label.to_string(),
+        };
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{}{}",
+            paint(accent, &label),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        let Some((start, end)) = self.span else {
+            for note in &self.notes {
+                let _ = write!(out, "\n  {} {}", paint(BLUE, "="), note.message);
+            }
+            return out;
+        };
+
+        let start = start.min(source.len());
+        let end = end.clamp(start, source.len());
+        let position = Position::from_offset(source, start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_text = &source[line_start..line_end];
+        let underline = source[start..end.min(line_end)].chars().count().max(1);
+
+        let number = position.line.to_string();
+        let pad = " ".repeat(number.len());
+        let bar = paint(BLUE, "|");
+        let file = path.map(|path| format!("{}:", path)).unwrap_or_default();
+        let _ = write!(
+            out,
+            "\n{}{} {}{}:{}",
+            pad,
+            paint(BLUE, "-->"),
+            file,
+            position.line,
+            position.column
+        );
+        let _ = write!(out, "\n{} {}", pad, bar);
+        let _ = write!(out, "\n{} {} {}", paint(BLUE, &number), bar, line_text);
+        let _ = write!(
+            out,
+            "\n{} {} {}{}",
+            pad,
+            bar,
+            " ".repeat(position.column - 1),
+            paint(accent, &"^".repeat(underline))
+        );
+
+        for note in &self.notes {
+            let location = note.span.map(|(start, _)| {
+                let position = Position::from_offset(source, start);
+                format!(" at {}:{}", position.line, position.column)
+            });
+            let _ = write!(
+                out,
+                "\n{} {} {}{}",
+                pad,
+                paint(BLUE, "="),
+                note.message,
+                location.unwrap_or_default()
+            );
+        }
+        out
+    }
+
+    /// Encodes the diagnostic as a single-line JSON object, resolving spans
+    /// against `source` to 1-based line and column numbers:
+    ///
+    /// ```text
+    /// {"severity":"error","code":"E0104","message":"Division by zero",
+    ///  "span":{"start":19,"end":24,"line":2,"column":9},"notes":[]}
+    /// ```
+    pub fn to_json(&self, source: &str) -> String {
+        let span_json = |span: Option<Span>| match span {
+            Some((start, end)) => {
+                let position = Position::from_offset(source, start.min(source.len()));
+                format!(
+                    "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}",
+                    start, end, position.line, position.column
+                )
+            }
+            None => "null".to_string(),
+        };
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let notes: Vec<String> = self
+            .notes
+            .iter()
+            .map(|note| {
+                format!(
+                    "{{\"message\":{},\"span\":{}}}",
+                    json_string(&note.message),
+                    span_json(note.span)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"severity\":\"{}\",\"code\":{},\"message\":{},\"span\":{},\"notes\":[{}]}}",
+            severity,
+            self.code.map_or("null".to_string(), json_string),
+            json_string(&self.message),
+            span_json(self.span),
+            notes.join(",")
+        )
+    }
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Diagnostic {
+    /// One diagnostic per scan or parse error, or the runtime error.
+    pub fn from_run_error(error: &RunError) -> Vec<Diagnostic> {
+        match error {
+            RunError::Scan(errors) => errors.iter().map(Diagnostic::from).collect(),
+            RunError::Parse(errors) => errors.iter().map(Diagnostic::from).collect(),
+            RunError::Runtime(error) => vec![error.into()],
+        }
+    }
+}
+
+impl From<&ScanError> for Diagnostic {
+    fn from(error: &ScanError) -> Self {
+        Diagnostic::error(error.message.clone(), Some(error.span)).with_code("E0001")
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic::error(error.message.clone(), Some(error.span)).with_code("E0002")
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        let mut diagnostic =
+            Diagnostic::error(error.message.clone(), error.span).with_code(error.kind.code());
+        for frame in &error.trace {
+            diagnostic = diagnostic.with_note(
+                format!("in {}() called", frame.function),
+                Some(frame.call_site),
+            );
+        }
+        diagnostic
+    }
+}
@@ -0,0 +1,333 @@
+//! Printable views of a parsed program: an indented tree for reading and
+//! JSON for tools. Both are rendered from one generic description of the
+//! syntax tree, so they always show the same nodes and fields.
+
+use std::fmt::Write;
+
+use crate::ast::{BinOp, Expr, ExprKind, LogicalOp, Program, Span, Stmt, StmtKind, UnaryOp};
+use crate::diagnostic::json_string;
+use crate::symbol::Symbol;
+use crate::tokens::Position;
+
+/// One syntax tree node: its kind (`Let`, `Binary`, ...), where it came
+/// from, and its fields in declaration order.
+struct Node {
+    kind: &'static str,
+    span: Option<Span>,
+    fields: Vec<(&'static str, Field)>,
+}
+
+enum Field {
+    /// A name or operator, shown bare in the tree.
+    Name(String),
+    /// A string literal, quoted in the tree.
+    Str(String),
+    /// A number or boolean, the same in both views.
+    Raw(String),
+    /// A parameter list.
+    Names(Vec<String>),
+    Node(Node),
+    Nodes(Vec<Node>),
+}
+
+impl Node {
+    fn new(kind: &'static str, span: Option<Span>) -> Self {
+        Node {
+            kind,
+            span,
+            fields: Vec::new(),
+        }
+    }
+
+    fn field(mut self, name: &'static str, field: Field) -> Self {
+        self.fields.push((name, field));
+        self
+    }
+
+    fn program(program: &Program) -> Node {
+        Node::new("Program", None).field("statements", statements(&program.statements))
+    }
+
+    fn stmt(stmt: &Stmt) -> Node {
+        let node = |kind| Node::new(kind, Some(stmt.span));
+        match &stmt.kind {
+            StmtKind::Expr(expr) => node("Expr").field("expression", expression(expr)),
+            StmtKind::Let { name, initializer } => {
+                let node = node("Let").field("name", Field::Name(name.to_string()));
+                match initializer {
+                    Some(initializer) => node.field("initializer", expression(initializer)),
+                    None => node,
+                }
+            }
+            StmtKind::Print(expr) => node("Print").field("expression", expression(expr)),
+            StmtKind::Block(body) => node("Block").field("statements", statements(body)),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let node = node("If")
+                    .field("condition", expression(condition))
+                    .field("then", Field::Node(Node::stmt(then_branch)));
+                match else_branch {
+                    Some(else_branch) => node.field("else", Field::Node(Node::stmt(else_branch))),
+                    None => node,
+                }
+            }
+            StmtKind::While { condition, body } => node("While")
+                .field("condition", expression(condition))
+                .field("body", Field::Node(Node::stmt(body))),
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => node("For")
+                .field("variable", Field::Name(variable.to_string()))
+                .field("iterable", expression(iterable))
+                .field("body", Field::Node(Node::stmt(body))),
+            StmtKind::Function {
+                name, params, body, ..
+            } => node("Function")
+                .field("name", Field::Name(name.to_string()))
+                .field("params", names(params))
+                .field("body", statements(body)),
+            StmtKind::Return { value } => match value {
+                Some(value) => node("Return").field("value", expression(value)),
+                None => node("Return"),
+            },
+            StmtKind::Test { name, body } => node("Test")
+                .field("name", Field::Str(name.clone()))
+                .field("body", statements(body)),
+            StmtKind::Bench { name, body } => node("Bench")
+                .field("name", Field::Str(name.clone()))
+                .field("body", statements(body)),
+        }
+    }
+
+    fn expr(expr: &Expr) -> Node {
+        let node = |kind| Node::new(kind, Some(expr.span));
+        match &expr.kind {
+            ExprKind::Number(n) => node("Number").field("value", Field::Raw(n.to_string())),
+            ExprKind::Float(n) => node("Float").field("value", Field::Raw(format!("{:?}", n))),
+            ExprKind::String(s) => node("String").field("value", Field::Str(s.clone())),
+            ExprKind::Boolean(b) => node("Boolean").field("value", Field::Raw(b.to_string())),
+            ExprKind::Nil => node("Nil"),
+            ExprKind::Map(entries) => node("Map").field(
+                "entries",
+                Field::Nodes(
+                    entries
+                        .iter()
+                        .map(|(key, value)| {
+                            Node::new("Entry", Some(value.span))
+                                .field("key", Field::Name(key.to_string()))
+                                .field("value", expression(value))
+                        })
+                        .collect(),
+                ),
+            ),
+            ExprKind::Array(items) => node("Array").field("items", expressions(items)),
+            ExprKind::Variable { name, .. } => {
+                node("Variable").field("name", Field::Name(name.to_string()))
+            }
+            ExprKind::Assign { name, value, .. } => node("Assign")
+                .field("name", Field::Name(name.to_string()))
+                .field("value", expression(value)),
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => node("Binary")
+                .field(
+                    "operator",
+                    Field::Name(binary_operator(operator).to_string()),
+                )
+                .field("left", expression(left))
+                .field("right", expression(right)),
+            ExprKind::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let operator = match operator {
+                    LogicalOp::And => "and",
+                    LogicalOp::Or => "or",
+                };
+                node("Logical")
+                    .field("operator", Field::Name(operator.to_string()))
+                    .field("left", expression(left))
+                    .field("right", expression(right))
+            }
+            ExprKind::Unary { operator, right } => {
+                let operator = match operator {
+                    UnaryOp::Negate => "-",
+                    UnaryOp::Not => "!",
+                };
+                node("Unary")
+                    .field("operator", Field::Name(operator.to_string()))
+                    .field("right", expression(right))
+            }
+            ExprKind::Call { callee, arguments } => node("Call")
+                .field("callee", expression(callee))
+                .field("arguments", expressions(arguments)),
+            ExprKind::Index { object, index } => node("Index")
+                .field("object", expression(object))
+                .field("index", expression(index)),
+            ExprKind::IndexAssign {
+                object,
+                index,
+                value,
+            } => node("IndexAssign")
+                .field("object", expression(object))
+                .field("index", expression(index))
+                .field("value", expression(value)),
+            ExprKind::Dot { object, field } => node("Dot")
+                .field("object", expression(object))
+                .field("field", Field::Name(field.to_string())),
+            ExprKind::DotAssign {
+                object,
+                field,
+                value,
+            } => node("DotAssign")
+                .field("object", expression(object))
+                .field("field", Field::Name(field.to_string()))
+                .field("value", expression(value)),
+            ExprKind::Lambda { params, body, .. } => node("Lambda")
+                .field("params", names(params))
+                .field("body", statements(body)),
+        }
+    }
+}
+
+fn expression(expr: &Expr) -> Field {
+    Field::Node(Node::expr(expr))
+}
+
+fn expressions(exprs: &[Expr]) -> Field {
+    Field::Nodes(exprs.iter().map(Node::expr).collect())
+}
+
+fn statements(stmts: &[Stmt]) -> Field {
+    Field::Nodes(stmts.iter().map(Node::stmt).collect())
+}
+
+fn names(names: &[Symbol]) -> Field {
+    Field::Names(names.iter().map(|name| name.to_string()).collect())
+}
+
+fn binary_operator(operator: &BinOp) -> &'static str {
+    match operator {
+        BinOp::Add => "+",
+        BinOp::Subtract => "-",
+        BinOp::Multiply => "*",
+        BinOp::Divide => "/",
+        BinOp::Greater => ">",
+        BinOp::GreaterEqual => ">=",
+        BinOp::Less => "<",
+        BinOp::LessEqual => "<=",
+        BinOp::EqualEqual => "==",
+        BinOp::BangEqual => "!=",
+    }
+}
+
+/// The program as an indented tree, one node per line with its simple
+/// fields inline and its line and column in `source`:
+///
+/// ```text
+/// Program
+///   statements:
+///     Let name=x @1:1
+///       initializer: Number value=1 @1:9
+/// ```
+pub fn tree(program: &Program, source: &str) -> String {
+    let mut out = String::new();
+    write_tree(&mut out, &Node::program(program), source, 0);
+    out
+}
+
+fn write_tree(out: &mut String, node: &Node, source: &str, depth: usize) {
+    out.push_str(node.kind);
+    for (name, field) in &node.fields {
+        match field {
+            Field::Name(text) | Field::Raw(text) => {
+                let _ = write!(out, " {}={}", name, text);
+            }
+            Field::Str(text) => {
+                let _ = write!(out, " {}={:?}", name, text);
+            }
+            Field::Names(names) => {
+                let _ = write!(out, " {}=({})", name, names.join(", "));
+            }
+            Field::Node(_) | Field::Nodes(_) => {}
+        }
+    }
+    if let Some((start, _)) = node.span {
+        let position = Position::from_offset(source, start);
+        let _ = write!(out, " @{}:{}", position.line, position.column);
+    }
+    out.push('\n');
+
+    let indent = "  ".repeat(depth + 1);
+    for (name, field) in &node.fields {
+        match field {
+            Field::Node(child) => {
+                let _ = write!(out, "{}{}: ", indent, name);
+                write_tree(out, child, source, depth + 1);
+            }
+            Field::Nodes(children) if !children.is_empty() => {
+                let _ = writeln!(out, "{}{}:", indent, name);
+                for child in children {
+                    out.push_str(&indent);
+                    out.push_str("  ");
+                    write_tree(out, child, source, depth + 2);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The program as one JSON object. Each node has a `kind`, a `span` with
+/// byte offsets and the 1-based line and column where it starts (as in
+/// `Diagnostic::to_json`), and its fields by name: child nodes as objects,
+/// lists and parameters as arrays, numbers and booleans as JSON values, and names,
+/// operators and string literals as strings.
+pub fn json(program: &Program, source: &str) -> String {
+    let mut out = String::new();
+    write_json(&mut out, &Node::program(program), source);
+    out
+}
+
+fn write_json(out: &mut String, node: &Node, source: &str) {
+    let _ = write!(out, "{{\"kind\":\"{}\"", node.kind);
+    if let Some((start, end)) = node.span {
+        let position = Position::from_offset(source, start);
+        let _ = write!(
+            out,
+            ",\"span\":{{\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}",
+            start, end, position.line, position.column
+        );
+    }
+    for (name, field) in &node.fields {
+        let _ = write!(out, ",\"{}\":", name);
+        match field {
+            Field::Name(text) | Field::Str(text) => out.push_str(&json_string(text)),
+            Field::Raw(text) => out.push_str(text),
+            Field::Names(names) => {
+                let names: Vec<String> = names.iter().map(|name| json_string(name)).collect();
+                let _ = write!(out, "[{}]", names.join(","));
+            }
+            Field::Node(child) => write_json(out, child, source),
+            Field::Nodes(children) => {
+                out.push('[');
+                for (i, child) in children.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json(out, child, source);
+                }
+                out.push(']');
+            }
+        }
+    }
+    out.push('}');
+}
@@ -1,28 +1,129 @@
+use std::any::Any;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
 use crate::ast::Stmt;
+use crate::bigint::BigInt;
+use crate::error::RuntimeError;
+use crate::hash::FastHashMap;
+use crate::symbol::Symbol;
 
+/// A script value. Strings, arrays and maps are reference-counted, so
+/// copying a value (reading a variable, passing an argument) never copies
+/// their contents; an update copies them only if another value still shares
+/// them, through `Rc::make_mut`.
 #[derive(Debug, Clone)]
 pub enum Value {
     Number(i64),
-    String(String),
+    /// An int too large for `Number`, produced only under
+    /// `Overflow::Promote`.
+    BigInt(BigInt),
+    Float(f64),
+    String(Rc<str>),
     Boolean(bool),
     Function(Function),
-    Array(Vec<Value>),
-    Map(std::collections::HashMap<String, Value>),
+    Array(Rc<Vec<Value>>),
+    Map(Rc<FastHashMap<String, Value>>),
+    Nil,
+    /// An array or map that rejects index and field assignment.
+    Frozen(Box<Value>),
+    /// A handle to a host object, opaque to scripts except through the
+    /// methods its host attached.
+    UserData(UserData),
+}
+
+impl Value {
+    pub fn string(s: impl Into<Rc<str>>) -> Value {
+        Value::String(s.into())
+    }
+
+    pub fn array(items: impl IntoIterator<Item = Value>) -> Value {
+        Value::Array(Rc::new(items.into_iter().collect()))
+    }
+
+    pub fn map(entries: impl IntoIterator<Item = (String, Value)>) -> Value {
+        Value::Map(Rc::new(entries.into_iter().collect()))
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Number(n as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::string(s)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::string(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self {
+        Value::Array(Rc::new(items))
+    }
+}
+
+impl From<UserData> for Value {
+    fn from(data: UserData) -> Self {
+        Value::UserData(data)
+    }
+}
+
+/// `None` becomes nil.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Number(a), Value::Float(b)) | (Value::Float(b), Value::Number(a)) => {
+                *a as f64 == *b
+            }
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::BigInt(a), Value::Float(b)) | (Value::Float(b), Value::BigInt(a)) => {
+                a.to_f64() == *b
+            }
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
             (Value::Array(a), Value::Array(b)) => a == b,
             (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Frozen(a), b) => a.as_ref() == b,
+            (a, Value::Frozen(b)) => a == b.as_ref(),
             (Value::Function(_), Value::Function(_)) => false, // Functions are not equal
+            (Value::UserData(a), Value::UserData(b)) => a.is_same(b),
             _ => false,
         }
     }
@@ -30,10 +131,37 @@ impl PartialEq for Value {
 
 #[derive(Debug, Clone)]
 pub struct Function {
-    pub name: String,
-    pub params: Vec<String>,
-    pub body: Vec<Stmt>,
+    pub name: Symbol,
+    /// The parameters and body are shared with the AST, so making a
+    /// function value copies neither.
+    pub params: Rc<[Symbol]>,
+    pub body: Rc<Vec<Stmt>>,
     pub closure: Rc<RefCell<Environment>>,
+    /// Allocated afresh each time a `fn` is evaluated and shared by every
+    /// copy of the resulting value.
+    identity: Rc<()>,
+}
+
+impl Function {
+    pub fn new(
+        name: Symbol,
+        params: Rc<[Symbol]>,
+        body: Rc<Vec<Stmt>>,
+        closure: Rc<RefCell<Environment>>,
+    ) -> Self {
+        Function {
+            name,
+            params,
+            body,
+            closure,
+            identity: Rc::new(()),
+        }
+    }
+
+    /// Whether both values come from the same evaluation of a `fn`.
+    pub fn is_same(&self, other: &Function) -> bool {
+        Rc::ptr_eq(&self.identity, &other.identity)
+    }
 }
 
 impl PartialEq for Function {
@@ -42,34 +170,137 @@ impl PartialEq for Function {
     }
 }
 
+/// A method a host attaches to its userdata, called from scripts as
+/// `handle.name(args)` with the handle and the evaluated arguments.
+pub type NativeMethod = Rc<dyn Fn(&UserData, &[Value]) -> Result<Value, RuntimeError>>;
+
+/// The methods scripts can call on one kind of userdata, usually built once
+/// and shared by every handle of that kind.
+#[derive(Clone, Default)]
+pub struct MethodTable {
+    methods: HashMap<String, NativeMethod>,
+}
+
+impl MethodTable {
+    pub fn new() -> Self {
+        MethodTable::default()
+    }
+
+    /// Adds (or replaces) the method `name`.
+    pub fn with(
+        mut self,
+        name: &str,
+        method: impl Fn(&UserData, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) -> Self {
+        self.methods.insert(name.to_string(), Rc::new(method));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeMethod> {
+        self.methods.get(name)
+    }
+}
+
+/// A host object passed through scripts by reference. Copies of the value
+/// share the object; it is equal only to itself, and the host gets it back
+/// with `downcast_ref`.
+#[derive(Clone)]
+pub struct UserData {
+    type_name: Rc<str>,
+    object: Rc<dyn Any>,
+    methods: Option<Rc<MethodTable>>,
+}
+
+impl UserData {
+    /// Wraps `object`, shown to scripts as `<type_name>`.
+    pub fn new<T: Any>(type_name: &str, object: T) -> Self {
+        UserData {
+            type_name: type_name.into(),
+            object: Rc::new(object),
+            methods: None,
+        }
+    }
+
+    pub fn with_methods(mut self, methods: Rc<MethodTable>) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// The wrapped object, if it is a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.object.downcast_ref()
+    }
+
+    /// The method `name`, if the host attached one.
+    pub fn method(&self, name: &str) -> Option<NativeMethod> {
+        self.methods.as_ref()?.get(name).cloned()
+    }
+
+    /// Whether both handles refer to the same host object.
+    pub fn is_same(&self, other: &UserData) -> bool {
+        Rc::ptr_eq(&self.object, &other.object)
+    }
+}
+
+impl fmt::Debug for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.type_name)
+    }
+}
+
+/// One scope of bindings. Values sit in slots numbered in the order their
+/// names were first defined, which is the order the resolver numbers them,
+/// so resolved code reads a local by `(depth, index)` without hashing.
 #[derive(Debug, Clone)]
 pub struct Environment {
-    values: HashMap<String, Value>,
+    slots: HashMap<Symbol, usize>,
+    values: Vec<Value>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn new() -> Rc<RefCell<Self>> {
         Rc::new(RefCell::new(Environment {
-            values: HashMap::new(),
+            slots: HashMap::new(),
+            values: Vec::new(),
             enclosing: None,
         }))
     }
 
     pub fn new_enclosed(enclosing: &Rc<RefCell<Environment>>) -> Rc<RefCell<Self>> {
         Rc::new(RefCell::new(Environment {
-            values: HashMap::new(),
+            slots: HashMap::new(),
+            values: Vec::new(),
             enclosing: Some(Rc::clone(enclosing)),
         }))
     }
 
-    pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+    /// Binds `name` in this scope, reusing its slot if it is already bound.
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        match self.slots.get(&name) {
+            Some(&index) => self.values[index] = value,
+            None => {
+                self.slots.insert(name, self.values.len());
+                self.values.push(value);
+            }
+        }
     }
 
+    /// Whether `name` is bound in this scope itself, ignoring enclosing ones.
+    pub fn is_defined_here(&self, name: &str) -> bool {
+        self.slots.contains_key(name)
+    }
+
+    /// The value bound to `name`. This is a cheap handle: strings, arrays
+    /// and maps come back sharing their storage with the binding, so reading
+    /// a large structure in a loop never copies it.
     pub fn get(&self, name: &str) -> Option<Value> {
-        match self.values.get(name) {
-            Some(value) => Some(value.clone()),
+        match self.slots.get(name) {
+            Some(&index) => Some(self.values[index].clone()),
             None => {
                 if let Some(enclosing) = &self.enclosing {
                     enclosing.borrow().get(name)
@@ -80,9 +311,26 @@ impl Environment {
         }
     }
 
+    /// The value in slot `index` of the scope `depth` levels out, or `None`
+    /// if that slot has not been defined yet.
+    pub fn get_at(&self, depth: usize, index: usize) -> Option<Value> {
+        if depth == 0 {
+            return self.values.get(index).cloned();
+        }
+        self.enclosing.as_ref()?.borrow().get_at(depth - 1, index)
+    }
+
+    /// Looks `name` up in the outermost scope only.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get_global(name),
+            None => self.get(name),
+        }
+    }
+
     pub fn assign(&mut self, name: &str, value: Value) -> bool {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_string(), value);
+        if let Some(&index) = self.slots.get(name) {
+            self.values[index] = value;
             true
         } else if let Some(enclosing) = &self.enclosing {
             enclosing.borrow_mut().assign(name, value)
@@ -91,6 +339,86 @@ impl Environment {
         }
     }
 
+    pub fn assign_at(&mut self, depth: usize, index: usize, value: Value) -> bool {
+        if depth == 0 {
+            return match self.values.get_mut(index) {
+                Some(slot) => {
+                    *slot = value;
+                    true
+                }
+                None => false,
+            };
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(depth - 1, index, value),
+            None => false,
+        }
+    }
+
+    pub fn assign_global(&mut self, name: &str, value: Value) -> bool {
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_global(name, value),
+            None => self.assign(name, value),
+        }
+    }
+
+    /// Every binding visible from this scope, with inner names shadowing
+    /// outer ones.
+    pub fn visible_values(&self) -> HashMap<String, Value> {
+        let mut values = match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().visible_values(),
+            None => HashMap::new(),
+        };
+        values.extend(self.bindings());
+        values
+    }
+
+    /// Bindings of the outermost (global) scope in this chain.
+    pub fn global_values(&self) -> HashMap<String, Value> {
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().global_values(),
+            None => self.bindings().collect(),
+        }
+    }
+
+    fn bindings(&self) -> impl Iterator<Item = (String, Value)> + '_ {
+        self.slots
+            .iter()
+            .map(|(name, &index)| (name.to_string(), self.values[index].clone()))
+    }
+
+    pub(crate) fn enclosing(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.enclosing.as_ref()
+    }
+
+    pub(crate) fn slot_values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// A copy of this scope with every value passed through `copy` and the
+    /// enclosing scope replaced by `enclosing`.
+    pub(crate) fn copy_with(
+        &self,
+        enclosing: Option<Rc<RefCell<Environment>>>,
+        copy: impl FnMut(&Value) -> Value,
+    ) -> Environment {
+        Environment {
+            slots: self.slots.clone(),
+            values: self.values.iter().map(copy).collect(),
+            enclosing,
+        }
+    }
+
+    /// Empties this scope, handing back what it held so the caller can drop
+    /// it once no environment is borrowed.
+    pub(crate) fn take_contents(&mut self) -> Environment {
+        Environment {
+            slots: std::mem::take(&mut self.slots),
+            values: std::mem::take(&mut self.values),
+            enclosing: self.enclosing.take(),
+        }
+    }
+
     pub fn get_array_length(&self, name: &str) -> Option<usize> {
         match self.get(name) {
             Some(Value::Array(arr)) => Some(arr.len()),
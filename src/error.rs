@@ -0,0 +1,229 @@
+use std::fmt;
+
+use crate::ast::Span;
+
+/// Broad category of a runtime error, for hosts that want to react to
+/// particular failures without matching on message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable,
+    /// A missing map key or field was read in strict mode.
+    UnknownField,
+    TypeMismatch,
+    IndexOutOfBounds {
+        index: i64,
+        length: usize,
+    },
+    DivisionByZero,
+    /// An int result did not fit in 64 bits under `Overflow::Checked`.
+    IntegerOverflow,
+    ArgumentCount,
+    AssertionFailed,
+    FrozenValue,
+    /// A `let` repeated a name from the same scope in strict mode.
+    Redeclaration,
+    Io,
+    /// The script called `exit(code)`. Not a failure as such, but it unwinds
+    /// the same way so the host decides what exiting means.
+    Exit(i32),
+    /// The script used up the step budget the host set with
+    /// `Interpreter::set_step_limit`.
+    StepLimitExceeded,
+    /// The values the script holds outgrew the cap the host set with
+    /// `Interpreter::set_memory_limit`.
+    MemoryLimitExceeded,
+    /// The host cancelled the run through a `CancelHandle`.
+    Cancelled,
+    /// Calls nested deeper than the host allowed with
+    /// `Interpreter::set_max_call_depth`.
+    CallDepthExceeded,
+    Other,
+}
+
+impl RuntimeErrorKind {
+    /// The diagnostic code reported for errors of this kind.
+    pub fn code(self) -> &'static str {
+        match self {
+            RuntimeErrorKind::UndefinedVariable => "E0100",
+            RuntimeErrorKind::UnknownField => "E0101",
+            RuntimeErrorKind::TypeMismatch => "E0102",
+            RuntimeErrorKind::IndexOutOfBounds { .. } => "E0103",
+            RuntimeErrorKind::DivisionByZero => "E0104",
+            RuntimeErrorKind::ArgumentCount => "E0105",
+            RuntimeErrorKind::AssertionFailed => "E0106",
+            RuntimeErrorKind::FrozenValue => "E0107",
+            RuntimeErrorKind::Io => "E0108",
+            RuntimeErrorKind::Exit(_) => "E0109",
+            RuntimeErrorKind::Redeclaration => "E0110",
+            RuntimeErrorKind::IntegerOverflow => "E0111",
+            RuntimeErrorKind::StepLimitExceeded => "E0112",
+            RuntimeErrorKind::MemoryLimitExceeded => "E0113",
+            RuntimeErrorKind::Cancelled => "E0114",
+            RuntimeErrorKind::CallDepthExceeded => "E0115",
+            RuntimeErrorKind::Other => "E0199",
+        }
+    }
+}
+
+/// A problem found while scanning source text, located by a byte range into
+/// the source so tools can underline it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub message: String,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Scan error at {}:{}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// A syntax error found while parsing, located at the offending token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        ParseError {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = self.span;
+        write!(f, "Parse error at {}..{}: {}", start, end, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Anything that can stop `run` from completing a script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunError {
+    Scan(Vec<ScanError>),
+    Parse(Vec<ParseError>),
+    Runtime(RuntimeError),
+}
+
+impl From<RuntimeError> for RunError {
+    fn from(error: RuntimeError) -> Self {
+        RunError::Runtime(error)
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Scan(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            RunError::Parse(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            RunError::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// A user function call that was active when an error was raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub function: String,
+    pub call_site: Span,
+}
+
+/// An error raised while executing a script. Unlike a panic it can be caught
+/// by script code (see the `pcall` builtin) or returned to the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    /// Source range of the innermost expression or statement that failed.
+    pub span: Option<Span>,
+    /// Function calls the error escaped from, innermost first.
+    pub trace: Vec<CallFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        RuntimeError::with_kind(RuntimeErrorKind::Other, message)
+    }
+
+    pub fn with_kind(kind: RuntimeErrorKind, message: impl Into<String>) -> Self {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            span: None,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Records where the error happened, unless a more specific location
+    /// was already recorded further down the tree.
+    pub fn at(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    /// Whether `pcall` may turn this error into a result. Exits and host
+    /// limits always unwind to the host.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self.kind,
+            RuntimeErrorKind::Exit(_)
+                | RuntimeErrorKind::StepLimitExceeded
+                | RuntimeErrorKind::MemoryLimitExceeded
+                | RuntimeErrorKind::Cancelled
+                | RuntimeErrorKind::CallDepthExceeded
+        )
+    }
+
+    /// The requested exit code if this error carries an `exit()` call.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.kind {
+            RuntimeErrorKind::Exit(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some((start, end)) => {
+                write!(f, "Runtime error at {}..{}: {}", start, end, self.message)?
+            }
+            None => write!(f, "Runtime error: {}", self.message)?,
+        }
+        for frame in &self.trace {
+            let (start, end) = frame.call_site;
+            write!(
+                f,
+                "\n  in {}() called at {}..{}",
+                frame.function, start, end
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
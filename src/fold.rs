@@ -0,0 +1,198 @@
+use std::rc::Rc;
+
+use crate::ast::{BinOp, Expr, ExprKind, LogicalOp, Program, Stmt, StmtKind};
+use crate::environment::Value;
+use crate::interpreter::{binary_values, is_truthy, unary_value};
+use crate::numeric::Overflow;
+
+/// Rewrites operations on literals into their results before the program
+/// runs: `2 * 3` becomes `6`, `"a" + "b"` becomes `"ab"`, `true and x`
+/// becomes `x`, and `if`/`while` statements with a literal condition lose
+/// the branch that can never run.
+///
+/// Folding uses the interpreter's own operators, so results are exactly
+/// what evaluation would produce. Anything that would fail at runtime, such
+/// as `1 / 0` or an integer overflow, is left in place so it is reported
+/// (or handled by the overflow mode) when reached, and so is a string
+/// repeated into more than a few hundred bytes. The lint and type checks
+/// look at conditions as written, so run them before folding.
+pub fn fold_constants(program: &mut Program) {
+    statements(&mut program.statements);
+}
+
+fn statements(statements: &mut [Stmt]) {
+    for stmt in statements {
+        statement(stmt);
+    }
+}
+
+fn statement(stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Expr(expr) | StmtKind::Print(expr) => expression(expr),
+        StmtKind::Let { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                expression(initializer);
+            }
+        }
+        StmtKind::Block(body) => statements(body),
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            expression(condition);
+            statement(then_branch);
+            if let Some(else_branch) = else_branch {
+                statement(else_branch);
+            }
+            if let Some(value) = literal_value(condition) {
+                let taken = if is_truthy(&value) {
+                    Some(std::mem::replace(&mut **then_branch, empty_block()))
+                } else {
+                    else_branch.take().map(|branch| *branch)
+                };
+                stmt.kind = taken.map_or(StmtKind::Block(Vec::new()), |branch| branch.kind);
+            }
+        }
+        StmtKind::While { condition, body } => {
+            expression(condition);
+            statement(body);
+            if literal_value(condition).is_some_and(|value| !is_truthy(&value)) {
+                stmt.kind = StmtKind::Block(Vec::new());
+            }
+        }
+        StmtKind::For { iterable, body, .. } => {
+            expression(iterable);
+            statement(body);
+        }
+        StmtKind::Function { body, .. }
+        | StmtKind::Test { body, .. }
+        | StmtKind::Bench { body, .. } => statements(Rc::make_mut(body).as_mut_slice()),
+        StmtKind::Return { value } => {
+            if let Some(value) = value {
+                expression(value);
+            }
+        }
+    }
+}
+
+fn expression(expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Number(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Boolean(_)
+        | ExprKind::Nil
+        | ExprKind::Variable { .. } => {}
+        ExprKind::Assign { value, .. } => expression(value),
+        ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+            expression(left);
+            expression(right);
+        }
+        ExprKind::Unary { right, .. } => expression(right),
+        ExprKind::Call { callee, arguments } => {
+            expression(callee);
+            arguments.iter_mut().for_each(expression);
+        }
+        ExprKind::Array(elements) => elements.iter_mut().for_each(expression),
+        ExprKind::Map(pairs) => {
+            for (_, value) in pairs {
+                expression(value);
+            }
+        }
+        ExprKind::Index { object, index } => {
+            expression(object);
+            expression(index);
+        }
+        ExprKind::IndexAssign {
+            object,
+            index,
+            value,
+        } => {
+            expression(object);
+            expression(index);
+            expression(value);
+        }
+        ExprKind::Dot { object, .. } => expression(object),
+        ExprKind::DotAssign { object, value, .. } => {
+            expression(object);
+            expression(value);
+        }
+        ExprKind::Lambda { body, .. } => statements(Rc::make_mut(body).as_mut_slice()),
+    }
+    if let Some(folded) = fold(&mut expr.kind) {
+        expr.kind = folded;
+    }
+}
+
+/// The longest string, in bytes, that `*` on a string is folded into.
+const MAX_FOLDED_STRING: i128 = 256;
+
+/// The literal an operation on literals evaluates to, if it evaluates
+/// without error.
+fn fold(kind: &mut ExprKind) -> Option<ExprKind> {
+    match kind {
+        ExprKind::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (literal_value(left)?, literal_value(right)?);
+            if let (BinOp::Multiply, Value::String(s), Value::Number(n))
+            | (BinOp::Multiply, Value::Number(n), Value::String(s)) = (&*operator, &left, &right)
+            {
+                // Repeating a string can make it arbitrarily long, and this
+                // runs even for code that is never reached.
+                if (s.len() as i128) * i128::from(*n) > MAX_FOLDED_STRING {
+                    return None;
+                }
+            }
+            literal(binary_values(operator, &left, &right, Overflow::Checked).ok()?)
+        }
+        ExprKind::Unary { operator, right } => {
+            literal(unary_value(operator, literal_value(right)?, Overflow::Checked).ok()?)
+        }
+        ExprKind::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            let truthy = is_truthy(&literal_value(left)?);
+            Some(match (operator, truthy) {
+                (LogicalOp::And, false) => ExprKind::Boolean(false),
+                (LogicalOp::Or, true) => ExprKind::Boolean(true),
+                _ => std::mem::replace(&mut right.kind, ExprKind::Nil),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn literal_value(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        ExprKind::Number(n) => Some(Value::Number(*n)),
+        ExprKind::Float(f) => Some(Value::Float(*f)),
+        ExprKind::String(s) => Some(Value::string(s.clone())),
+        ExprKind::Boolean(b) => Some(Value::Boolean(*b)),
+        ExprKind::Nil => Some(Value::Nil),
+        _ => None,
+    }
+}
+
+fn literal(value: Value) -> Option<ExprKind> {
+    match value {
+        Value::Number(n) => Some(ExprKind::Number(n)),
+        Value::Float(f) => Some(ExprKind::Float(f)),
+        Value::String(s) => Some(ExprKind::String(s.to_string())),
+        Value::Boolean(b) => Some(ExprKind::Boolean(b)),
+        Value::Nil => Some(ExprKind::Nil),
+        _ => None,
+    }
+}
+
+fn empty_block() -> Stmt {
+    Stmt {
+        kind: StmtKind::Block(Vec::new()),
+        span: (0, 0),
+    }
+}
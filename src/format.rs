@@ -0,0 +1,547 @@
+//! The canonical layout of a script, for `ruilian fmt`.
+//!
+//! The program is printed back from its syntax tree: four-space
+//! indentation, one statement per line, `{` on the line that opens the
+//! block, single spaces around binary operators and after commas, and
+//! parentheses only where the grammar needs them. Literals keep their
+//! source text, comments are kept, blank lines between statements are kept
+//! (runs of them become one), and an array or map literal written over
+//! several lines stays one item per line.
+
+use crate::ast::{BinOp, Expr, ExprKind, LogicalOp, Program, Span, Stmt, StmtKind, UnaryOp};
+use crate::error::RunError;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::tokens::Comment;
+
+const INDENT: &str = "    ";
+
+/// `source` in canonical layout. Fails on scan and parse errors.
+pub fn format(source: &str) -> Result<String, RunError> {
+    let mut scanner = Scanner::new(source).with_comments();
+    let tokens = scanner.scan_tokens().map_err(RunError::Scan)?;
+    let comments = scanner.take_comments();
+    let program = Parser::new(tokens).parse().map_err(RunError::Parse)?;
+    Ok(format_program(&program, &comments, source))
+}
+
+/// `program`, parsed from `source`, in canonical layout, with `comments`
+/// (as scanned from `source`) put back.
+///
+/// A comment goes before the statement or literal item that follows it, or
+/// stays at the end of the line it ended, so comments between statements
+/// keep their place. One in the middle of a statement with nowhere to go,
+/// such as between the arguments of a call, moves to just after the
+/// statement; none is ever dropped.
+pub fn format_program(program: &Program, comments: &[Comment], source: &str) -> String {
+    let mut formatter = Formatter {
+        source,
+        comments,
+        next_comment: 0,
+        out: String::new(),
+        depth: 0,
+    };
+    formatter.statements(&program.statements, source.len());
+    formatter.out
+}
+
+/// How tightly an expression binds, from assignment up to literals. An
+/// operand that binds more loosely than its position allows is wrapped in
+/// parentheses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    /// Indexing, field access and calls on anything but a plain name.
+    Postfix,
+    Primary,
+}
+
+impl Precedence {
+    fn of(expr: &Expr) -> Precedence {
+        match &expr.kind {
+            ExprKind::Assign { .. } | ExprKind::IndexAssign { .. } | ExprKind::DotAssign { .. } => {
+                Precedence::Assignment
+            }
+            ExprKind::Logical { operator, .. } => match operator {
+                LogicalOp::Or => Precedence::Or,
+                LogicalOp::And => Precedence::And,
+            },
+            ExprKind::Binary { operator, .. } => binary_precedence(operator),
+            ExprKind::Unary { .. } => Precedence::Unary,
+            ExprKind::Index { .. } | ExprKind::Dot { .. } => Precedence::Postfix,
+            // The parser reads `name(...)` as a primary expression.
+            ExprKind::Call { callee, .. } => match callee.kind {
+                ExprKind::Variable { .. } => Precedence::Primary,
+                _ => Precedence::Postfix,
+            },
+            _ => Precedence::Primary,
+        }
+    }
+
+    /// The next level up, for the right operand of a left-associative
+    /// operator.
+    fn tighter(self) -> Precedence {
+        match self {
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Postfix,
+            Precedence::Postfix | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+fn binary_precedence(operator: &BinOp) -> Precedence {
+    match operator {
+        BinOp::EqualEqual | BinOp::BangEqual => Precedence::Equality,
+        BinOp::Greater | BinOp::GreaterEqual | BinOp::Less | BinOp::LessEqual => {
+            Precedence::Comparison
+        }
+        BinOp::Add | BinOp::Subtract => Precedence::Term,
+        BinOp::Multiply | BinOp::Divide => Precedence::Factor,
+    }
+}
+
+fn binary_operator(operator: &BinOp) -> &'static str {
+    match operator {
+        BinOp::Add => "+",
+        BinOp::Subtract => "-",
+        BinOp::Multiply => "*",
+        BinOp::Divide => "/",
+        BinOp::Greater => ">",
+        BinOp::GreaterEqual => ">=",
+        BinOp::Less => "<",
+        BinOp::LessEqual => "<=",
+        BinOp::EqualEqual => "==",
+        BinOp::BangEqual => "!=",
+    }
+}
+
+/// Whether `expr` printed as-is would start with `{`, which at the start of
+/// a statement opens a block rather than a map.
+fn starts_with_brace(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Map(_) => true,
+        ExprKind::Binary { left, .. } | ExprKind::Logical { left, .. } => starts_with_brace(left),
+        ExprKind::Index { object, .. }
+        | ExprKind::IndexAssign { object, .. }
+        | ExprKind::Dot { object, .. }
+        | ExprKind::DotAssign { object, .. } => starts_with_brace(object),
+        ExprKind::Call { callee, .. } => starts_with_brace(callee),
+        _ => false,
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: &'a [Comment],
+    /// The first comment not yet written.
+    next_comment: usize,
+    out: String,
+    depth: usize,
+}
+
+impl Formatter<'_> {
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn text(&self, span: Span) -> &str {
+        &self.source[span.0..span.1]
+    }
+
+    /// Whether the source has a blank line between offsets `before` and
+    /// `after`. A comment moved out of a statement comes after it.
+    fn blank_line_between(&self, before: usize, after: usize) -> bool {
+        self.source
+            .get(before..after)
+            .is_some_and(|gap| gap.matches('\n').count() > 1)
+    }
+
+    /// Starts a line at the current depth for something at offset `start`,
+    /// after a blank line if the source had one since `last`, the end of
+    /// what was written before at this depth.
+    fn start_line(&mut self, last: Option<usize>, start: usize) {
+        if last.is_some_and(|last| self.blank_line_between(last, start)) {
+            self.out.push('\n');
+        }
+        self.indent();
+    }
+
+    fn pending_comment(&self) -> Option<&Comment> {
+        self.comments.get(self.next_comment)
+    }
+
+    /// Writes the comments that start before `offset`, each on its own
+    /// line, updating `last` as `start_line` expects.
+    fn comments_before(&mut self, offset: usize, last: &mut Option<usize>) {
+        while let Some(comment) = self.pending_comment().filter(|c| c.span.0 < offset) {
+            let (text, span) = (comment.text.clone(), comment.span);
+            self.start_line(*last, span.0);
+            self.out.push_str(&text);
+            self.out.push('\n');
+            self.next_comment += 1;
+            *last = Some(last.map_or(span.1, |last| last.max(span.1)));
+        }
+    }
+
+    /// Appends the comment that follows offset `end` on the same line with
+    /// only spaces between, if there is one, returning where it ends. A
+    /// comment after a closing `}` or `else` belongs to the statement those
+    /// are part of, not to the last one inside it.
+    fn trailing_comment(&mut self, end: usize) -> Option<usize> {
+        let comment = self.pending_comment()?;
+        let between = self.source.get(end..comment.span.0)?;
+        if between.contains('\n') || !between.trim().is_empty() {
+            return None;
+        }
+        let (text, span) = (comment.text.clone(), comment.span);
+        self.out.push(' ');
+        self.out.push_str(&text);
+        self.next_comment += 1;
+        Some(span.1)
+    }
+
+    /// One statement per line at the current depth, followed by the
+    /// comments before offset `end`, where the enclosing block or the file
+    /// ends.
+    fn statements(&mut self, statements: &[Stmt], end: usize) {
+        let mut last = None;
+        for stmt in statements {
+            self.comments_before(stmt.span.0, &mut last);
+            self.start_line(last, stmt.span.0);
+            self.stmt(stmt);
+            let end = self.trailing_comment(stmt.span.1);
+            self.out.push('\n');
+            last = Some(end.unwrap_or(stmt.span.1));
+        }
+        self.comments_before(end, &mut last);
+    }
+
+    /// `{`, the statements one level deeper, then `}` at the current depth,
+    /// or `{}` when there are none. `end` is the offset of the block's end.
+    fn block(&mut self, statements: &[Stmt], end: usize) {
+        if statements.is_empty() && self.pending_comment().is_none_or(|c| c.span.0 >= end) {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.statements(statements, end);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+    }
+
+    /// A statement from the current position, without the final newline.
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                if starts_with_brace(expr) {
+                    self.out.push('(');
+                    self.expr(expr, Precedence::Assignment);
+                    self.out.push(')');
+                } else {
+                    self.expr(expr, Precedence::Assignment);
+                }
+                self.out.push(';');
+            }
+            StmtKind::Let { name, initializer } => {
+                self.out.push_str("let ");
+                self.out.push_str(name);
+                if let Some(initializer) = initializer {
+                    self.out.push_str(" = ");
+                    self.expr(initializer, Precedence::Assignment);
+                }
+                self.out.push(';');
+            }
+            StmtKind::Print(expr) => {
+                self.out.push_str("print ");
+                self.expr(expr, Precedence::Assignment);
+                self.out.push(';');
+            }
+            StmtKind::Block(statements) => self.block(statements, stmt.span.1),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.out.push_str("if (");
+                self.expr(condition, Precedence::Assignment);
+                self.out.push_str(") ");
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    if matches!(then_branch.kind, StmtKind::Block(_)) {
+                        self.out.push(' ');
+                    } else {
+                        self.out.push('\n');
+                        self.indent();
+                    }
+                    self.out.push_str("else ");
+                    self.stmt(else_branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.out.push_str("while (");
+                self.expr(condition, Precedence::Assignment);
+                self.out.push_str(") ");
+                self.stmt(body);
+            }
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.out.push_str("for (");
+                self.out.push_str(variable);
+                self.out.push_str(" in ");
+                self.expr(iterable, Precedence::Assignment);
+                self.out.push_str(") ");
+                self.stmt(body);
+            }
+            StmtKind::Function {
+                name, params, body, ..
+            } => {
+                self.out.push_str("fn ");
+                self.out.push_str(name);
+                self.out.push('(');
+                self.out.push_str(&params.join(", "));
+                self.out.push_str(") ");
+                self.block(body, stmt.span.1);
+            }
+            StmtKind::Return { value } => {
+                self.out.push_str("return");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value, Precedence::Assignment);
+                }
+                self.out.push(';');
+            }
+            StmtKind::Test { name, body } | StmtKind::Bench { name, body } => {
+                let keyword = match stmt.kind {
+                    StmtKind::Test { .. } => "test",
+                    _ => "bench",
+                };
+                self.out.push_str(keyword);
+                self.out.push_str(" \"");
+                self.out.push_str(name);
+                self.out.push_str("\" ");
+                self.block(body, stmt.span.1);
+            }
+        }
+    }
+
+    /// `expr`, in parentheses if it binds more loosely than `minimum`.
+    fn expr(&mut self, expr: &Expr, minimum: Precedence) {
+        if Precedence::of(expr) < minimum {
+            self.out.push('(');
+            self.expr_kind(expr);
+            self.out.push(')');
+        } else {
+            self.expr_kind(expr);
+        }
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::String(_) => {
+                let text = self.text(expr.span).to_string();
+                self.out.push_str(&text);
+            }
+            ExprKind::Boolean(value) => self.out.push_str(if *value { "true" } else { "false" }),
+            ExprKind::Nil => self.out.push_str("nil"),
+            ExprKind::Variable { name, .. } => self.out.push_str(name),
+            ExprKind::Assign { name, value, .. } => {
+                self.out.push_str(name);
+                self.out.push_str(" = ");
+                self.expr(value, Precedence::Assignment);
+            }
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let precedence = binary_precedence(operator);
+                self.expr(left, precedence);
+                self.out.push(' ');
+                self.out.push_str(binary_operator(operator));
+                self.out.push(' ');
+                self.expr(right, precedence.tighter());
+            }
+            ExprKind::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let (precedence, text) = match operator {
+                    LogicalOp::Or => (Precedence::Or, "or"),
+                    LogicalOp::And => (Precedence::And, "and"),
+                };
+                self.expr(left, precedence);
+                self.out.push(' ');
+                self.out.push_str(text);
+                self.out.push(' ');
+                self.expr(right, precedence.tighter());
+            }
+            ExprKind::Unary { operator, right } => {
+                self.out.push(match operator {
+                    UnaryOp::Negate => '-',
+                    UnaryOp::Not => '!',
+                });
+                // The operand of a prefix operator is another prefix
+                // operation or a primary expression, never a postfix one.
+                if matches!(right.kind, ExprKind::Unary { .. }) {
+                    self.expr_kind(right);
+                } else {
+                    self.expr(right, Precedence::Primary);
+                }
+            }
+            ExprKind::Call { callee, arguments } => {
+                // Unlike an index or a field, a call right after a prefix
+                // operator would apply to the name alone: `-f(x)`.
+                self.expr(callee, Precedence::Postfix);
+                self.out.push('(');
+                self.list(arguments, |formatter, argument| {
+                    formatter.expr(argument, Precedence::Assignment)
+                });
+                self.out.push(')');
+            }
+            ExprKind::Index { object, index } => {
+                self.expr(object, Precedence::Unary);
+                self.out.push('[');
+                self.expr(index, Precedence::Assignment);
+                self.out.push(']');
+            }
+            ExprKind::IndexAssign {
+                object,
+                index,
+                value,
+            } => {
+                self.expr(object, Precedence::Unary);
+                self.out.push('[');
+                self.expr(index, Precedence::Assignment);
+                self.out.push_str("] = ");
+                self.expr(value, Precedence::Assignment);
+            }
+            ExprKind::Dot { object, field } => {
+                self.expr(object, Precedence::Unary);
+                self.out.push('.');
+                self.out.push_str(field);
+            }
+            ExprKind::DotAssign {
+                object,
+                field,
+                value,
+            } => {
+                self.expr(object, Precedence::Unary);
+                self.out.push('.');
+                self.out.push_str(field);
+                self.out.push_str(" = ");
+                self.expr(value, Precedence::Assignment);
+            }
+            ExprKind::Array(items) => {
+                self.literal(
+                    expr.span,
+                    "[",
+                    "]",
+                    items,
+                    |item| item.span,
+                    |formatter, item| formatter.expr(item, Precedence::Assignment),
+                );
+            }
+            ExprKind::Map(entries) => {
+                if entries.is_empty() {
+                    self.out.push_str("{}");
+                } else {
+                    self.literal(
+                        expr.span,
+                        "{ ",
+                        " }",
+                        entries,
+                        |(_, value)| value.span,
+                        |formatter, (key, value)| {
+                            formatter.out.push('"');
+                            formatter.out.push_str(key);
+                            formatter.out.push_str("\": ");
+                            formatter.expr(value, Precedence::Assignment);
+                        },
+                    );
+                }
+            }
+            ExprKind::Lambda { params, body, .. } => {
+                self.out.push_str("fn(");
+                self.out.push_str(&params.join(", "));
+                self.out.push_str(") ");
+                self.block(body, expr.span.1);
+            }
+        }
+    }
+
+    /// `items` separated by `, `.
+    fn list<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T)) {
+        for (i, each) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            item(self, each);
+        }
+    }
+
+    /// An array or map literal: on one line between `open` and `close`, or,
+    /// if the source spread it over several lines, one item per line with
+    /// the comments around the items. `start` gives where an item begins.
+    fn literal<T>(
+        &mut self,
+        span: Span,
+        open: &str,
+        close: &str,
+        items: &[T],
+        start: impl Fn(&T) -> Span,
+        mut item: impl FnMut(&mut Self, &T),
+    ) {
+        if items.is_empty() || !self.text(span).contains('\n') {
+            self.out.push_str(open);
+            self.list(items, item);
+            self.out.push_str(close);
+            return;
+        }
+        self.out.push_str(open.trim_end());
+        self.out.push('\n');
+        self.depth += 1;
+        let mut last = None;
+        for (i, each) in items.iter().enumerate() {
+            let item_span = start(each);
+            self.comments_before(item_span.0, &mut last);
+            self.start_line(last, item_span.0);
+            item(self, each);
+            if i + 1 < items.len() {
+                self.out.push(',');
+            }
+            // Past the comma, if there is one.
+            let end = self.source[item_span.1..span.1]
+                .find(',')
+                .filter(|_| i + 1 < items.len())
+                .map_or(item_span.1, |comma| item_span.1 + comma + 1);
+            let end = self.trailing_comment(end).unwrap_or(end);
+            self.out.push('\n');
+            last = Some(end);
+        }
+        self.comments_before(span.1, &mut last);
+        self.depth -= 1;
+        self.indent();
+        self.out.push_str(close.trim_start());
+    }
+}
@@ -0,0 +1,186 @@
+//! Cycle collection for environments.
+//!
+//! Values are reference-counted, and the only way to build a cycle is
+//! through a closure: a function value stored in (or below) the environment
+//! it captured, as every top-level or recursive function is. Such cycles
+//! never drop on their own, so the collector finds environments that are
+//! only reachable from each other and empties them, which frees the
+//! functions and everything they hold.
+//!
+//! Only environments captured by a closure are tracked, along with the
+//! scopes that enclose them. A collection counts, for each tracked
+//! environment, how many references come from the tracked set itself; any
+//! reference beyond those must come from outside (the interpreter's current
+//! scope, a call in progress, a value held by the host), which keeps it and
+//! everything it reaches alive. References inside an array or map that is
+//! also shared with something outside the set are not counted, so the
+//! collector may miss such a cycle but never frees a live environment.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::{Rc, Weak};
+
+use crate::environment::{Environment, Value};
+
+/// Tracking more environments than this since the last collection starts a
+/// new one automatically.
+const MIN_THRESHOLD: usize = 1024;
+
+/// Counters exposed through `gc_stats()` and `Interpreter::gc_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Collections run so far, automatic or through `gc()`.
+    pub collections: usize,
+    /// Environments freed across all collections.
+    pub freed: usize,
+    /// Environments currently tracked, some of which may already be gone.
+    pub tracked: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Collector {
+    tracked: Vec<Weak<RefCell<Environment>>>,
+    threshold: usize,
+    stats: GcStats,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector {
+            tracked: Vec::new(),
+            threshold: MIN_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+}
+
+type EnvRef = Rc<RefCell<Environment>>;
+
+impl Collector {
+    /// Records that a closure captured `environment`.
+    pub(crate) fn track(&mut self, environment: &EnvRef) {
+        self.tracked.push(Rc::downgrade(environment));
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.tracked.len() >= self.threshold
+    }
+
+    pub(crate) fn stats(&self) -> GcStats {
+        GcStats {
+            tracked: self.tracked.len(),
+            ..self.stats
+        }
+    }
+
+    /// Frees every tracked environment that is unreachable from outside the
+    /// tracked set, returning how many were freed.
+    pub(crate) fn collect(&mut self) -> usize {
+        let candidates = self.candidates();
+        let index: HashMap<*const RefCell<Environment>, usize> = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, env)| (Rc::as_ptr(env), i))
+            .collect();
+
+        // References to each candidate from the other candidates.
+        let mut internal = vec![0; candidates.len()];
+        for env in &candidates {
+            visit_edges(&env.borrow(), true, &mut |target| {
+                if let Some(&i) = index.get(&Rc::as_ptr(target)) {
+                    internal[i] += 1;
+                }
+            });
+        }
+
+        // Anything referenced from outside is live, and so is everything it
+        // reaches. `candidates` itself holds one reference to each.
+        let mut live = vec![false; candidates.len()];
+        let mut queue: VecDeque<usize> = (0..candidates.len())
+            .filter(|&i| Rc::strong_count(&candidates[i]) - 1 > internal[i])
+            .collect();
+        for &i in &queue {
+            live[i] = true;
+        }
+        while let Some(i) = queue.pop_front() {
+            visit_edges(&candidates[i].borrow(), false, &mut |target| {
+                if let Some(&j) = index.get(&Rc::as_ptr(target))
+                    && !live[j]
+                {
+                    live[j] = true;
+                    queue.push_back(j);
+                }
+            });
+        }
+
+        // Empty the garbage first and drop the contents afterwards, so no
+        // environment is borrowed while the values it held are dropped.
+        let mut contents = Vec::new();
+        let mut freed = 0;
+        for (env, _) in candidates.iter().zip(&live).filter(|(_, live)| !**live) {
+            contents.push(env.borrow_mut().take_contents());
+            freed += 1;
+        }
+        drop(contents);
+
+        self.tracked = candidates
+            .iter()
+            .zip(&live)
+            .filter(|(_, live)| **live)
+            .map(|(env, _)| Rc::downgrade(env))
+            .collect();
+        self.threshold = (self.tracked.len() * 2).max(MIN_THRESHOLD);
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        freed
+    }
+
+    /// The tracked environments still alive, plus every scope enclosing
+    /// them, without duplicates.
+    fn candidates(&self) -> Vec<EnvRef> {
+        let mut seen = HashMap::new();
+        let mut candidates = Vec::new();
+        for weak in &self.tracked {
+            let mut next = weak.upgrade();
+            while let Some(env) = next {
+                if seen.insert(Rc::as_ptr(&env), ()).is_some() {
+                    break;
+                }
+                next = env.borrow().enclosing().cloned();
+                candidates.push(env);
+            }
+        }
+        candidates
+    }
+}
+
+/// Calls `edge` for each environment `env` refers to: its enclosing scope
+/// and the closures of the functions it holds. With `owned_only`, arrays and
+/// maps shared with another value are skipped, since references inside them
+/// cannot be attributed to `env` alone.
+fn visit_edges(env: &Environment, owned_only: bool, edge: &mut dyn FnMut(&EnvRef)) {
+    if let Some(enclosing) = env.enclosing() {
+        edge(enclosing);
+    }
+    for value in env.slot_values() {
+        visit_value(value, owned_only, edge);
+    }
+}
+
+fn visit_value(value: &Value, owned_only: bool, edge: &mut dyn FnMut(&EnvRef)) {
+    match value {
+        Value::Function(function) => edge(&function.closure),
+        Value::Array(items) if !owned_only || Rc::strong_count(items) == 1 => {
+            for item in items.iter() {
+                visit_value(item, owned_only, edge);
+            }
+        }
+        Value::Map(entries) if !owned_only || Rc::strong_count(entries) == 1 => {
+            for item in entries.values() {
+                visit_value(item, owned_only, edge);
+            }
+        }
+        Value::Frozen(inner) => visit_value(inner, owned_only, edge),
+        _ => {}
+    }
+}
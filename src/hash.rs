@@ -0,0 +1,63 @@
+//! The hasher behind script maps.
+//!
+//! Script maps are small and keyed by short strings, where std's SipHash
+//! spends more time setting up and finalizing than hashing. This is the
+//! multiply-and-rotate hash used by the Rust compiler (FxHash): a few
+//! instructions per word, no per-map random seed. It gives up SipHash's
+//! resistance to keys crafted to collide, so a script that fills a map from
+//! hostile input can be made slower, but not incorrect.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// A `HashMap` hashing its keys with [`FastHasher`].
+pub type FastHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FastHasher>>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastHasher {
+    hash: u64,
+}
+
+impl FastHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FastHasher {
+    fn write(&mut self, mut bytes: &[u8]) {
+        while let Some((word, rest)) = bytes.split_first_chunk::<8>() {
+            self.add(u64::from_le_bytes(*word));
+            bytes = rest;
+        }
+        if let Some((word, rest)) = bytes.split_first_chunk::<4>() {
+            self.add(u32::from_le_bytes(*word) as u64);
+            bytes = rest;
+        }
+        if let Some((word, rest)) = bytes.split_first_chunk::<2>() {
+            self.add(u16::from_le_bytes(*word) as u64);
+            bytes = rest;
+        }
+        if let Some(&byte) = bytes.first() {
+            self.add(byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
@@ -0,0 +1,130 @@
+//! Callbacks for host tooling that follows a script as it runs, such as a
+//! debugger, a tracer or a coverage tool.
+
+use std::io::Write;
+
+use crate::ast::Span;
+use crate::environment::Value;
+use crate::error::RuntimeError;
+use crate::interpreter::value_to_string;
+use crate::tokens::Position;
+
+/// Implemented by hosts that want to watch execution; install one with
+/// `Interpreter::set_hooks`. Every method does nothing by default, so an
+/// implementation overrides only the events it cares about.
+pub trait Hooks {
+    /// A statement at `span` is about to run. Loop and function bodies
+    /// report their statements each time they run.
+    fn on_statement(&mut self, _span: Span) {}
+
+    /// A user function is being called from `call_site`, with its
+    /// arguments already evaluated.
+    fn on_call(&mut self, _function: &str, _arguments: &[Value], _call_site: Span) {}
+
+    /// A user function finished, returning a value or with the error that
+    /// escaped it.
+    fn on_return(&mut self, _function: &str, _result: Result<&Value, &RuntimeError>) {}
+
+    /// The variable `name` was bound by `let` or assigned, in whole or
+    /// through an index or field, by the code at `span`. `value` is the
+    /// variable's new value.
+    fn on_assign(&mut self, _name: &str, _value: &Value, _span: Span) {}
+}
+
+/// Both sets of hooks, `self.0`'s first, so a tracer and a profiler can
+/// watch the same run.
+impl<A: Hooks, B: Hooks> Hooks for (A, B) {
+    fn on_statement(&mut self, span: Span) {
+        self.0.on_statement(span);
+        self.1.on_statement(span);
+    }
+
+    fn on_call(&mut self, function: &str, arguments: &[Value], call_site: Span) {
+        self.0.on_call(function, arguments, call_site);
+        self.1.on_call(function, arguments, call_site);
+    }
+
+    fn on_return(&mut self, function: &str, result: Result<&Value, &RuntimeError>) {
+        self.0.on_return(function, result);
+        self.1.on_return(function, result);
+    }
+
+    fn on_assign(&mut self, name: &str, value: &Value, span: Span) {
+        self.0.on_assign(name, value, span);
+        self.1.on_assign(name, value, span);
+    }
+}
+
+/// Hooks that log execution as it happens, one line per event, for
+/// `ruilian --trace`. Each statement is shown with its location and the
+/// first line of its source text; calls show their arguments and returns
+/// their value, and everything inside a call is indented one more step.
+///
+/// ```text
+/// [trace] main.rl:3:1  print f(2);
+/// [trace]   call f(2) at main.rl:3:7
+/// [trace]   main.rl:1:10  return a * 2;
+/// [trace]   f returned 4
+/// ```
+pub struct Tracer<W: Write> {
+    out: W,
+    path: String,
+    source: String,
+    depth: usize,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Traces a run of `source`, read from `path`, to `out`.
+    pub fn new(out: W, path: impl Into<String>, source: impl Into<String>) -> Self {
+        Tracer {
+            out,
+            path: path.into(),
+            source: source.into(),
+            depth: 0,
+        }
+    }
+
+    fn location(&self, span: Span) -> String {
+        let position = Position::from_offset(&self.source, span.0);
+        format!("{}:{}:{}", self.path, position.line, position.column)
+    }
+
+    fn log(&mut self, event: std::fmt::Arguments) {
+        let indent = "  ".repeat(self.depth);
+        let _ = writeln!(self.out, "[trace] {}{}", indent, event);
+    }
+}
+
+impl<W: Write> Hooks for Tracer<W> {
+    fn on_statement(&mut self, span: Span) {
+        let text = self.source.get(span.0..span.1).unwrap_or_default();
+        let text = text.lines().next().unwrap_or_default().trim().to_string();
+        let location = self.location(span);
+        self.log(format_args!("{}  {}", location, text));
+    }
+
+    fn on_call(&mut self, function: &str, arguments: &[Value], call_site: Span) {
+        let arguments: Vec<String> = arguments.iter().map(value_to_string).collect();
+        let location = self.location(call_site);
+        self.depth += 1;
+        self.log(format_args!(
+            "call {}({}) at {}",
+            function,
+            arguments.join(", "),
+            location
+        ));
+    }
+
+    fn on_return(&mut self, function: &str, result: Result<&Value, &RuntimeError>) {
+        match result {
+            Ok(value) => {
+                let value = value_to_string(value);
+                self.log(format_args!("{} returned {}", function, value));
+            }
+            Err(error) => {
+                self.log(format_args!("{} failed: {}", function, error.message));
+            }
+        }
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
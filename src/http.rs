@@ -0,0 +1,197 @@
+//! Plain-HTTP/1.1 client for the `http_get` / `http_post` builtins, built on
+//! `std::net` so the crate stays dependency-free. `https://` URLs are
+//! rejected since there is no TLS implementation available.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+pub(crate) struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    /// What the `Host` header names: the host, with the port unless it is
+    /// the default one.
+    fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: Option<&str>,
+) -> Result<Response, String> {
+    let url = parse_url(url)?;
+    for (name, value) in headers {
+        check_header(name, value)?;
+    }
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port))
+        .map_err(|e| format!("cannot connect to {}:{}: {}", url.host, url.port, e))?;
+    stream.set_read_timeout(Some(TIMEOUT)).ok();
+    stream.set_write_timeout(Some(TIMEOUT)).ok();
+
+    let head = request_head(method, &url, headers, body);
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body.unwrap_or("").as_bytes()))
+        .map_err(|e| format!("failed to send request: {}", e))?;
+
+    let mut raw = Vec::new();
+    stream
+        .read_to_end(&mut raw)
+        .map_err(|e| format!("failed to read response: {}", e))?;
+    parse_response(&raw)
+}
+
+/// The request line and headers, up to the blank line before the body.
+pub(crate) fn request_head(
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: Option<&str>,
+) -> String {
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: ruilian\r\n",
+        method,
+        url.path,
+        url.authority()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(body) = body {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+pub(crate) fn parse_url(url: &str) -> Result<Url, String> {
+    if url.starts_with("https://") {
+        return Err("https is not supported (no TLS); use an http:// URL".to_string());
+    }
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported URL '{}'", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in URL '{}'", url))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in URL '{}'", url));
+    }
+    // Both go into the request head verbatim, so a line break or space
+    // would let the URL smuggle in headers or another request.
+    if host.contains(|c: char| c.is_ascii_whitespace()) {
+        return Err(format!("invalid host in URL '{}'", url));
+    }
+    if path.contains([' ', '\t', '\r', '\n']) {
+        return Err(format!(
+            "invalid path in URL '{}': spaces and line breaks must be percent-encoded",
+            url.escape_debug()
+        ));
+    }
+    Ok(Url {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+/// Rejects a header that would not survive being written as one
+/// `name: value` line of the request head.
+fn check_header(name: &str, value: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains([':', '\r', '\n', ' ', '\t']) {
+        return Err(format!("invalid header name '{}'", name.escape_debug()));
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(format!(
+            "invalid value for header '{}': line breaks are not allowed",
+            name
+        ));
+    }
+    Ok(())
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, String> {
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed response: missing header terminator")?;
+    let head = String::from_utf8_lossy(&raw[..split]);
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().unwrap_or("");
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("malformed status line '{}'", status_line))?;
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let mut body = raw[split + 4..].to_vec();
+    let chunked = headers
+        .iter()
+        .any(|(name, value)| name == "transfer-encoding" && value.eq_ignore_ascii_case("chunked"));
+    if chunked {
+        body = decode_chunked(&body)?;
+    }
+
+    Ok(Response {
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("malformed chunked body")?;
+        let size_text = String::from_utf8_lossy(&data[..line_end]);
+        let size_text = size_text.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_text, 16)
+            .map_err(|_| format!("invalid chunk size '{}'", size_text))?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if data.len() < size {
+            return Err("truncated chunked body".to_string());
+        }
+        out.extend_from_slice(&data[..size]);
+        data = data.get(size + 2..).unwrap_or(&[]);
+    }
+}
@@ -1,553 +1,3270 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+#[cfg(feature = "process")]
+use std::env;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::{self, BufRead, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "process")]
+use std::process;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::ast::{BinOp, Expr, LogicalOp, Program, Stmt, UnaryOp};
-use crate::environment::{Environment, Function, Value};
+use crate::ast::{BinOp, Expr, ExprKind, LogicalOp, Program, Slot, Span, Stmt, StmtKind, UnaryOp};
+use crate::builtins::{Builtin, Builtins, Plugin};
+use crate::csv;
+use crate::datetime;
+use crate::environment::{Environment, Function, UserData, Value};
+use crate::error::{CallFrame, RuntimeError, RuntimeErrorKind};
+use crate::gc::{Collector, GcStats};
+use crate::hash::FastHashMap;
+use crate::hooks::Hooks;
+#[cfg(feature = "net")]
+use crate::http;
+use crate::memory::{self, Meter};
+use crate::numeric::{self, Op, Overflow};
+use crate::random::Rng;
+use crate::snapshot::Snapshot;
+use crate::symbol::Symbol;
+
+macro_rules! runtime_error {
+    ($kind:ident, $($arg:tt)*) => {
+        RuntimeError::with_kind(RuntimeErrorKind::$kind, format!($($arg)*))
+    };
+    ($($arg:tt)*) => {
+        RuntimeError::new(format!($($arg)*))
+    };
+}
+
+/// The target of an assignment: a variable, or a path of indices and fields
+/// into the arrays and maps it holds, with the indices already evaluated.
+struct Place {
+    root: PlaceRoot,
+    path: Vec<PathStep>,
+}
+
+enum PlaceRoot {
+    Variable(Symbol, Slot),
+    /// A base that is not a variable, such as a call result.
+    Temporary(Value),
+}
+
+enum PathStep {
+    Index(Value),
+    Field(Symbol),
+}
+
+/// Adapts a text callback to the `Write` the interpreter prints through.
+/// Each print arrives as whole UTF-8 text, one write per piece.
+struct CallbackWriter<F>(F);
+
+impl<F: FnMut(&str)> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where `input()` reads lines from.
+enum Input {
+    /// The process's stdin, locked only for each read.
+    Stdin,
+    Reader(Box<dyn BufRead>),
+    /// A host function returning the next line, or `None` at end of input.
+    Lines(Box<dyn FnMut() -> Option<String>>),
+}
+
+/// Lets another thread stop a running script. Cancelling makes the
+/// interpreter fail with a `Cancelled` error at the next statement it
+/// starts, or partway through a `sleep()`, and the request is used up by
+/// that error so the interpreter can run again afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Consumes a pending cancellation, returning whether there was one.
+    fn take(&self) -> bool {
+        self.0.swap(false, AtomicOrdering::Relaxed)
+    }
+}
+
+/// Whether this is a `wasm32-unknown-unknown` build, as run in a browser.
+/// There is no clock, no threads and no process environment there, and
+/// `std` panics rather than failing when asked for them, so the builtins
+/// that need them report an error instead. I/O fails on its own.
+const BARE_WASM: bool = cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+/// Fails the builtin `name` on targets without a clock, threads or process
+/// environment.
+fn require_os(name: &str) -> Result<(), RuntimeError> {
+    if BARE_WASM {
+        return Err(runtime_error!(
+            Io,
+            "{}() is not available on this platform",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The longest string `*`, `repeat()` and the pad builtins will build, in
+/// bytes. A script asking for more has almost certainly gone wrong.
+const MAX_STRING_LENGTH: usize = 1 << 30;
+
+/// The length in bytes of `count` copies of something `unit` bytes long,
+/// failing if that overflows or exceeds `MAX_STRING_LENGTH`.
+fn repeated_length(unit: usize, count: i64) -> Result<usize, RuntimeError> {
+    usize::try_from(count)
+        .ok()
+        .and_then(|count| unit.checked_mul(count))
+        .filter(|&length| length <= MAX_STRING_LENGTH)
+        .ok_or_else(|| runtime_error!("String of {} copies of {} bytes is too long", count, unit))
+}
+
+/// How many user function calls may be executing at once unless the host
+/// says otherwise. A debug build fits about 600 in the 8 MiB a main thread
+/// usually gets, a release build several times that.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 500;
+
+/// The longest `sleep()` waits between checks for cancellation.
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+/// The standard builtins, implemented by the functions
+/// `standard_function` gives and reached through an interpreter's
+/// `Builtins` table, apart from those behind a `Capability`.
+pub(crate) const BUILTIN_NAMES: &[&str] = &[
+    "print",
+    "len",
+    "to_number",
+    "to_string",
+    "random",
+    "random_int",
+    "random_seed",
+    "input",
+    "now",
+    "time_format",
+    "time_parse",
+    "clock",
+    "sleep",
+    "assert",
+    "assert_eq",
+    "exit",
+    "args",
+    "format",
+    "range",
+    "zip",
+    "reverse",
+    "unique",
+    "flatten",
+    "flatten_deep",
+    "slice",
+    "concat",
+    "index_of",
+    "contains",
+    "sort_by",
+    "group_by",
+    "merge",
+    "deep_merge",
+    "deep_copy",
+    "deep_equal",
+    "chars",
+    "byte_len",
+    "bytes",
+    "repeat",
+    "pad_left",
+    "pad_right",
+    "csv_parse",
+    "csv_stringify",
+    "eprint",
+    "pcall",
+    "globals",
+    "locals",
+    "arity",
+    "fn_name",
+    "fn_params",
+    "freeze",
+    "is_frozen",
+    "same",
+    "gc",
+    "gc_stats",
+    "num_format",
+    "pretty",
+    "print_pretty",
+    "get",
+];
+
+/// A call to a standard builtin, as the function implementing it sees it.
+pub(crate) struct Call<'a> {
+    /// The standard name of the builtin, whatever the script called it.
+    name: &'static str,
+    /// The unevaluated arguments, since some builtins evaluate their own.
+    arguments: &'a [Expr],
+    span: Span,
+}
+
+pub(crate) type StandardFunction = fn(&mut Interpreter, Call) -> Result<Value, RuntimeError>;
+
+/// The function implementing the standard builtin `name`. Each builtin is a
+/// function of its own so its locals stay out of `evaluate_kind`, whose
+/// stack frame every nested expression and call pays for.
+pub(crate) fn standard_function(name: &str) -> Option<StandardFunction> {
+    Some(match name {
+        "print" => Interpreter::builtin_print,
+        "len" => Interpreter::builtin_len,
+        "to_number" => Interpreter::builtin_to_number,
+        "to_string" => Interpreter::builtin_to_string,
+        "random" => Interpreter::builtin_random,
+        "random_int" => Interpreter::builtin_random_int,
+        "random_seed" => Interpreter::builtin_random_seed,
+        "input" => Interpreter::builtin_input,
+        #[cfg(feature = "fs")]
+        "read_file" => Interpreter::builtin_read_file,
+        #[cfg(feature = "fs")]
+        "write_file" => Interpreter::builtin_write_file,
+        #[cfg(feature = "fs")]
+        "append_file" => Interpreter::builtin_write_file,
+        "now" => Interpreter::builtin_now,
+        "time_format" => Interpreter::builtin_time_format,
+        "time_parse" => Interpreter::builtin_time_parse,
+        "clock" => Interpreter::builtin_clock,
+        "sleep" => Interpreter::builtin_sleep,
+        "assert" => Interpreter::builtin_assert,
+        "assert_eq" => Interpreter::builtin_assert_eq,
+        "exit" => Interpreter::builtin_exit,
+        #[cfg(feature = "process")]
+        "getenv" => Interpreter::builtin_getenv,
+        #[cfg(feature = "process")]
+        "setenv" => Interpreter::builtin_setenv,
+        "args" => Interpreter::builtin_args,
+        "format" => Interpreter::builtin_format,
+        "range" => Interpreter::builtin_range,
+        "zip" => Interpreter::builtin_zip,
+        "reverse" => Interpreter::builtin_reverse,
+        "unique" => Interpreter::builtin_unique,
+        "flatten" => Interpreter::builtin_flatten,
+        "flatten_deep" => Interpreter::builtin_flatten,
+        "slice" => Interpreter::builtin_slice,
+        "concat" => Interpreter::builtin_concat,
+        "index_of" => Interpreter::builtin_index_of,
+        "contains" => Interpreter::builtin_contains,
+        "sort_by" => Interpreter::builtin_sort_by,
+        "group_by" => Interpreter::builtin_group_by,
+        "merge" => Interpreter::builtin_merge,
+        "deep_merge" => Interpreter::builtin_merge,
+        "deep_copy" => Interpreter::builtin_deep_copy,
+        "deep_equal" => Interpreter::builtin_deep_equal,
+        "chars" => Interpreter::builtin_chars,
+        "byte_len" => Interpreter::builtin_byte_len,
+        "bytes" => Interpreter::builtin_bytes,
+        "repeat" => Interpreter::builtin_repeat,
+        "pad_left" => Interpreter::builtin_pad,
+        "pad_right" => Interpreter::builtin_pad,
+        "csv_parse" => Interpreter::builtin_csv_parse,
+        "csv_stringify" => Interpreter::builtin_csv_stringify,
+        #[cfg(feature = "net")]
+        "http_get" => Interpreter::builtin_http_request,
+        #[cfg(feature = "net")]
+        "http_post" => Interpreter::builtin_http_request,
+        #[cfg(feature = "process")]
+        "exec" => Interpreter::builtin_exec,
+        #[cfg(feature = "fs")]
+        "list_dir" => Interpreter::builtin_list_dir,
+        #[cfg(feature = "fs")]
+        "exists" => Interpreter::builtin_exists,
+        #[cfg(feature = "fs")]
+        "is_dir" => Interpreter::builtin_exists,
+        #[cfg(feature = "fs")]
+        "mkdir" => Interpreter::builtin_mkdir,
+        #[cfg(feature = "fs")]
+        "remove" => Interpreter::builtin_remove,
+        "eprint" => Interpreter::builtin_eprint,
+        "pcall" => Interpreter::builtin_pcall,
+        "globals" => Interpreter::builtin_globals,
+        "locals" => Interpreter::builtin_locals,
+        "arity" => Interpreter::builtin_arity,
+        "fn_name" => Interpreter::builtin_fn_name,
+        "fn_params" => Interpreter::builtin_fn_params,
+        "freeze" => Interpreter::builtin_freeze,
+        "same" => Interpreter::builtin_same,
+        "gc" => Interpreter::builtin_gc,
+        "gc_stats" => Interpreter::builtin_gc_stats,
+        "is_frozen" => Interpreter::builtin_is_frozen,
+        "num_format" => Interpreter::builtin_num_format,
+        "pretty" => Interpreter::builtin_pretty,
+        "print_pretty" => Interpreter::builtin_print_pretty,
+        "get" => Interpreter::builtin_get,
+        _ => return None,
+    })
+}
+
+/// Why execution of a statement stopped early: a `return` unwinding to the
+/// enclosing call, or a runtime error unwinding to the nearest handler.
+enum Unwind {
+    Return(Value),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
 
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
+    rng: Rng,
+    /// When the interpreter was created, for `clock()`; `None` without a
+    /// clock.
+    started: Option<Instant>,
+    script_args: Vec<String>,
+    /// User functions currently executing, outermost first.
+    call_stack: Vec<CallFrame>,
+    /// The environments those calls were made from, in the same order, so a
+    /// memory measurement can reach what the callers hold.
+    callers: Vec<Rc<RefCell<Environment>>>,
+    /// Whether reading a missing map key is an error rather than 0.
+    strict: bool,
+    overflow: Overflow,
+    /// Frees environments that closures keep alive only through each other.
+    gc: Collector,
+    /// Where `print` and friends write; stdout unless the host redirects it.
+    output: Box<dyn Write>,
+    /// Where `eprint` and warnings write; stderr unless redirected.
+    error_output: Box<dyn Write>,
+    input: Input,
+    /// Statements the script may still execute, if the host set a limit.
+    fuel: Option<u64>,
+    /// How many user function calls may be executing at once.
+    max_call_depth: Option<usize>,
+    memory: Meter,
+    cancel: CancelHandle,
+    hooks: Option<Box<dyn Hooks>>,
+    builtins: Builtins,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         Interpreter {
             environment: Environment::new(),
+            // Seeded from the clock where there is one; scripts wanting
+            // different numbers on every run elsewhere call random_seed().
+            rng: if BARE_WASM {
+                Rng::new(0)
+            } else {
+                Rng::from_time()
+            },
+            started: (!BARE_WASM).then(Instant::now),
+            script_args: Vec::new(),
+            call_stack: Vec::new(),
+            callers: Vec::new(),
+            strict: false,
+            overflow: Overflow::default(),
+            gc: Collector::default(),
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            input: Input::Stdin,
+            fuel: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            memory: Meter::default(),
+            cancel: CancelHandle::default(),
+            hooks: None,
+            builtins: Builtins::standard(),
         }
     }
 
-    pub fn interpret(&mut self, program: &Program) {
-        for stmt in &program.statements {
-            if let Err(return_value) = self.execute(stmt) {
-                println!(
-                    "Warning: Top-level return value ignored: {:?}",
-                    return_value
-                );
+    /// Sets the command-line arguments returned by the `args()` builtin.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.script_args = args;
+    }
+
+    /// In strict mode, reading a map key or field that does not exist raises
+    /// a catchable runtime error instead of yielding nil, and so does a `let`
+    /// that repeats a name already declared in the same scope. Undefined
+    /// variables are an error in either mode.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Chooses what integer `+ - * /` and negation do when the result does
+    /// not fit in an `i64`. The default, `Overflow::Checked`, raises a
+    /// catchable `IntegerOverflow` error.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Lets scripts execute at most `limit` statements, counting loop and
+    /// function bodies each time they run, before failing with a
+    /// `StepLimitExceeded` error that `pcall` cannot catch. The budget is
+    /// shared by every later `interpret` call until it is set again; `None`
+    /// removes it.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.fuel = limit;
+    }
+
+    /// How many statements the script may still execute, if limited.
+    pub fn remaining_steps(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Lets at most `limit` user function calls be executing at once before
+    /// the next call fails with a `CallDepthExceeded` error that `pcall`
+    /// cannot catch, instead of runaway recursion overflowing the host's
+    /// stack. The default is `DEFAULT_MAX_CALL_DEPTH`; `None` removes the
+    /// limit, for hosts that run scripts on a thread with a larger stack.
+    pub fn set_max_call_depth(&mut self, limit: Option<usize>) {
+        self.max_call_depth = limit;
+    }
+
+    /// Replaces the functions scripts can call without defining them, such
+    /// as a table with the file and network builtins removed.
+    pub fn set_builtins(&mut self, builtins: Builtins) {
+        self.builtins = builtins;
+    }
+
+    /// The builtin table, for adding, removing or renaming entries in place.
+    pub fn builtins_mut(&mut self) -> &mut Builtins {
+        &mut self.builtins
+    }
+
+    /// Makes the functions of `plugin` callable from this interpreter's
+    /// scripts.
+    pub fn load_plugin(&mut self, plugin: &dyn Plugin) {
+        self.builtins.install(plugin);
+    }
+
+    /// Reports statements, calls, returns and assignments to `hooks` as the
+    /// script runs, replacing any hooks set before.
+    pub fn set_hooks(&mut self, hooks: impl Hooks + 'static) {
+        self.hooks = Some(Box::new(hooks));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks = None;
+    }
+
+    /// A handle that stops this interpreter's current or next run when
+    /// cancelled. It is `Send`, so a GUI or server thread can hold it while
+    /// another thread runs the script.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    fn check_cancelled(&self) -> Result<(), RuntimeError> {
+        if self.cancel.take() {
+            return Err(runtime_error!(Cancelled, "Execution cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Caps the approximate number of bytes the script's variables may hold,
+    /// counting strings, array slots and map entries, and storage shared
+    /// between values once. Going over fails with a `MemoryLimitExceeded`
+    /// error that `pcall` cannot catch. `None` removes the cap.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory.set_limit(limit);
+    }
+
+    /// The approximate number of bytes reachable from the script's scopes
+    /// right now, counted the way `set_memory_limit` counts them.
+    pub fn memory_usage(&self) -> usize {
+        self.measure_memory(None)
+    }
+
+    fn measure_memory(&self, extra: Option<&Value>) -> usize {
+        let mut roots: Vec<&Rc<RefCell<Environment>>> = self.callers.iter().collect();
+        roots.push(&self.environment);
+        memory::measure(&roots, extra)
+    }
+
+    /// Charges a value an expression just built against the memory limit,
+    /// measuring what the script holds once the charges add up.
+    #[inline(never)]
+    fn account(&mut self, value: &Value) -> Result<(), RuntimeError> {
+        if !self.memory.charge(memory::shallow_size(value)) {
+            return Ok(());
+        }
+        let used = self.measure_memory(Some(value));
+        if self.memory.measured(used) {
+            return Ok(());
+        }
+        Err(runtime_error!(
+            MemoryLimitExceeded,
+            "Memory limit of {} bytes exceeded",
+            self.memory.limit().unwrap_or_default()
+        ))
+    }
+
+    /// Fails before a value of about `bytes` is built if it would not fit in
+    /// what the memory limit leaves, so a script asking for a huge string
+    /// or array is stopped before the host has to allocate it.
+    #[inline(never)]
+    fn reserve(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        let Some(limit) = self.memory.limit() else {
+            return Ok(());
+        };
+        if bytes <= limit
+            && (self.memory.allows(bytes)
+                || self.measure_memory(None).saturating_add(bytes) <= limit)
+        {
+            return Ok(());
+        }
+        Err(runtime_error!(
+            MemoryLimitExceeded,
+            "Memory limit of {} bytes exceeded: {} more bytes needed",
+            limit,
+            bytes
+        ))
+    }
+
+    /// Frees every environment kept alive only by a reference cycle through
+    /// a closure, returning how many were freed. The interpreter also runs
+    /// this on its own once enough closures have been created.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.gc.collect()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc.stats()
+    }
+
+    /// The current environment, for a closure being created, registered
+    /// with the cycle collector.
+    fn capture_environment(&mut self) -> Rc<RefCell<Environment>> {
+        if self.gc.should_collect() {
+            self.gc.collect();
+        }
+        self.gc.track(&self.environment);
+        Rc::clone(&self.environment)
+    }
+
+    /// Binds `name` to `value` in the global scope, replacing any existing
+    /// binding, so the host can hand values (such as userdata) to scripts.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals()
+            .borrow_mut()
+            .define(Symbol::from(name), value);
+    }
+
+    /// The outermost scope, where top-level bindings live.
+    fn globals(&self) -> Rc<RefCell<Environment>> {
+        let mut global = Rc::clone(&self.environment);
+        loop {
+            let enclosing = global.borrow().enclosing().cloned();
+            match enclosing {
+                Some(enclosing) => global = enclosing,
+                None => return global,
             }
         }
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), Value> {
-        match stmt {
-            Stmt::Expr(expr) => {
-                self.evaluate(expr);
-                Ok(())
+    /// Copies every global variable, along with the state of any closures
+    /// they hold, so `restore` can return to this point later.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::take(&self.globals())
+    }
+
+    /// Puts the global variables back as they were when `snapshot` was
+    /// taken, dropping globals defined since. Nothing else is reset: the
+    /// random generator, limits, hooks and output stay as they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        for environment in snapshot.restore_into(&self.globals()) {
+            self.gc.track(&environment);
+        }
+    }
+
+    /// The value of the global `name`, such as a result the script left
+    /// behind, or `None` if no such global was ever defined.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.environment.borrow().get_global(name)
+    }
+
+    /// Sends everything the script prints to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Calls `callback` with each piece of text the script prints, instead
+    /// of writing it to stdout.
+    pub fn set_output_callback(&mut self, callback: impl FnMut(&str) + 'static) {
+        self.output = Box::new(CallbackWriter(callback));
+    }
+
+    /// Sends `eprint` output and interpreter warnings to `output` instead of
+    /// stderr.
+    pub fn set_error_output(&mut self, output: impl Write + 'static) {
+        self.error_output = Box::new(output);
+    }
+
+    pub fn set_error_output_callback(&mut self, callback: impl FnMut(&str) + 'static) {
+        self.error_output = Box::new(CallbackWriter(callback));
+    }
+
+    /// Makes `input()` read lines from `input` instead of stdin, such as a
+    /// `&[u8]` or `io::Cursor` holding canned answers.
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Input::Reader(Box::new(input));
+    }
+
+    /// Makes `input()` return whatever `next_line` does, nil once it returns
+    /// `None`. A host can feed lines from a channel this way.
+    pub fn set_input_callback(&mut self, next_line: impl FnMut() -> Option<String> + 'static) {
+        self.input = Input::Lines(Box::new(next_line));
+    }
+
+    /// Runs a program to completion, stopping at the first runtime error.
+    /// A script calling `exit()` also ends up here, as an error whose kind is
+    /// `RuntimeErrorKind::Exit`.
+    ///
+    /// Successive calls run in the same global scope: variables and
+    /// functions defined by one program are visible to the next, even when
+    /// an earlier program stopped with an error, so an interpreter can be
+    /// fed a session one statement at a time.
+    pub fn interpret(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        self.interpret_value(program).map(|_| ())
+    }
+
+    /// Like `interpret`, but returns the value of the program's last
+    /// statement when that is an expression, such as a final `total;` or
+    /// `main();`, and nil otherwise.
+    pub fn interpret_value(&mut self, program: &Program) -> Result<Value, RuntimeError> {
+        let mut value = Value::Nil;
+        for (i, stmt) in program.statements.iter().enumerate() {
+            let result = match &stmt.kind {
+                StmtKind::Expr(expr) if i + 1 == program.statements.len() => self
+                    .step(stmt.span)
+                    .and_then(|()| self.evaluate(expr))
+                    .map(|result| value = result)
+                    .map_err(|error| Unwind::Error(error.at(stmt.span))),
+                _ => self.execute(stmt),
+            };
+            match result {
+                Ok(()) => {}
+                Err(Unwind::Return(return_value)) => {
+                    let _ = writeln!(
+                        self.error_output,
+                        "Warning: Top-level return value ignored: {:?}",
+                        return_value
+                    );
+                }
+                Err(Unwind::Error(error)) => return Err(error),
             }
-            Stmt::Let { name, initializer } => {
-                let value = match initializer {
-                    Some(expr) => self.evaluate(expr),
-                    None => Value::Number(0),
-                };
-                self.environment.borrow_mut().define(name.clone(), value);
-                Ok(())
+        }
+        Ok(value)
+    }
+
+    /// Calls the global function `name` with `arguments`, as a script would,
+    /// so a host can load handlers once and invoke them again and again.
+    /// The call runs with the same globals, limits and hooks as `interpret`.
+    pub fn call(&mut self, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        match self.get_global(name) {
+            Some(Value::Function(function)) => {
+                // The host made the call, so there is no call site in the
+                // source; the frame points at its start.
+                self.call_function(&function, arguments.to_vec(), (0, 0))
             }
-            Stmt::Print(expr) => {
-                let value = self.evaluate(expr);
-                println!("{}", value_to_string(&value));
-                Ok(())
+            Some(other) => Err(runtime_error!(
+                TypeMismatch,
+                "'{}' is not a function, got {}",
+                name,
+                value_to_string(&other)
+            )),
+            None => Err(runtime_error!(
+                UndefinedVariable,
+                "Undefined function '{}'",
+                name
+            )),
+        }
+    }
+
+    /// Runs the body of a `test` or `bench` block in a scope of its own
+    /// under the globals, as `ruilian test` and `ruilian bench` do once the
+    /// script's top level has run. A `return` ends the block early.
+    pub fn run_block(&mut self, body: &[Stmt]) -> Result<(), RuntimeError> {
+        let previous_env = self.environment.clone();
+        self.environment = Environment::new_enclosed(&previous_env);
+        let mut result = Ok(());
+        for stmt in body {
+            result = self.execute(stmt);
+            if result.is_err() {
+                break;
             }
-            Stmt::Block(statements) => {
-                let new_env = Environment::new_enclosed(&self.environment);
-                let previous_env = self.environment.clone();
-                self.environment = new_env;
+        }
+        self.environment = previous_env;
+        match result {
+            Ok(()) | Err(Unwind::Return(_)) => Ok(()),
+            Err(Unwind::Error(error)) => Err(error),
+        }
+    }
 
-                let mut result = Ok(());
-                for stmt in statements {
-                    result = self.execute(stmt);
-                    if result.is_err() {
-                        break;
+    /// Writes `text` to the script's output.
+    fn write_output(&mut self, text: &str) -> Result<(), RuntimeError> {
+        self.output
+            .write_all(text.as_bytes())
+            .map_err(|e| runtime_error!(Io, "Failed to write output: {}", e))
+    }
+
+    /// Reads one line of input without its trailing newline; nil at end of
+    /// input.
+    fn read_line(&mut self) -> Result<Value, RuntimeError> {
+        let read = |reader: &mut dyn BufRead| {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(line)),
+                Err(e) => Err(runtime_error!(Io, "input() failed to read input: {}", e)),
+            }
+        };
+        let line = match &mut self.input {
+            Input::Stdin => read(&mut io::stdin().lock())?,
+            Input::Reader(reader) => read(reader)?,
+            Input::Lines(next_line) => next_line(),
+        };
+        Ok(match line {
+            Some(mut line) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
                     }
                 }
+                Value::string(line)
+            }
+            None => Value::Nil,
+        })
+    }
 
-                self.environment = previous_env;
-                result
+    /// Spends one step of the host's budget on the statement at `span`, and
+    /// stops there if the host cancelled the run; otherwise tells the hooks
+    /// the statement is starting.
+    fn step(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.check_cancelled().map_err(|error| error.at(span))?;
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(runtime_error!(StepLimitExceeded, "Step limit exceeded").at(span));
             }
-            Stmt::If {
+            *fuel -= 1;
+        }
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_statement(span);
+        }
+        Ok(())
+    }
+
+    fn notify_assign(&mut self, name: &str, value: &Value, span: Span) {
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_assign(name, value, span);
+        }
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        self.step(stmt.span)?;
+        self.execute_kind(&stmt.kind, stmt.span)
+            .map_err(|unwind| match unwind {
+                Unwind::Error(error) => Unwind::Error(error.at(stmt.span)),
+                other => other,
+            })
+    }
+
+    fn execute_kind(&mut self, kind: &StmtKind, span: Span) -> Result<(), Unwind> {
+        // Like `evaluate_kind`, on the path of every nested statement, so the
+        // bigger cases live in functions of their own, kept out of line.
+        match kind {
+            StmtKind::Expr(expr) => match self.evaluate(expr) {
+                Ok(_) => Ok(()),
+                Err(error) => Err(Unwind::Error(error)),
+            },
+            StmtKind::Let { name, initializer } => self
+                .execute_let(name, initializer.as_ref(), span)
+                .map_err(Unwind::Error),
+            StmtKind::Print(expr) => self.execute_print(expr).map_err(Unwind::Error),
+            StmtKind::Block(statements) => self.execute_block(statements),
+            StmtKind::If {
                 condition,
                 then_branch,
                 else_branch,
+            } => self.execute_if(condition, then_branch, else_branch.as_deref()),
+            StmtKind::While { condition, body } => self.execute_while(condition, body),
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => self.execute_for(variable, iterable, body),
+            StmtKind::Function {
+                name, params, body, ..
             } => {
-                let condition_value = self.evaluate(condition);
-                if is_truthy(&condition_value) {
-                    self.execute(then_branch)
-                } else if let Some(else_branch) = else_branch {
-                    self.execute(else_branch)
-                } else {
-                    Ok(())
+                let function = self.make_function(name.clone(), params, body);
+                self.environment.borrow_mut().define(name.clone(), function);
+                Ok(())
+            }
+            StmtKind::Return { value } => Err(match value {
+                Some(expr) => match self.evaluate_raw(expr) {
+                    Ok(value) => Unwind::Return(value),
+                    Err(error) => Unwind::Error(error),
+                },
+                None => Unwind::Return(Value::Number(0)),
+            }),
+            // Only `ruilian test` and `ruilian bench` run these, through
+            // `run_block`.
+            StmtKind::Test { .. } | StmtKind::Bench { .. } => Ok(()),
+        }
+    }
+
+    #[inline(never)]
+    fn execute_print(&mut self, expr: &Expr) -> Result<(), RuntimeError> {
+        let value = self.evaluate(expr)?;
+        self.write_output(&format!("{}\n", value_to_string(&value)))
+    }
+
+    fn execute_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Result<(), Unwind> {
+        let condition_value = self.evaluate(condition)?;
+        if is_truthy(&condition_value) {
+            self.execute(then_branch)
+        } else if let Some(else_branch) = else_branch {
+            self.execute(else_branch)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn execute_while(&mut self, condition: &Expr, body: &Stmt) -> Result<(), Unwind> {
+        while is_truthy(&self.evaluate(condition)?) {
+            self.execute(body)?;
+        }
+        Ok(())
+    }
+
+    /// A function value closing over the current environment.
+    fn make_function(
+        &mut self,
+        name: Symbol,
+        params: &Rc<[Symbol]>,
+        body: &Rc<Vec<Stmt>>,
+    ) -> Value {
+        let closure = self.capture_environment();
+        Value::Function(Function::new(
+            name,
+            Rc::clone(params),
+            Rc::clone(body),
+            closure,
+        ))
+    }
+
+    #[inline(never)]
+    fn execute_let(
+        &mut self,
+        name: &Symbol,
+        initializer: Option<&Expr>,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        let value = match initializer {
+            Some(expr) => self.evaluate_raw(expr)?,
+            None => Value::Number(0),
+        };
+        if self.strict && self.environment.borrow().is_defined_here(name) {
+            return Err(runtime_error!(
+                Redeclaration,
+                "'{}' is already declared in this scope",
+                name
+            ));
+        }
+        self.environment
+            .borrow_mut()
+            .define(name.clone(), value.clone());
+        self.notify_assign(name, &value, span);
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn execute_block(&mut self, statements: &[Stmt]) -> Result<(), Unwind> {
+        let new_env = Environment::new_enclosed(&self.environment);
+        let previous_env = self.environment.clone();
+        self.environment = new_env;
+
+        let mut result = Ok(());
+        for stmt in statements {
+            result = self.execute(stmt);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.environment = previous_env;
+        result
+    }
+
+    #[inline(never)]
+    fn execute_for(
+        &mut self,
+        variable: &Symbol,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Result<(), Unwind> {
+        let iterable_value = unfreeze(self.evaluate_raw(iterable)?);
+
+        match iterable_value {
+            Value::Array(arr) => {
+                for element in arr.iter() {
+                    let loop_env = Environment::new_enclosed(&self.environment);
+                    loop_env
+                        .borrow_mut()
+                        .define(variable.clone(), element.clone());
+
+                    let previous_env = self.environment.clone();
+                    self.environment = loop_env;
+
+                    let result = self.execute(body);
+
+                    self.environment = previous_env;
+
+                    result?;
                 }
+                Ok(())
             }
-            Stmt::While { condition, body } => {
-                while is_truthy(&self.evaluate(condition)) {
+            Value::String(s) => {
+                for ch in s.chars() {
+                    let loop_env = Environment::new_enclosed(&self.environment);
+                    loop_env
+                        .borrow_mut()
+                        .define(variable.clone(), Value::string(ch.to_string()));
+
+                    let previous_env = self.environment.clone();
+                    self.environment = loop_env;
+
                     let result = self.execute(body);
-                    if result.is_err() {
-                        return result;
-                    }
+                    self.environment = previous_env;
+
+                    result?;
                 }
                 Ok(())
             }
-            Stmt::For {
-                variable,
-                iterable,
-                body,
-            } => {
-                let iterable_value = self.evaluate(iterable);
+            _ => {
+                Err(runtime_error!(TypeMismatch, "Can only iterate over arrays or strings").into())
+            }
+        }
+    }
 
-                match iterable_value {
-                    Value::Array(arr) => {
-                        for element in arr {
-                            let loop_env = Environment::new_enclosed(&self.environment);
-                            loop_env.borrow_mut().define(variable.clone(), element);
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.evaluate_raw(expr).map(thaw)
+    }
 
-                            let previous_env = self.environment.clone();
-                            self.environment = loop_env;
+    /// Like `evaluate`, but keeps the frozen marker on the result. Used
+    /// wherever a value is stored rather than consumed: bindings, returns,
+    /// call arguments, literal elements and assignment targets.
+    fn evaluate_raw(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        let value = self
+            .evaluate_kind(expr)
+            .map_err(|error| error.at(expr.span))?;
+        if self.memory.limit().is_some() && allocates(&expr.kind) {
+            self.account(&value).map_err(|error| error.at(expr.span))?;
+        }
+        Ok(value)
+    }
 
-                            let result = self.execute(body);
+    fn evaluate_kind(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        // Every nested expression recurses through here, so each case with
+        // any work to do has a function of its own to keep this frame small.
+        // The ones off the path of an ordinary call are kept out of line, or
+        // the optimizer would merge their frames back in.
+        match &expr.kind {
+            ExprKind::Number(n) => Ok(Value::Number(*n)),
+            ExprKind::Float(f) => Ok(Value::Float(*f)),
+            ExprKind::String(s) => Ok(Value::string(s.clone())),
+            ExprKind::Boolean(b) => Ok(Value::Boolean(*b)),
+            ExprKind::Nil => Ok(Value::Nil),
+            ExprKind::Variable { name, slot } => self.lookup(name, *slot),
+            ExprKind::Assign { name, slot, value } => {
+                let place = Place {
+                    root: PlaceRoot::Variable(name.clone(), *slot),
+                    path: Vec::new(),
+                };
+                self.assign_expr(place, value, expr.span)
+            }
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => self.evaluate_binary(left, operator, right),
+            ExprKind::Logical {
+                left,
+                operator,
+                right,
+            } => self.evaluate_logical(left, operator, right),
+            ExprKind::Unary { operator, right } => {
+                let overflow = self.overflow;
+                self.evaluate(right)
+                    .and_then(|right| unary_value(operator, right, overflow))
+            }
+            ExprKind::Call { callee, arguments } => {
+                self.evaluate_call(callee, arguments, expr.span)
+            }
+            ExprKind::Lambda { params, body, .. } => {
+                Ok(self.make_function(Symbol::from("anonymous"), params, body))
+            }
+            ExprKind::Array(elements) => self.evaluate_all_raw(elements).map(Value::array),
+            ExprKind::Map(pairs) => self.evaluate_map(pairs),
+            ExprKind::Index { object, index } => self.evaluate_index(object, index),
+            ExprKind::IndexAssign {
+                object,
+                index,
+                value,
+            } => self.evaluate_index_assign(object, index, value, expr.span),
+            ExprKind::Dot { object, field } => self.evaluate_dot(object, field),
+            ExprKind::DotAssign {
+                object,
+                field,
+                value,
+            } => self.evaluate_dot_assign(object, field, value, expr.span),
+        }
+    }
 
-                            self.environment = previous_env;
+    fn evaluate_dot(&mut self, object: &Expr, field: &str) -> Result<Value, RuntimeError> {
+        let object = unfreeze(self.evaluate_raw(object)?);
+        self.field_value(object, field)
+    }
 
-                            if result.is_err() {
-                                return result;
-                            }
-                        }
-                        Ok(())
+    #[inline(never)]
+    fn evaluate_index_assign(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let mut place = self.resolve_place(object)?;
+        place.path.push(PathStep::Index(self.evaluate(index)?));
+        self.assign_expr(place, value, span)
+    }
+
+    #[inline(never)]
+    fn evaluate_dot_assign(
+        &mut self,
+        object: &Expr,
+        field: &Symbol,
+        value: &Expr,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let mut place = self.resolve_place(object)?;
+        place.path.push(PathStep::Field(field.clone()));
+        self.assign_expr(place, value, span)
+    }
+
+    /// Evaluates `value` and stores it at `place`, giving it back as the
+    /// value of the assignment.
+    fn assign_expr(
+        &mut self,
+        place: Place,
+        value: &Expr,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let value = self.evaluate_raw(value)?;
+        self.assign_place(place, value.clone(), span)?;
+        Ok(value)
+    }
+
+    fn evaluate_binary(
+        &mut self,
+        left: &Expr,
+        operator: &BinOp,
+        right: &Expr,
+    ) -> Result<Value, RuntimeError> {
+        let left_val = self.evaluate(left)?;
+        let right_val = self.evaluate(right)?;
+        if let BinOp::Multiply = operator
+            && let (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) =
+                (&left_val, &right_val)
+        {
+            self.reserve(s.len().saturating_mul(usize::try_from(*n).unwrap_or(0)))?;
+        }
+        binary_values(operator, &left_val, &right_val, self.overflow)
+    }
+
+    fn evaluate_logical(
+        &mut self,
+        left: &Expr,
+        operator: &LogicalOp,
+        right: &Expr,
+    ) -> Result<Value, RuntimeError> {
+        let left_val = self.evaluate(left)?;
+        match operator {
+            LogicalOp::And if !is_truthy(&left_val) => Ok(Value::Boolean(false)),
+            LogicalOp::Or if is_truthy(&left_val) => Ok(Value::Boolean(true)),
+            _ => self.evaluate(right),
+        }
+    }
+
+    fn evaluate_call(
+        &mut self,
+        callee: &Expr,
+        arguments: &[Expr],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        // A script's own binding wins over a builtin of the same name; the
+        // table is only consulted for names the script has not bound.
+        if let ExprKind::Variable { name, slot } = &callee.kind {
+            if let Some(function) = self.binding(name, *slot) {
+                return self.call_value(thaw(function), arguments, span);
+            }
+            if let Some(builtin) = self.builtins.get(name).cloned() {
+                return self.call_builtin(builtin, arguments, span);
+            }
+        }
+        if let ExprKind::Dot { object, field } = &callee.kind {
+            return self.call_field(object, field, arguments, span);
+        }
+        let callee = self.evaluate(callee)?;
+        self.call_value(callee, arguments, span)
+    }
+
+    #[inline(never)]
+    fn call_builtin(
+        &mut self,
+        builtin: Builtin,
+        arguments: &[Expr],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        match builtin {
+            Builtin::Standard(name, function) => function(
+                self,
+                Call {
+                    name,
+                    arguments,
+                    span,
+                },
+            ),
+            Builtin::Native(function) => {
+                let args = self.evaluate_all(arguments)?;
+                function(&args)
+            }
+        }
+    }
+
+    /// `object.field(arguments)`: a method of a host object, or a function
+    /// stored in a map.
+    #[inline(never)]
+    fn call_field(
+        &mut self,
+        object: &Expr,
+        field: &str,
+        arguments: &[Expr],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let object = unfreeze(self.evaluate_raw(object)?);
+        if let Value::UserData(data) = &object {
+            return self.call_method(data, field, arguments);
+        }
+        let callee = thaw(self.field_value(object, field)?);
+        self.call_value(callee, arguments, span)
+    }
+
+    #[inline(never)]
+    fn evaluate_map(&mut self, pairs: &[(Symbol, Expr)]) -> Result<Value, RuntimeError> {
+        let mut map = FastHashMap::default();
+        for (key, value_expr) in pairs {
+            let value = self.evaluate_raw(value_expr)?;
+            map.insert(key.to_string(), value);
+        }
+        Ok(Value::map(map))
+    }
+
+    fn evaluate_index(&mut self, object: &Expr, index: &Expr) -> Result<Value, RuntimeError> {
+        let object_val = unfreeze(self.evaluate_raw(object)?);
+        let index_val = self.evaluate(index)?;
+
+        match (object_val, index_val) {
+            (Value::Array(arr), Value::Number(idx)) => {
+                let idx = check_index("Array", idx, arr.len())?;
+                Ok(arr[idx].clone())
+            }
+            // Strings index by character, so `"你好"[1]` is `"好"`.
+            (Value::String(s), Value::Number(idx)) => {
+                let idx = check_index("String", idx, s.chars().count())?;
+                Ok(Value::string(s.chars().nth(idx).unwrap().to_string()))
+            }
+            (Value::Map(map), Value::String(key)) => {
+                self.missing_key(map.get(&*key).cloned(), &key)
+            }
+            (Value::Map(_), index_val) => Err(runtime_error!(
+                TypeMismatch,
+                "Map key must be a string, got {:?}",
+                index_val
+            )),
+            _ => Err(runtime_error!(
+                TypeMismatch,
+                "Cannot index a value that is not an array, map or string"
+            )),
+        }
+    }
+
+    /// Reads a variable from the slot the resolver gave it, or by name
+    /// through the scope chain if the program was not resolved.
+    fn lookup(&self, name: &str, slot: Slot) -> Result<Value, RuntimeError> {
+        self.binding(name, slot)
+            .ok_or_else(|| runtime_error!(UndefinedVariable, "Undefined variable '{}'", name))
+    }
+
+    /// The value bound to a variable, if it is bound yet.
+    fn binding(&self, name: &str, slot: Slot) -> Option<Value> {
+        let environment = self.environment.borrow();
+        match slot {
+            Slot::Unresolved => environment.get(name),
+            Slot::Global => environment.get_global(name),
+            Slot::Local { depth, index } => environment.get_at(depth, index),
+        }
+    }
+
+    /// Resolves the target of an assignment to a place, evaluating every
+    /// index along the way exactly once, left to right.
+    fn resolve_place(&mut self, target: &Expr) -> Result<Place, RuntimeError> {
+        match &target.kind {
+            ExprKind::Variable { name, slot } => Ok(Place {
+                root: PlaceRoot::Variable(name.clone(), *slot),
+                path: Vec::new(),
+            }),
+            ExprKind::Index { object, index } => {
+                let mut place = self.resolve_place(object)?;
+                place.path.push(PathStep::Index(self.evaluate(index)?));
+                Ok(place)
+            }
+            ExprKind::Dot { object, field } => {
+                let mut place = self.resolve_place(object)?;
+                place.path.push(PathStep::Field(field.clone()));
+                Ok(place)
+            }
+            _ => Ok(Place {
+                root: PlaceRoot::Temporary(self.evaluate_raw(target)?),
+                path: Vec::new(),
+            }),
+        }
+    }
+
+    /// Stores `value` at `place`. Arrays and maps are values, so writing to
+    /// `a.b[0]` updates `a.b` inside `a` without affecting anything that
+    /// shares their storage.
+    fn assign_place(&mut self, place: Place, value: Value, span: Span) -> Result<(), RuntimeError> {
+        match place.root {
+            PlaceRoot::Variable(name, slot) if place.path.is_empty() => {
+                self.store(&name, slot, value.clone())?;
+                self.notify_assign(&name, &value, span);
+                Ok(())
+            }
+            PlaceRoot::Variable(name, slot) => {
+                // Leave nil in the variable while updating its value, so the
+                // binding does not hold a second reference that would force a
+                // copy of the whole structure.
+                let mut current = self.lookup(&name, slot)?;
+                self.store(&name, slot, Value::Nil)?;
+                let result = set_path(&mut current, &place.path, value);
+                self.store(&name, slot, current.clone())?;
+                if result.is_ok() {
+                    self.notify_assign(&name, &current, span);
+                }
+                result
+            }
+            // Still applied, so a bad index or a frozen value is reported,
+            // but the result has nowhere to go.
+            PlaceRoot::Temporary(mut container) => set_path(&mut container, &place.path, value),
+        }
+    }
+
+    /// Assigns an existing variable through the slot the resolver gave it.
+    fn store(&mut self, name: &str, slot: Slot, value: Value) -> Result<(), RuntimeError> {
+        let mut environment = self.environment.borrow_mut();
+        let assigned = match slot {
+            Slot::Unresolved => environment.assign(name, value),
+            Slot::Global => environment.assign_global(name, value),
+            Slot::Local { depth, index } => environment.assign_at(depth, index, value),
+        };
+        if !assigned {
+            return Err(runtime_error!(
+                UndefinedVariable,
+                "Undefined variable '{}' in assignment",
+                name
+            ));
+        }
+        Ok(())
+    }
+
+    /// The value read for a map key or field: in strict mode an absent key is
+    /// an error, otherwise it reads as nil.
+    fn missing_key(&self, value: Option<Value>, key: &str) -> Result<Value, RuntimeError> {
+        match value {
+            Some(value) => Ok(value),
+            None if self.strict => Err(runtime_error!(UnknownField, "Unknown field '{}'", key)),
+            None => Ok(Value::Nil),
+        }
+    }
+
+    fn evaluate_all(&mut self, arguments: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        arguments.iter().map(|arg| self.evaluate(arg)).collect()
+    }
+
+    fn evaluate_all_raw(&mut self, arguments: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        arguments.iter().map(|arg| self.evaluate_raw(arg)).collect()
+    }
+
+    fn evaluate_args(
+        &mut self,
+        name: &str,
+        arguments: &[Expr],
+        expected: usize,
+    ) -> Result<Vec<Value>, RuntimeError> {
+        if arguments.len() != expected {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "{}() expects {} argument(s) but got {}",
+                name,
+                expected,
+                arguments.len()
+            ));
+        }
+        self.evaluate_all(arguments)
+    }
+
+    /// `object.field` for an already-evaluated, unfrozen object.
+    fn field_value(&self, object: Value, field: &str) -> Result<Value, RuntimeError> {
+        match object {
+            Value::Map(map) => self.missing_key(map.get(field).cloned(), field),
+            _ => Err(runtime_error!(
+                TypeMismatch,
+                "Cannot access field '{}' on non-map value",
+                field
+            )),
+        }
+    }
+
+    /// `handle.name(arguments)` on host userdata.
+    fn call_method(
+        &mut self,
+        data: &UserData,
+        name: &str,
+        arguments: &[Expr],
+    ) -> Result<Value, RuntimeError> {
+        let Some(method) = data.method(name) else {
+            return Err(runtime_error!(
+                TypeMismatch,
+                "<{}> has no method '{}'",
+                data.type_name(),
+                name
+            ));
+        };
+        let args = self.evaluate_all_raw(arguments)?;
+        method(data, &args)
+    }
+
+    fn call_value(
+        &mut self,
+        callee_value: Value,
+        arguments: &[Expr],
+        call_site: Span,
+    ) -> Result<Value, RuntimeError> {
+        match callee_value {
+            Value::Function(function) => {
+                if arguments.len() != function.params.len() {
+                    return Err(runtime_error!(
+                        ArgumentCount,
+                        "Expected {} arguments but got {}",
+                        function.params.len(),
+                        arguments.len()
+                    ));
+                }
+
+                let arg_values = self.evaluate_all_raw(arguments)?;
+                self.call_function(&function, arg_values, call_site)
+            }
+            _ => Err(runtime_error!(TypeMismatch, "Can only call functions")),
+        }
+    }
+
+    /// Invokes a user function with already-evaluated arguments; used by
+    /// calls in script code and by builtins taking callbacks. The call is
+    /// tracked on `call_stack` so errors escaping it carry a trace.
+    fn call_function(
+        &mut self,
+        function: &Function,
+        arg_values: Vec<Value>,
+        call_site: Span,
+    ) -> Result<Value, RuntimeError> {
+        if arg_values.len() != function.params.len() {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "Expected {} arguments but got {}",
+                function.params.len(),
+                arg_values.len()
+            ));
+        }
+        if self
+            .max_call_depth
+            .is_some_and(|limit| self.call_stack.len() >= limit)
+        {
+            return Err(runtime_error!(
+                CallDepthExceeded,
+                "Call depth exceeded: more than {} calls nested",
+                self.call_stack.len()
+            ));
+        }
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_call(&function.name, &arg_values, call_site);
+        }
+
+        let call_env = Environment::new_enclosed(&function.closure);
+        for (param, arg_value) in function.params.iter().zip(arg_values) {
+            call_env.borrow_mut().define(param.clone(), arg_value);
+        }
+
+        let previous_env = std::mem::replace(&mut self.environment, call_env);
+        self.callers.push(Rc::clone(&previous_env));
+        self.call_stack.push(CallFrame {
+            function: function.name.to_string(),
+            call_site,
+        });
+
+        let mut result = Ok(Value::Number(0));
+        for stmt in function.body.iter() {
+            match self.execute(stmt) {
+                Ok(()) => continue,
+                Err(Unwind::Return(value)) => {
+                    result = Ok(value);
+                    break;
+                }
+                Err(Unwind::Error(mut error)) => {
+                    if error.trace.is_empty() {
+                        error.trace = self.call_stack.iter().rev().cloned().collect();
                     }
-                    Value::String(s) => {
-                        for ch in s.chars() {
-                            let loop_env = Environment::new_enclosed(&self.environment);
-                            loop_env
-                                .borrow_mut()
-                                .define(variable.clone(), Value::String(ch.to_string()));
-
-                            let previous_env = self.environment.clone();
-                            self.environment = loop_env;
-
-                            let result = self.execute(body);
-                            self.environment = previous_env;
-
-                            if result.is_err() {
-                                return result;
-                            }
-                        }
-                        Ok(())
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+
+        self.call_stack.pop();
+        self.callers.pop();
+        self.environment = previous_env;
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_return(&function.name, result.as_ref());
+        }
+        result
+    }
+}
+
+/// The standard builtins, each reached through the function
+/// `standard_function` gives for its name.
+impl Interpreter {
+    fn builtin_print(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        for arg in arguments {
+            let value = self.evaluate(arg)?;
+            self.write_output(&format!("{} ", value_to_string(&value)))?;
+        }
+        self.write_output("\n")?;
+        Ok(Value::Number(0))
+    }
+
+    fn builtin_len(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() != 1 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "len() expects exactly 1 argument"
+            ));
+        }
+        // Only the outer container matters, so skip the
+        // full thaw and its walk over every element.
+        let arg_value = unfreeze(self.evaluate_raw(&arguments[0])?);
+        match arg_value {
+            Value::String(s) => Ok(Value::Number(s.chars().count() as i64)),
+            Value::Array(arr) => Ok(Value::Number(arr.len() as i64)),
+            Value::Map(map) => Ok(Value::Number(map.len() as i64)),
+            _ => Err(runtime_error!(
+                TypeMismatch,
+                "len() expects a string, array, or map"
+            )),
+        }
+    }
+
+    fn builtin_to_number(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("to_number", arguments, 1)?;
+        to_number(&args[0])
+    }
+
+    fn builtin_to_string(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("to_string", arguments, 1)?;
+        Ok(Value::string(value_to_string(&args[0])))
+    }
+
+    fn builtin_random(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("random", arguments, 0)?;
+        Ok(Value::Float(self.rng.next_f64()))
+    }
+
+    fn builtin_random_int(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("random_int", arguments, 2)?;
+        match (&args[0], &args[1]) {
+            (Value::Number(lo), Value::Number(hi)) => {
+                if lo > hi {
+                    return Err(runtime_error!(
+                        "random_int() lower bound {} exceeds upper bound {}",
+                        lo,
+                        hi
+                    ));
+                }
+                Ok(Value::Number(self.rng.range_inclusive(*lo, *hi)))
+            }
+            _ => Err(runtime_error!(
+                TypeMismatch,
+                "random_int() expects two integers"
+            )),
+        }
+    }
+
+    fn builtin_random_seed(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("random_seed", arguments, 1)?;
+        match &args[0] {
+            Value::Number(seed) => self.rng.seed(*seed as u64),
+            _ => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "random_seed() expects an integer seed"
+                ));
+            }
+        }
+        Ok(Value::Nil)
+    }
+
+    fn builtin_input(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() > 1 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "input() expects at most 1 argument"
+            ));
+        }
+        if let Some(prompt) = arguments.first() {
+            let prompt = self.evaluate(prompt)?;
+            self.write_output(&value_to_string(&prompt))?;
+            self.output.flush().ok();
+        }
+        self.read_line()
+    }
+
+    #[cfg(feature = "fs")]
+    fn builtin_read_file(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("read_file", arguments, 1)?;
+        let path = expect_string("read_file", &args[0])?;
+        Ok(match fs::read_to_string(path) {
+            Ok(contents) => Value::string(contents),
+            Err(e) => {
+                return Err(runtime_error!(Io, "read_file('{}') failed: {}", path, e));
+            }
+        })
+    }
+
+    #[cfg(feature = "fs")]
+    fn builtin_write_file(
+        &mut self,
+        Call {
+            name, arguments, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args(name, arguments, 2)?;
+        let path = expect_string(name, &args[0])?;
+        let contents = value_to_string(&args[1]);
+        let result = if name == "write_file" {
+            fs::write(path, contents)
+        } else {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+        };
+        if let Err(e) = result {
+            return Err(runtime_error!(Io, "{}('{}') failed: {}", name, path, e));
+        }
+        Ok(Value::Nil)
+    }
+
+    fn builtin_now(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("now", arguments, 0)?;
+        require_os("now")?;
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Ok(Value::Number(secs))
+    }
+
+    fn builtin_time_format(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("time_format", arguments, 2)?;
+        let ts = match &args[0] {
+            Value::Number(ts) => *ts,
+            other => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "time_format() expects an integer timestamp, got {}",
+                    value_to_string(other)
+                ));
+            }
+        };
+        let fmt = expect_string("time_format", &args[1])?;
+        Ok(match datetime::format(ts, fmt) {
+            Ok(s) => Value::string(s),
+            Err(e) => return Err(runtime_error!("time_format(): {}", e)),
+        })
+    }
+
+    fn builtin_time_parse(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("time_parse", arguments, 2)?;
+        let input = expect_string("time_parse", &args[0])?;
+        let fmt = expect_string("time_parse", &args[1])?;
+        Ok(match datetime::parse(input, fmt) {
+            Ok(Some(ts)) => Value::Number(ts),
+            Ok(None) => Value::Nil,
+            Err(e) => return Err(runtime_error!("time_parse(): {}", e)),
+        })
+    }
+
+    fn builtin_clock(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("clock", arguments, 0)?;
+        require_os("clock")?;
+        let elapsed = self
+            .started
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        Ok(Value::Float(elapsed))
+    }
+
+    fn builtin_sleep(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("sleep", arguments, 1)?;
+        require_os("sleep")?;
+        match &args[0] {
+            Value::Number(ms) if *ms >= 0 => {
+                let end = Instant::now() + Duration::from_millis(*ms as u64);
+                loop {
+                    self.check_cancelled()?;
+                    let left = end.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        break;
                     }
-                    _ => panic!("Can only iterate over arrays or strings"),
+                    thread::sleep(left.min(SLEEP_SLICE));
                 }
             }
-            Stmt::Function { name, params, body } => {
-                let function = Function {
-                    name: name.clone(),
-                    params: params.clone(),
-                    body: body.clone(),
-                    closure: Rc::clone(&self.environment),
-                };
-                self.environment
-                    .borrow_mut()
-                    .define(name.clone(), Value::Function(function));
-                Ok(())
+            other => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "sleep() expects a non-negative number of milliseconds, got {}",
+                    value_to_string(other)
+                ));
             }
-            Stmt::Return { value } => {
-                let return_value = match value {
-                    Some(expr) => self.evaluate(expr),
-                    None => Value::Number(0),
-                };
-                Err(return_value)
+        }
+        Ok(Value::Nil)
+    }
+
+    fn builtin_assert(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "assert() expects 1 or 2 arguments"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        if !is_truthy(&args[0]) {
+            match args.get(1) {
+                Some(msg) => {
+                    return Err(runtime_error!(
+                        AssertionFailed,
+                        "Assertion failed: {}",
+                        value_to_string(msg)
+                    ));
+                }
+                None => {
+                    return Err(runtime_error!(AssertionFailed, "Assertion failed"));
+                }
+            }
+        }
+        Ok(Value::Nil)
+    }
+
+    fn builtin_assert_eq(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 || arguments.len() > 3 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "assert_eq() expects 2 or 3 arguments"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        if !is_truthy(&compare_equal(&args[0], &args[1])) {
+            let detail = format!(
+                "left: {}, right: {}",
+                value_to_string(&args[0]),
+                value_to_string(&args[1])
+            );
+            match args.get(2) {
+                Some(msg) => {
+                    return Err(runtime_error!(
+                        AssertionFailed,
+                        "Assertion failed: {} ({})",
+                        value_to_string(msg),
+                        detail
+                    ));
+                }
+                None => {
+                    return Err(runtime_error!(
+                        AssertionFailed,
+                        "Assertion failed: {}",
+                        detail
+                    ));
+                }
+            }
+        }
+        Ok(Value::Nil)
+    }
+
+    fn builtin_exit(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() > 1 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "exit() expects at most 1 argument"
+            ));
+        }
+        let code = match arguments
+            .first()
+            .map(|arg| self.evaluate(arg))
+            .transpose()?
+        {
+            None => 0,
+            // Only the low 8 bits reach the shell, so a
+            // wider code would silently wrap.
+            Some(Value::Number(code @ 0..=255)) => code as i32,
+            Some(Value::Number(code)) => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "exit() code must be between 0 and 255, got {}",
+                    code
+                ));
+            }
+            Some(other) => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "exit() expects an integer exit code, got {}",
+                    value_to_string(&other)
+                ));
+            }
+        };
+        self.output.flush().ok();
+        self.error_output.flush().ok();
+        Err(RuntimeError::with_kind(
+            RuntimeErrorKind::Exit(code),
+            format!("exit({})", code),
+        ))
+    }
+
+    #[cfg(feature = "process")]
+    fn builtin_getenv(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("getenv", arguments, 1)?;
+        let key = expect_string("getenv", &args[0])?;
+        Ok(env::var(key).map(Value::string).unwrap_or(Value::Nil))
+    }
+
+    #[cfg(feature = "process")]
+    fn builtin_setenv(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("setenv", arguments, 2)?;
+        require_os("setenv")?;
+        let key = expect_string("setenv", &args[0])?;
+        if key.is_empty() || key.contains('=') || key.contains('\0') {
+            return Err(runtime_error!(
+                "setenv() got an invalid variable name '{}'",
+                key
+            ));
+        }
+        let value = value_to_string(&args[1]);
+        if value.contains('\0') {
+            return Err(runtime_error!("setenv() value must not contain NUL bytes"));
+        }
+        // SAFETY: the interpreter runs scripts on a single thread; hosts
+        // that embed it alongside other threads reading the environment
+        // should not expose scripts that call setenv().
+        unsafe { env::set_var(key, value) };
+        Ok(Value::Nil)
+    }
+
+    fn builtin_args(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("args", arguments, 0)?;
+        Ok(Value::array(
+            self.script_args
+                .iter()
+                .map(|arg| Value::string(arg.as_str())),
+        ))
+    }
+
+    fn builtin_format(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "format() expects a template string"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let template = expect_string("format", &args[0])?;
+        Ok(Value::string(format_template(template, &args[1..])?))
+    }
+
+    fn builtin_range(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() || arguments.len() > 3 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "range() expects 1 to 3 arguments"
+            ));
+        }
+        let bounds = self
+            .evaluate_all(arguments)?
+            .iter()
+            .map(|arg| expect_integer("range", arg))
+            .collect::<Result<Vec<i64>, _>>()?;
+        let (start, end, step) = match bounds.as_slice() {
+            [end] => (0, *end, 1),
+            [start, end] => (*start, *end, 1),
+            [start, end, step] => (*start, *end, *step),
+            _ => unreachable!(),
+        };
+        let length = usize::try_from(range_length(start, end, step)).unwrap_or(usize::MAX);
+        self.reserve(length.saturating_mul(std::mem::size_of::<Value>()))?;
+        Ok(Value::array(range_values(start, end, step)?))
+    }
+
+    fn builtin_zip(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "zip() expects at least 2 arrays"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let arrays: Vec<&Vec<Value>> = args
+            .iter()
+            .map(|arg| expect_array("zip", arg))
+            .collect::<Result<_, _>>()?;
+        let len = arrays.iter().map(|arr| arr.len()).min().unwrap_or(0);
+        Ok(Value::array((0..len).map(|i| {
+            Value::array(arrays.iter().map(|arr| arr[i].clone()))
+        })))
+    }
+
+    fn builtin_reverse(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("reverse", arguments, 1)?;
+        Ok(match &args[0] {
+            Value::String(s) => Value::string(s.chars().rev().collect::<String>()),
+            other => Value::array(expect_array("reverse", other)?.iter().rev().cloned()),
+        })
+    }
+
+    fn builtin_unique(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("unique", arguments, 1)?;
+        let mut seen: Vec<Value> = Vec::new();
+        for value in expect_array("unique", &args[0])? {
+            if !seen.contains(value) {
+                seen.push(value.clone());
             }
         }
+        Ok(Value::array(seen))
+    }
+
+    fn builtin_flatten(
+        &mut self,
+        Call {
+            name, arguments, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args(name, arguments, 1)?;
+        let depth = if name == "flatten" { 1 } else { usize::MAX };
+        let mut out = Vec::new();
+        flatten_into(expect_array(name, &args[0])?, depth, &mut out);
+        Ok(Value::array(out))
+    }
+
+    fn builtin_slice(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 || arguments.len() > 3 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "slice() expects 2 or 3 arguments"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let bound = |value: &Value| expect_integer("slice", value);
+        let start = bound(&args[1])?;
+        Ok(match &args[0] {
+            Value::String(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let end = args.get(2).map_or(Ok(chars.len() as i64), bound)?;
+                let (from, to) = slice_bounds(chars.len(), start, end);
+                Value::string(chars[from..to].iter().collect::<String>())
+            }
+            other => {
+                let arr = expect_array("slice", other)?;
+                let end = args.get(2).map_or(Ok(arr.len() as i64), bound)?;
+                let (from, to) = slice_bounds(arr.len(), start, end);
+                Value::array(arr[from..to].to_vec())
+            }
+        })
+    }
+
+    fn builtin_concat(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let mut out = Vec::new();
+        for arg in arguments {
+            let value = self.evaluate(arg)?;
+            out.extend(expect_array("concat", &value)?.iter().cloned());
+        }
+        Ok(Value::array(out))
+    }
+
+    fn builtin_index_of(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("index_of", arguments, 2)?;
+        let index = match &args[0] {
+            // Counted in characters, like `len` and indexing.
+            Value::String(s) => {
+                let needle = expect_string("index_of", &args[1])?;
+                s.find(needle).map(|byte| s[..byte].chars().count())
+            }
+            other => expect_array("index_of", other)?
+                .iter()
+                .position(|item| *item == args[1]),
+        };
+        Ok(Value::Number(index.map_or(-1, |i| i as i64)))
+    }
+
+    fn builtin_contains(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("contains", arguments, 2)?;
+        let found = match &args[0] {
+            Value::String(s) => s.contains(expect_string("contains", &args[1])?),
+            other => expect_array("contains", other)?.contains(&args[1]),
+        };
+        Ok(Value::Boolean(found))
+    }
+
+    fn builtin_sort_by(
+        &mut self,
+        Call {
+            arguments, span, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("sort_by", arguments, 2)?;
+        let key_fn = expect_function("sort_by", &args[1])?;
+        let mut keyed: Vec<(Value, Value)> = expect_array("sort_by", &args[0])?
+            .iter()
+            .map(|item| {
+                Ok((
+                    self.call_function(key_fn, vec![item.clone()], span)?,
+                    item.clone(),
+                ))
+            })
+            .collect::<Result<_, RuntimeError>>()?;
+        let mut failure = None;
+        keyed.sort_by(|(a, _), (b, _)| {
+            compare_keys(a, b).unwrap_or_else(|error| {
+                failure.get_or_insert(error);
+                Ordering::Equal
+            })
+        });
+        if let Some(error) = failure {
+            return Err(error);
+        }
+        Ok(Value::array(keyed.into_iter().map(|(_, item)| item)))
+    }
+
+    fn builtin_group_by(
+        &mut self,
+        Call {
+            arguments, span, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("group_by", arguments, 2)?;
+        let key_fn = expect_function("group_by", &args[1])?;
+        let mut groups: FastHashMap<String, Value> = FastHashMap::default();
+        for item in expect_array("group_by", &args[0])? {
+            let key = self.call_function(key_fn, vec![item.clone()], span)?;
+            match groups
+                .entry(value_to_string(&key))
+                .or_insert_with(|| Value::array(Vec::new()))
+            {
+                Value::Array(group) => Rc::make_mut(group).push(item.clone()),
+                _ => unreachable!(),
+            }
+        }
+        Ok(Value::map(groups))
+    }
+
+    fn builtin_merge(
+        &mut self,
+        Call {
+            name, arguments, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "{}() expects at least 2 maps",
+                name
+            ));
+        }
+        let mut merged = FastHashMap::default();
+        for arg in arguments {
+            let value = self.evaluate(arg)?;
+            let overrides = expect_map(name, &value)?;
+            if name == "merge" {
+                merged.extend(overrides.clone());
+            } else {
+                deep_merge_into(&mut merged, overrides);
+            }
+        }
+        Ok(Value::map(merged))
+    }
+
+    fn builtin_deep_copy(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("deep_copy", arguments, 1)?;
+        Ok(deep_copy(&args[0]))
+    }
+
+    fn builtin_deep_equal(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("deep_equal", arguments, 2)?;
+        Ok(Value::Boolean(deep_equal(&args[0], &args[1])))
+    }
+
+    fn builtin_chars(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("chars", arguments, 1)?;
+        let s = expect_string("chars", &args[0])?;
+        Ok(Value::array(
+            s.chars().map(|c| Value::string(c.to_string())),
+        ))
+    }
+
+    fn builtin_byte_len(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("byte_len", arguments, 1)?;
+        let s = expect_string("byte_len", &args[0])?;
+        Ok(Value::Number(s.len() as i64))
+    }
+
+    fn builtin_bytes(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("bytes", arguments, 1)?;
+        let s = expect_string("bytes", &args[0])?;
+        Ok(Value::array(s.bytes().map(|b| Value::Number(b as i64))))
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Value {
-        match expr {
-            Expr::Number(n) => Value::Number(*n),
-            Expr::String(s) => Value::String(s.clone()),
-            Expr::Boolean(b) => Value::Boolean(*b),
-            Expr::Variable(name) => self
-                .environment
-                .borrow()
-                .get(name)
-                .unwrap_or_else(|| panic!("Undefined variable '{}'", name)),
-            Expr::Assign(name, expr) => {
-                let value = self.evaluate(expr);
-                if !self.environment.borrow_mut().assign(name, value.clone()) {
-                    panic!("Undefined variable '{}' in assignment", name);
-                }
-                value
+    fn builtin_repeat(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("repeat", arguments, 2)?;
+        let s = expect_string("repeat", &args[0])?;
+        Ok(match &args[1] {
+            Value::Number(n) => {
+                self.reserve(s.len().saturating_mul(usize::try_from(*n).unwrap_or(0)))?;
+                Value::string(repeat_string(s, *n)?)
             }
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => {
-                let left_val = self.evaluate(left);
-                let right_val = self.evaluate(right);
-
-                match operator {
-                    BinOp::Add => add_values(&left_val, &right_val),
-                    BinOp::Subtract => subtract_values(&left_val, &right_val),
-                    BinOp::Multiply => multiply_values(&left_val, &right_val),
-                    BinOp::Divide => divide_values(&left_val, &right_val),
-                    BinOp::Greater => compare_greater(&left_val, &right_val),
-                    BinOp::GreaterEqual => compare_greater_equal(&left_val, &right_val),
-                    BinOp::Less => compare_less(&left_val, &right_val),
-                    BinOp::LessEqual => compare_less_equal(&left_val, &right_val),
-                    BinOp::EqualEqual => compare_equal(&left_val, &right_val),
-                    BinOp::BangEqual => compare_not_equal(&left_val, &right_val),
-                }
+            other => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "repeat() expects an integer count, got {}",
+                    value_to_string(other)
+                ));
             }
-            Expr::Logical {
-                left,
-                operator,
-                right,
-            } => {
-                let left_val = self.evaluate(left);
+        })
+    }
 
-                match operator {
-                    LogicalOp::And => {
-                        if !is_truthy(&left_val) {
-                            return Value::Boolean(false);
-                        }
-                        self.evaluate(right)
-                    }
-                    LogicalOp::Or => {
-                        if is_truthy(&left_val) {
-                            return Value::Boolean(true);
-                        }
-                        self.evaluate(right)
-                    }
-                }
+    fn builtin_pad(
+        &mut self,
+        Call {
+            name, arguments, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 || arguments.len() > 3 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "{}() expects 2 or 3 arguments",
+                name
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let text = value_to_string(&args[0]);
+        let width = match &args[1] {
+            Value::Number(n) if *n >= 0 => *n,
+            other => {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "{}() expects a non-negative width, got {}",
+                    name,
+                    value_to_string(other)
+                ));
             }
-            Expr::Unary { operator, right } => {
-                let right_val = self.evaluate(right);
-                match operator {
-                    UnaryOp::Negate => match right_val {
-                        Value::Number(n) => Value::Number(-n),
-                        _ => panic!("Cannot negate non-number"),
-                    },
-                    UnaryOp::Not => Value::Boolean(!is_truthy(&right_val)),
+        };
+        let fill = match args.get(2) {
+            None => ' ',
+            Some(value) => {
+                let mut chars = expect_string(name, value)?.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => {
+                        return Err(runtime_error!("{}() fill must be a single character", name));
+                    }
                 }
             }
+        };
+        let missing = width.saturating_sub(text.chars().count() as i64).max(0);
+        let padding_length = repeated_length(fill.len_utf8(), missing)?;
+        self.reserve(text.len() + padding_length)?;
+        let padding: String = std::iter::repeat_n(fill, missing as usize).collect();
+        Ok(Value::string(if name == "pad_left" {
+            padding + &text
+        } else {
+            text + &padding
+        }))
+    }
 
-            Expr::Call { callee, arguments } => {
-                if let Expr::Variable(name) = callee.as_ref() {
-                    match name.as_str() {
-                        "print" => {
-                            for arg in arguments {
-                                let value = self.evaluate(arg);
-                                print!("{} ", value_to_string(&value));
-                            }
-                            println!();
-                            return Value::Number(0);
-                        }
-                        "len" => {
-                            if arguments.len() != 1 {
-                                panic!("len() expects exactly 1 argument");
-                            }
-                            let arg_value = self.evaluate(&arguments[0]);
-                            match arg_value {
-                                Value::String(s) => return Value::Number(s.len() as i64),
-                                Value::Array(arr) => return Value::Number(arr.len() as i64),
-                                Value::Map(map) => return Value::Number(map.len() as i64),
-                                _ => panic!("len() expects a string, array, or map"),
-                            }
-                        }
-                        _ => {}
-                    };
+    fn builtin_csv_parse(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "csv_parse() expects 1 or 2 arguments"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let text = expect_string("csv_parse", &args[0])?;
+        let use_header = args.get(1).is_some_and(is_truthy);
+        let rows = match csv::parse(text) {
+            Ok(rows) => rows,
+            Err(e) => return Err(runtime_error!("csv_parse(): {}", e)),
+        };
+        Ok(csv_rows_to_value(rows, use_header))
+    }
+
+    fn builtin_csv_stringify(
+        &mut self,
+        Call { arguments, .. }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("csv_stringify", arguments, 1)?;
+        let rows = csv_rows_from_value(expect_array("csv_stringify", &args[0])?)?;
+        Ok(Value::string(csv::stringify(&rows)))
+    }
+
+    #[cfg(feature = "net")]
+    fn builtin_http_request(
+        &mut self,
+        Call {
+            name, arguments, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let (min, max) = if name == "http_get" { (1, 2) } else { (2, 3) };
+        if arguments.len() < min || arguments.len() > max {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "{}() expects {} to {} arguments",
+                name,
+                min,
+                max
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let url = expect_string(name, &args[0])?;
+        let (body, headers) = if name == "http_get" {
+            (None, args.get(1))
+        } else {
+            (Some(value_to_string(&args[1])), args.get(2))
+        };
+        let headers: Vec<(String, String)> = headers
+            .map(|headers| {
+                expect_map(name, headers).map(|headers| {
+                    headers
+                        .iter()
+                        .map(|(k, v)| (k.clone(), value_to_string(v)))
+                        .collect()
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let method = if name == "http_get" { "GET" } else { "POST" };
+        Ok(
+            match http::request(method, url, &headers, body.as_deref()) {
+                Ok(response) => http_response_to_value(response),
+                Err(e) => {
+                    return Err(runtime_error!(Io, "{}('{}') failed: {}", name, url, e));
                 }
-                self.call_user_function(callee, arguments)
-            }
+            },
+        )
+    }
 
-            Expr::Array(elements) => {
-                let array_values = elements.iter().map(|e| self.evaluate(e)).collect();
-                Value::Array(array_values)
+    #[cfg(feature = "process")]
+    fn builtin_exec(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "exec() expects 1 or 2 arguments"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let cmd = expect_string("exec", &args[0])?;
+        let cmd_args: Vec<String> = args
+            .get(1)
+            .map(|a| expect_array("exec", a).map(|a| a.iter().map(value_to_string).collect()))
+            .transpose()?
+            .unwrap_or_default();
+        let output = match process::Command::new(cmd).args(&cmd_args).output() {
+            Ok(output) => output,
+            Err(e) => {
+                return Err(runtime_error!(Io, "exec('{}') failed: {}", cmd, e));
             }
+        };
+        let mut result = FastHashMap::default();
+        result.insert(
+            "stdout".to_string(),
+            Value::string(String::from_utf8_lossy(&output.stdout).into_owned()),
+        );
+        result.insert(
+            "stderr".to_string(),
+            Value::string(String::from_utf8_lossy(&output.stderr).into_owned()),
+        );
+        result.insert(
+            "code".to_string(),
+            output
+                .status
+                .code()
+                .map_or(Value::Nil, |c| Value::Number(c as i64)),
+        );
+        Ok(Value::map(result))
+    }
 
-            Expr::Map(pairs) => {
-                let mut map = HashMap::new();
-                for (key, value_expr) in pairs {
-                    let value = self.evaluate(value_expr);
-                    map.insert(key.clone(), value);
-                }
-                Value::Map(map)
+    #[cfg(feature = "fs")]
+    fn builtin_list_dir(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("list_dir", arguments, 1)?;
+        let path = expect_string("list_dir", &args[0])?;
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                return Err(runtime_error!(Io, "list_dir('{}') failed: {}", path, e));
             }
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        Ok(Value::array(names.into_iter().map(Value::string)))
+    }
 
-            Expr::Index { object, index } => {
-                let object_val = self.evaluate(object);
-                let index_val = self.evaluate(index);
+    #[cfg(feature = "fs")]
+    fn builtin_exists(
+        &mut self,
+        Call {
+            name, arguments, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args(name, arguments, 1)?;
+        let path = Path::new(expect_string(name, &args[0])?);
+        Ok(Value::Boolean(if name == "exists" {
+            path.exists()
+        } else {
+            path.is_dir()
+        }))
+    }
 
-                match (object_val, index_val) {
-                    (Value::Array(arr), Value::Number(idx)) => {
-                        let idx = idx as usize;
-                        if idx >= arr.len() {
-                            panic!("Array index {} out of bounds", idx);
-                        }
-                        arr[idx].clone()
-                    }
-                    (Value::Map(map), Value::String(key)) => map
-                        .get(key.as_str())
-                        .cloned()
-                        .unwrap_or_else(|| Value::Number(0)),
-                    (Value::Map(_), index_val) => {
-                        panic!("Map key must be a string, got {:?}", index_val)
-                    }
-                    _ => panic!("Cannot index non-array or non-map"),
-                }
-            }
+    #[cfg(feature = "fs")]
+    fn builtin_mkdir(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("mkdir", arguments, 1)?;
+        let path = expect_string("mkdir", &args[0])?;
+        if let Err(e) = fs::create_dir_all(path) {
+            return Err(runtime_error!(Io, "mkdir('{}') failed: {}", path, e));
+        }
+        Ok(Value::Nil)
+    }
 
-            Expr::IndexAssign {
-                object,
-                index,
-                value,
-            } => {
-                let object_val = self.evaluate(object);
-                let index_val = self.evaluate(index);
-                let value_val = self.evaluate(value);
-
-                match (object_val, index_val) {
-                    (Value::Map(mut map), Value::String(key)) => {
-                        map.insert(key, value_val.clone());
-
-                        match object.as_ref() {
-                            Expr::Variable(var_name) => {
-                                self.environment
-                                    .borrow_mut()
-                                    .assign(var_name, Value::Map(map.clone()));
-                            }
-                            _ => {}
-                        }
-                        value_val
-                    }
-                    (Value::Array(mut arr), Value::Number(idx)) => {
-                        let idx = idx as usize;
-                        if idx >= arr.len() {
-                            panic!("Array index {} out of bounds", idx);
-                        }
-                        arr[idx] = value_val.clone();
-                        if let Expr::Variable(var_name) = object.as_ref() {
-                            self.environment
-                                .borrow_mut()
-                                .assign(var_name, Value::Array(arr.clone()));
-                        }
-                        value_val
-                    }
-                    _ => panic!("Cannot assign to non-array or non-map index"),
-                }
-            }
+    #[cfg(feature = "fs")]
+    fn builtin_remove(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "remove() expects 1 or 2 arguments"
+            ));
+        }
+        let args: Vec<Value> = self.evaluate_all(arguments)?;
+        let path = expect_string("remove", &args[0])?;
+        let recursive = args.get(1).is_some_and(is_truthy);
+        let result = if !Path::new(path).is_dir() {
+            fs::remove_file(path)
+        } else if recursive {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_dir(path)
+        };
+        if let Err(e) = result {
+            return Err(runtime_error!(Io, "remove('{}') failed: {}", path, e));
+        }
+        Ok(Value::Nil)
+    }
 
-            // --- Dot property access ---
-            Expr::Dot { object, field } => {
-                let object_val = self.evaluate(object);
+    fn builtin_eprint(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let parts: Vec<String> = self
+            .evaluate_all(arguments)?
+            .iter()
+            .map(value_to_string)
+            .collect();
+        self.error_output
+            .write_all(format!("{}\n", parts.join(" ")).as_bytes())
+            .map_err(|e| runtime_error!(Io, "Failed to write output: {}", e))?;
+        Ok(Value::Nil)
+    }
 
-                match object_val {
-                    Value::Map(map) => map
-                        .get(field.as_str())
-                        .cloned()
-                        .unwrap_or_else(|| Value::Number(0)),
-                    _ => panic!("Cannot access field '{}' on non-map value", field),
-                }
+    fn builtin_pcall(
+        &mut self,
+        Call {
+            arguments, span, ..
+        }: Call,
+    ) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "pcall() expects a function to call"
+            ));
+        }
+        let args = self.evaluate_all_raw(arguments)?;
+        let function = expect_function("pcall", &args[0])?;
+        let mut result = FastHashMap::default();
+        match self.call_function(function, args[1..].to_vec(), span) {
+            Ok(value) => {
+                result.insert("ok".to_string(), Value::Boolean(true));
+                result.insert("value".to_string(), value);
             }
-
-            Expr::DotAssign {
-                object,
-                field,
-                value,
-            } => {
-                let object_val = self.evaluate(object);
-                let value_val = self.evaluate(value);
-
-                match object_val {
-                    Value::Map(mut map) => {
-                        map.insert(field.clone(), value_val.clone());
-
-                        match object.as_ref() {
-                            Expr::Variable(var_name) => {
-                                self.environment
-                                    .borrow_mut()
-                                    .assign(var_name, Value::Map(map.clone()));
-                            }
-                            _ => {}
-                        }
-                        value_val
-                    }
-                    _ => panic!("Cannot assign to field '{}' on non-map value", field),
-                }
+            Err(error) if !error.is_catchable() => return Err(error),
+            Err(error) => {
+                result.insert("ok".to_string(), Value::Boolean(false));
+                result.insert("error".to_string(), Value::string(error.message));
             }
         }
+        Ok(Value::map(result))
     }
 
-    fn call_user_function(&mut self, callee: &Expr, arguments: &[Expr]) -> Value {
-        let callee_value = self.evaluate(callee);
+    fn builtin_globals(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("globals", arguments, 0)?;
+        Ok(Value::map(self.environment.borrow().global_values()))
+    }
 
-        match callee_value {
-            Value::Function(function) => {
-                if arguments.len() != function.params.len() {
-                    panic!(
-                        "Expected {} arguments but got {}",
-                        function.params.len(),
-                        arguments.len()
-                    );
-                }
+    fn builtin_locals(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("locals", arguments, 0)?;
+        Ok(Value::map(self.environment.borrow().visible_values()))
+    }
 
-                let call_env = Environment::new_enclosed(&function.closure);
-                let arg_values: Vec<Value> =
-                    arguments.iter().map(|arg| self.evaluate(arg)).collect();
+    fn builtin_arity(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("arity", arguments, 1)?;
+        let function = expect_function("arity", &args[0])?;
+        Ok(Value::Number(function.params.len() as i64))
+    }
 
-                for (param, arg_value) in function.params.iter().zip(arg_values) {
-                    call_env.borrow_mut().define(param.clone(), arg_value);
-                }
+    fn builtin_fn_name(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("fn_name", arguments, 1)?;
+        let function = expect_function("fn_name", &args[0])?;
+        Ok(Value::string(function.name.to_string()))
+    }
 
-                let previous_env = self.environment.clone();
-                self.environment = call_env;
+    fn builtin_fn_params(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("fn_params", arguments, 1)?;
+        let function = expect_function("fn_params", &args[0])?;
+        Ok(Value::array(
+            function
+                .params
+                .iter()
+                .map(|param| Value::string(param.to_string())),
+        ))
+    }
 
-                let mut return_value = Value::Number(0);
-                let mut return_occurred = false;
+    fn builtin_freeze(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() != 1 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "freeze() expects exactly 1 argument"
+            ));
+        }
+        Ok(freeze(self.evaluate_raw(&arguments[0])?))
+    }
 
-                for stmt in &function.body {
-                    match self.execute(stmt) {
-                        Ok(()) => continue,
-                        Err(value) => {
-                            return_value = value;
-                            return_occurred = true;
-                            break;
-                        }
-                    }
-                }
+    fn builtin_same(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("same", arguments, 2)?;
+        Ok(Value::Boolean(same_value(&args[0], &args[1])))
+    }
+
+    fn builtin_gc(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("gc", arguments, 0)?;
+        Ok(Value::Number(self.gc.collect() as i64))
+    }
 
-                self.environment = previous_env;
+    fn builtin_gc_stats(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        self.evaluate_args("gc_stats", arguments, 0)?;
+        let stats = self.gc.stats();
+        Ok(Value::map([
+            (
+                "collections".to_string(),
+                Value::Number(stats.collections as i64),
+            ),
+            ("freed".to_string(), Value::Number(stats.freed as i64)),
+            ("tracked".to_string(), Value::Number(stats.tracked as i64)),
+        ]))
+    }
 
-                if return_occurred {
-                    return_value
-                } else {
-                    Value::Number(0)
-                }
-            }
-            _ => panic!("Can only call functions"),
+    fn builtin_is_frozen(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() != 1 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "is_frozen() expects exactly 1 argument"
+            ));
+        }
+        let value = self.evaluate_raw(&arguments[0])?;
+        Ok(Value::Boolean(matches!(value, Value::Frozen(_))))
+    }
+
+    fn builtin_num_format(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "num_format() expects a number and an optional options map"
+            ));
         }
+        let args = self.evaluate_all(arguments)?;
+        let empty = FastHashMap::default();
+        let opts = match args.get(1) {
+            Some(opts) => expect_map("num_format", opts)?,
+            None => &empty,
+        };
+        Ok(Value::string(format_number(&args[0], opts)?))
+    }
+
+    fn builtin_pretty(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("pretty", arguments, 1)?;
+        Ok(Value::string(pretty_string(&args[0], 0)))
+    }
+
+    fn builtin_print_pretty(
+        &mut self,
+        Call { arguments, .. }: Call,
+    ) -> Result<Value, RuntimeError> {
+        let args = self.evaluate_args("print_pretty", arguments, 1)?;
+        self.write_output(&format!("{}\n", pretty_string(&args[0], 0)))?;
+        Ok(Value::Nil)
+    }
+
+    fn builtin_get(&mut self, Call { arguments, .. }: Call) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 || arguments.len() > 3 {
+            return Err(runtime_error!(
+                ArgumentCount,
+                "get() expects a map, a key and an optional default"
+            ));
+        }
+        let mut args = self.evaluate_all(arguments)?;
+        let default = if args.len() == 3 {
+            args.pop().unwrap()
+        } else {
+            Value::Nil
+        };
+        let key = expect_string("get", &args[1])?;
+        let map = expect_map("get", &args[0])?;
+        Ok(map.get(key).cloned().unwrap_or(default))
     }
 }
 
 // ---- Helpers ----
-fn is_truthy(value: &Value) -> bool {
+pub(crate) fn is_truthy(value: &Value) -> bool {
     match value {
         Value::Number(n) => *n != 0,
+        // Zero always fits in a Number, so a BigInt is never zero.
+        Value::BigInt(_) => true,
+        Value::Float(f) => *f != 0.0,
         Value::String(s) => !s.is_empty(),
         Value::Boolean(b) => *b,
         Value::Function(_) => true,
         Value::Array(arr) => !arr.is_empty(),
         Value::Map(map) => !map.is_empty(),
+        Value::Nil => false,
+        Value::Frozen(inner) => is_truthy(inner),
+        Value::UserData(_) => true,
+    }
+}
+
+/// Marks an array or map, and every container nested inside it, as frozen.
+/// Scalars are already immutable and are returned unchanged.
+fn freeze(value: Value) -> Value {
+    match value {
+        Value::Array(arr) => Value::Frozen(Box::new(Value::array(
+            Rc::unwrap_or_clone(arr).into_iter().map(freeze),
+        ))),
+        Value::Map(map) => Value::Frozen(Box::new(Value::map(
+            Rc::unwrap_or_clone(map)
+                .into_iter()
+                .map(|(k, v)| (k, freeze(v))),
+        ))),
+        other => other,
+    }
+}
+
+/// Removes the frozen marker from the outermost value only, leaving nested
+/// containers frozen; used when reading through an index or field.
+fn unfreeze(value: Value) -> Value {
+    match value {
+        Value::Frozen(inner) => *inner,
+        other => other,
+    }
+}
+
+/// Removes the frozen marker at every level so operators and builtins only
+/// ever see plain data. Containers with nothing frozen inside are returned
+/// as they are, still shared.
+fn thaw(value: Value) -> Value {
+    match value {
+        Value::Frozen(inner) => thaw(*inner),
+        Value::Array(arr) if arr.iter().any(contains_frozen) => {
+            Value::array(Rc::unwrap_or_clone(arr).into_iter().map(thaw))
+        }
+        Value::Map(map) if map.values().any(contains_frozen) => Value::map(
+            Rc::unwrap_or_clone(map)
+                .into_iter()
+                .map(|(k, v)| (k, thaw(v))),
+        ),
+        other => other,
+    }
+}
+
+fn contains_frozen(value: &Value) -> bool {
+    match value {
+        Value::Frozen(_) => true,
+        Value::Array(arr) => arr.iter().any(contains_frozen),
+        Value::Map(map) => map.values().any(contains_frozen),
+        _ => false,
     }
 }
 
-fn value_to_string(value: &Value) -> String {
+/// Renders `value` the way `print` shows it.
+pub fn value_to_string(value: &Value) -> String {
     match value {
         Value::Number(n) => n.to_string(),
-        Value::String(s) => s.clone(),
+        Value::BigInt(n) => n.to_string(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::String(s) => s.to_string(),
         Value::Boolean(b) => b.to_string(),
         Value::Function(func) => format!("<function {}>", func.name),
         Value::Array(arr) => {
-            let elements: Vec<String> = arr.iter().map(|v| value_to_string(v)).collect();
+            let elements: Vec<String> = arr.iter().map(value_to_string).collect();
             format!("[{}]", elements.join(", "))
         }
         Value::Map(map) => {
             let mut items = Vec::new();
-            for (key, val) in map {
+            for (key, val) in map.iter() {
                 items.push(format!("{}: {}", key, value_to_string(val)));
             }
             format!("{{{}}}", items.join(", "))
         }
+        Value::Nil => "nil".to_string(),
+        Value::Frozen(inner) => value_to_string(inner),
+        Value::UserData(data) => format!("<{}>", data.type_name()),
     }
 }
 
-fn add_values(left: &Value, right: &Value) -> Value {
-    if let Value::String(s) = left {
-        return Value::String(s.clone() + &value_to_string(right));
+/// Validates an index into an array or string (`what`), reporting the index
+/// and length on failure.
+fn check_index(what: &str, index: i64, length: usize) -> Result<usize, RuntimeError> {
+    if index < 0 || index as usize >= length {
+        return Err(RuntimeError::with_kind(
+            RuntimeErrorKind::IndexOutOfBounds { index, length },
+            format!(
+                "{} index {} out of bounds for length {}",
+                what, index, length
+            ),
+        ));
     }
-    if let Value::String(s) = right {
-        return Value::String(value_to_string(left) + s);
+    Ok(index as usize)
+}
+
+fn expect_string<'a>(name: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(runtime_error!(
+            TypeMismatch,
+            "{}() expects a string, got {}",
+            name,
+            value_to_string(value)
+        )),
     }
+}
 
-    if let (Value::Array(a), Value::Array(b)) = (left, right) {
-        let mut new_array = a.clone();
-        new_array.extend(b.clone());
-        return Value::Array(new_array);
+/// Expands `{}` (next argument), `{0}` (positional) and `{name}` (key of a
+/// map argument) placeholders; `{{` and `}}` produce literal braces.
+fn format_template(template: &str, args: &[Value]) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => {
+                            return Err(runtime_error!(
+                                "format() has an unclosed '{{' in template"
+                            ));
+                        }
+                    }
+                }
+                let spec = spec.trim();
+                let value = if spec.is_empty() {
+                    next += 1;
+                    args.get(next - 1).ok_or_else(|| {
+                        runtime_error!("format() is missing argument {}", next - 1)
+                    })?
+                } else if let Ok(index) = spec.parse::<usize>() {
+                    args.get(index)
+                        .ok_or_else(|| runtime_error!("format() is missing argument {}", index))?
+                } else {
+                    args.iter()
+                        .find_map(|arg| match arg {
+                            Value::Map(map) => map.get(spec),
+                            _ => None,
+                        })
+                        .ok_or_else(|| runtime_error!("format() has no value for '{{{}}}'", spec))?
+                };
+                out.push_str(&value_to_string(value));
+            }
+            '}' => return Err(runtime_error!("format() has an unmatched '}}' in template")),
+            _ => out.push(c),
+        }
     }
+    Ok(out)
+}
 
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
-        _ => panic!("Cannot add {:?} and {:?}", left, right),
+fn expect_array<'a>(name: &str, value: &'a Value) -> Result<&'a Vec<Value>, RuntimeError> {
+    match value {
+        Value::Array(arr) => Ok(arr),
+        _ => Err(runtime_error!(
+            TypeMismatch,
+            "{}() expects an array, got {}",
+            name,
+            value_to_string(value)
+        )),
     }
 }
 
-fn subtract_values(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Number(a - b),
-        _ => panic!("Cannot subtract {:?} from {:?}", right, left),
+/// Whether evaluating an expression of this kind can build a new string,
+/// array or map, or grow one, and so counts against the memory limit.
+fn allocates(kind: &ExprKind) -> bool {
+    matches!(
+        kind,
+        ExprKind::Binary { .. }
+            | ExprKind::Unary { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Array(_)
+            | ExprKind::Map(_)
+            | ExprKind::IndexAssign { .. }
+            | ExprKind::DotAssign { .. }
+    )
+}
+
+fn flatten_into(values: &[Value], depth: usize, out: &mut Vec<Value>) {
+    for value in values {
+        match value {
+            Value::Array(inner) if depth > 0 => flatten_into(inner, depth - 1, out),
+            _ => out.push(value.clone()),
+        }
     }
 }
 
-fn multiply_values(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Number(a * b),
-        _ => panic!("Cannot multiply {:?} and {:?}", left, right),
+fn expect_function<'a>(name: &str, value: &'a Value) -> Result<&'a Function, RuntimeError> {
+    match value {
+        Value::Function(function) => Ok(function),
+        _ => Err(runtime_error!(
+            TypeMismatch,
+            "{}() expects a function, got {}",
+            name,
+            value_to_string(value)
+        )),
     }
 }
 
-fn divide_values(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => {
-            if *b == 0 {
-                panic!("Division by zero");
+fn expect_map<'a>(
+    name: &str,
+    value: &'a Value,
+) -> Result<&'a FastHashMap<String, Value>, RuntimeError> {
+    match value {
+        Value::Map(map) => Ok(map),
+        _ => Err(runtime_error!(
+            TypeMismatch,
+            "{}() expects a map, got {}",
+            name,
+            value_to_string(value)
+        )),
+    }
+}
+
+fn expect_integer(name: &str, value: &Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        _ => Err(runtime_error!(
+            TypeMismatch,
+            "{}() expects an integer, got {}",
+            name,
+            value_to_string(value)
+        )),
+    }
+}
+
+/// Recursively merges `overrides` into `base`; nested maps are merged key by
+/// key, anything else is replaced.
+fn deep_merge_into(base: &mut FastHashMap<String, Value>, overrides: &FastHashMap<String, Value>) {
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (Some(Value::Map(existing)), Value::Map(nested)) => {
+                deep_merge_into(Rc::make_mut(existing), nested)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
             }
-            Value::Number(a / b)
         }
-        _ => panic!("Cannot divide {:?} by {:?}", left, right),
     }
 }
 
-fn compare_greater(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a > b),
-        _ => panic!("Cannot compare {:?} > {:?}", left, right),
+/// Structural copy of a value. Containers are rebuilt element by element so
+/// the result never aliases the original, whatever the storage model.
+fn deep_copy(value: &Value) -> Value {
+    match value {
+        Value::Array(arr) => Value::array(arr.iter().map(deep_copy)),
+        Value::Map(map) => Value::map(
+            map.iter()
+                .map(|(key, value)| (key.clone(), deep_copy(value))),
+        ),
+        other => other.clone(),
     }
 }
 
-fn compare_greater_equal(left: &Value, right: &Value) -> Value {
+/// Structural equality: containers compare element-wise, numbers compare
+/// numerically across int/float, and functions are equal only to themselves.
+fn deep_equal(left: &Value, right: &Value) -> bool {
     match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a >= b),
-        _ => panic!("Cannot compare {:?} >= {:?}", left, right),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| deep_equal(x, y))
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, x)| b.get(key).is_some_and(|y| deep_equal(x, y)))
+        }
+        (Value::Function(a), Value::Function(b)) => a.is_same(b),
+        _ => is_truthy(&compare_equal(left, right)),
     }
 }
 
-fn compare_less(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a < b),
-        _ => panic!("Cannot compare {:?} < {:?}", left, right),
+/// Turns parsed CSV into an array of arrays, or of maps keyed by the first
+/// row when `use_header` is set.
+fn csv_rows_to_value(rows: Vec<Vec<String>>, use_header: bool) -> Value {
+    let mut rows = rows.into_iter();
+    if !use_header {
+        return Value::array(rows.map(|row| Value::array(row.into_iter().map(Value::string))));
     }
+    let header = rows.next().unwrap_or_default();
+    Value::array(rows.map(|row| {
+        Value::map(
+            header
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(Value::string)),
+        )
+    }))
 }
 
-fn compare_less_equal(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a <= b),
-        _ => panic!("Cannot compare {:?} <= {:?}", left, right),
+/// Accepts rows as arrays of values, or as maps (in which case a header row
+/// with the sorted keys of the first map is emitted).
+fn csv_rows_from_value(rows: &[Value]) -> Result<Vec<Vec<String>>, RuntimeError> {
+    let header: Option<Vec<String>> = match rows.first() {
+        Some(Value::Map(first)) => {
+            let mut keys: Vec<String> = first.keys().cloned().collect();
+            keys.sort();
+            Some(keys)
+        }
+        _ => None,
+    };
+    let mut out = Vec::new();
+    if let Some(header) = &header {
+        out.push(header.clone());
+    }
+    for row in rows {
+        out.push(match (row, &header) {
+            (Value::Array(fields), None) => fields.iter().map(value_to_string).collect(),
+            (Value::Map(map), Some(header)) => header
+                .iter()
+                .map(|key| map.get(key).map(value_to_string).unwrap_or_default())
+                .collect(),
+            _ => {
+                return Err(runtime_error!(
+                    "csv_stringify() rows must all be arrays or all be maps"
+                ));
+            }
+        });
     }
+    Ok(out)
 }
 
-fn compare_equal(left: &Value, right: &Value) -> Value {
+#[cfg(feature = "net")]
+fn http_response_to_value(response: http::Response) -> Value {
+    let mut map = FastHashMap::default();
+    map.insert("status".to_string(), Value::Number(response.status as i64));
+    map.insert(
+        "headers".to_string(),
+        Value::map(
+            response
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name, Value::string(value))),
+        ),
+    );
+    map.insert("body".to_string(), Value::string(response.body));
+    Value::map(map)
+}
+
+/// Orders sort keys: numbers numerically, strings lexicographically.
+fn compare_keys(a: &Value, b: &Value) -> Result<Ordering, RuntimeError> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        (Value::Boolean(a), Value::Boolean(b)) => Ok(a.cmp(b)),
+        _ => match numeric::promote(a, b) {
+            Some(operands) => Ok(numeric::compare(operands).unwrap_or(Ordering::Equal)),
+            None => Err(runtime_error!(
+                TypeMismatch,
+                "Cannot compare sort keys {} and {}",
+                value_to_string(a),
+                value_to_string(b)
+            )),
+        },
+    }
+}
+
+/// Resolves possibly-negative slice bounds against `len`, clamping to range.
+fn slice_bounds(len: usize, start: i64, end: i64) -> (usize, usize) {
+    let resolve = |i: i64| {
+        if i < 0 {
+            (len as i64 + i).max(0) as usize
+        } else {
+            (i as usize).min(len)
+        }
+    };
+    let (from, to) = (resolve(start), resolve(end));
+    (from, to.max(from))
+}
+
+/// How many numbers `range_values` gives for these bounds.
+fn range_length(start: i64, end: i64, step: i64) -> u64 {
+    let (start, end, step) = (i128::from(start), i128::from(end), i128::from(step));
+    let distance = if step > 0 { end - start } else { start - end };
+    if step == 0 || distance <= 0 {
+        return 0;
+    }
+    ((distance - 1) / step.abs() + 1) as u64
+}
+
+/// Half-open `[start, end)` sequence; a negative step counts down.
+fn range_values(start: i64, end: i64, step: i64) -> Result<Vec<Value>, RuntimeError> {
+    if step == 0 {
+        return Err(runtime_error!("range() step must not be zero"));
+    }
+    let mut values = Vec::new();
+    let mut current = start;
+    while (step > 0 && current < end) || (step < 0 && current > end) {
+        values.push(Value::Number(current));
+        current = match current.checked_add(step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(values)
+}
+
+/// Converts a value to a number, yielding nil when a string doesn't parse.
+fn to_number(value: &Value) -> Result<Value, RuntimeError> {
+    Ok(match value {
+        Value::Number(n) => Value::Number(*n),
+        Value::Float(f) => Value::Float(*f),
+        Value::Boolean(b) => Value::Number(*b as i64),
+        Value::String(s) => {
+            let s = s.trim();
+            if let Ok(n) = s.parse::<i64>() {
+                Value::Number(n)
+            } else if let Ok(f) = s.parse::<f64>() {
+                Value::Float(f)
+            } else {
+                Value::Nil
+            }
+        }
+        Value::Nil => Value::Nil,
+        _ => {
+            return Err(runtime_error!(
+                TypeMismatch,
+                "to_number() cannot convert {}",
+                value_to_string(value)
+            ));
+        }
+    })
+}
+
+/// `container[path] = value`, updating `container` in place. Arrays and
+/// maps along the path are copied only if another value shares them, and
+/// nothing changes if the assignment fails.
+fn set_path(container: &mut Value, path: &[PathStep], value: Value) -> Result<(), RuntimeError> {
+    let Some((step, rest)) = path.split_first() else {
+        *container = value;
+        return Ok(());
+    };
+    let child = match (&mut *container, step) {
+        (Value::Map(map), PathStep::Index(Value::String(key))) if rest.is_empty() => {
+            Rc::make_mut(map).insert(key.to_string(), value);
+            return Ok(());
+        }
+        (Value::Map(map), PathStep::Field(field)) if rest.is_empty() => {
+            Rc::make_mut(map).insert(field.to_string(), value);
+            return Ok(());
+        }
+        (Value::Map(map), PathStep::Index(Value::String(key))) => Rc::make_mut(map).get_mut(&**key),
+        (Value::Map(map), PathStep::Field(field)) => Rc::make_mut(map).get_mut(&**field),
+        (Value::Array(arr), PathStep::Index(Value::Number(idx))) => {
+            let idx = check_index("Array", *idx, arr.len())?;
+            Some(&mut Rc::make_mut(arr)[idx])
+        }
+        (container, step) => return Err(cannot_assign(container, step)),
+    };
+    match child {
+        Some(child) => set_path(child, rest, value),
+        // A missing key reads as nil, which cannot be assigned into.
+        None => Err(cannot_assign(&Value::Nil, &rest[0])),
+    }
+}
+
+fn cannot_assign(container: &Value, step: &PathStep) -> RuntimeError {
+    match (container, step) {
+        (Value::Frozen(_), PathStep::Index(_)) => {
+            runtime_error!(FrozenValue, "Cannot assign into a frozen value")
+        }
+        (Value::Frozen(_), PathStep::Field(field)) => runtime_error!(
+            FrozenValue,
+            "Cannot assign field '{}' on a frozen value",
+            field
+        ),
+        (_, PathStep::Index(_)) => {
+            runtime_error!(TypeMismatch, "Cannot assign to non-array or non-map index")
+        }
+        (_, PathStep::Field(field)) => runtime_error!(
+            TypeMismatch,
+            "Cannot assign to field '{}' on non-map value",
+            field
+        ),
+    }
+}
+
+/// Identity comparison for `same()`, as opposed to the structural `==`.
+/// Arrays and maps are values, copied whenever they are read or assigned,
+/// so no two of them are ever the same object. Functions are the same only
+/// when they come from one evaluation of a `fn`; other values have no
+/// identity beyond their contents.
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Function(f), Value::Function(g)) => f.is_same(g),
+        (Value::Array(_) | Value::Map(_), _) | (_, Value::Array(_) | Value::Map(_)) => false,
+        _ => a == b,
+    }
+}
+
+/// Applies a binary operator to two evaluated operands.
+pub(crate) fn binary_values(
+    operator: &BinOp,
+    left: &Value,
+    right: &Value,
+    overflow: Overflow,
+) -> Result<Value, RuntimeError> {
+    match operator {
+        BinOp::Add => add_values(left, right, overflow),
+        BinOp::Subtract => subtract_values(left, right, overflow),
+        BinOp::Multiply => multiply_values(left, right, overflow),
+        BinOp::Divide => divide_values(left, right, overflow),
+        BinOp::Greater => compare_values(left, right, ">", Ordering::is_gt),
+        BinOp::GreaterEqual => compare_values(left, right, ">=", Ordering::is_ge),
+        BinOp::Less => compare_values(left, right, "<", Ordering::is_lt),
+        BinOp::LessEqual => compare_values(left, right, "<=", Ordering::is_le),
+        BinOp::EqualEqual => Ok(compare_equal(left, right)),
+        BinOp::BangEqual => Ok(compare_not_equal(left, right)),
+    }
+}
+
+pub(crate) fn unary_value(
+    operator: &UnaryOp,
+    right: Value,
+    overflow: Overflow,
+) -> Result<Value, RuntimeError> {
+    match operator {
+        UnaryOp::Negate => match right {
+            Value::Number(n) => numeric::negate(n, overflow),
+            Value::BigInt(n) => Ok(numeric::from_big(n.negated())),
+            Value::Float(f) => Ok(Value::Float(-f)),
+            _ => Err(runtime_error!(TypeMismatch, "Cannot negate non-number")),
+        },
+        UnaryOp::Not => Ok(Value::Boolean(!is_truthy(&right))),
+    }
+}
+
+fn add_values(left: &Value, right: &Value, overflow: Overflow) -> Result<Value, RuntimeError> {
+    if let Value::String(s) = left {
+        return Ok(Value::string(s.to_string() + &value_to_string(right)));
+    }
+    if let Value::String(s) = right {
+        return Ok(Value::string(value_to_string(left) + s));
+    }
+
+    if let (Value::Array(a), Value::Array(b)) = (left, right) {
+        return Ok(Value::array(a.iter().chain(b.iter()).cloned()));
+    }
+
+    match numeric::promote(left, right) {
+        Some(operands) => numeric::arithmetic(Op::Add, operands, overflow),
+        None => Err(runtime_error!(
+            TypeMismatch,
+            "Cannot add {:?} and {:?}",
+            left,
+            right
+        )),
+    }
+}
+
+fn subtract_values(left: &Value, right: &Value, overflow: Overflow) -> Result<Value, RuntimeError> {
+    match numeric::promote(left, right) {
+        Some(operands) => numeric::arithmetic(Op::Subtract, operands, overflow),
+        None => Err(runtime_error!(
+            TypeMismatch,
+            "Cannot subtract {:?} from {:?}",
+            right,
+            left
+        )),
+    }
+}
+
+fn multiply_values(left: &Value, right: &Value, overflow: Overflow) -> Result<Value, RuntimeError> {
     match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a == b),
-        (Value::String(a), Value::String(b)) => Value::Boolean(a == b),
-        (Value::Boolean(a), Value::Boolean(b)) => Value::Boolean(a == b),
-        (Value::Array(a), Value::Array(b)) => Value::Boolean(a == b),
-        (Value::Map(a), Value::Map(b)) => Value::Boolean(a == b),
-        _ => Value::Boolean(false),
+        (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) => {
+            Ok(Value::string(repeat_string(s, *n)?))
+        }
+        _ => match numeric::promote(left, right) {
+            Some(operands) => numeric::arithmetic(Op::Multiply, operands, overflow),
+            None => Err(runtime_error!(
+                TypeMismatch,
+                "Cannot multiply {:?} and {:?}",
+                left,
+                right
+            )),
+        },
+    }
+}
+
+fn repeat_string(s: &str, count: i64) -> Result<String, RuntimeError> {
+    if count < 0 {
+        return Err(runtime_error!(
+            TypeMismatch,
+            "Cannot repeat a string {} times",
+            count
+        ));
     }
+    repeated_length(s.len(), count)?;
+    Ok(s.repeat(count as usize))
+}
+
+fn divide_values(left: &Value, right: &Value, overflow: Overflow) -> Result<Value, RuntimeError> {
+    match numeric::promote(left, right) {
+        Some(operands) => numeric::arithmetic(Op::Divide, operands, overflow),
+        None => Err(runtime_error!(
+            TypeMismatch,
+            "Cannot divide {:?} by {:?}",
+            left,
+            right
+        )),
+    }
+}
+
+/// `<`, `<=`, `>` and `>=`, which only apply to numbers. Comparisons with
+/// NaN are false.
+fn compare_values(
+    left: &Value,
+    right: &Value,
+    symbol: &str,
+    test: fn(Ordering) -> bool,
+) -> Result<Value, RuntimeError> {
+    match numeric::promote(left, right) {
+        Some(operands) => Ok(Value::Boolean(numeric::compare(operands).is_some_and(test))),
+        None => Err(runtime_error!(
+            TypeMismatch,
+            "Cannot compare {:?} {} {:?}",
+            left,
+            symbol,
+            right
+        )),
+    }
+}
+
+fn compare_equal(left: &Value, right: &Value) -> Value {
+    Value::Boolean(left == right)
 }
 
 fn compare_not_equal(left: &Value, right: &Value) -> Value {
-    match (left, right) {
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a != b),
-        (Value::String(a), Value::String(b)) => Value::Boolean(a != b),
-        (Value::Boolean(a), Value::Boolean(b)) => Value::Boolean(a != b),
-        (Value::Array(a), Value::Array(b)) => Value::Boolean(a != b),
-        (Value::Map(a), Value::Map(b)) => Value::Boolean(a != b),
-        _ => Value::Boolean(true),
+    Value::Boolean(left != right)
+}
+
+/// The most digits `num_format` will print after the point.
+const MAX_PRECISION: i64 = 100;
+
+/// Renders a number according to the `num_format` options map:
+/// `precision` (digits after the point), `style` (`"fixed"` or `"sci"`) and
+/// `thousands` (`true` for `,`, or a custom separator string).
+fn format_number(value: &Value, opts: &FastHashMap<String, Value>) -> Result<String, RuntimeError> {
+    let precision = match opts.get("precision") {
+        Some(p) => {
+            let p = expect_integer("num_format", p)?;
+            if !(0..=MAX_PRECISION).contains(&p) {
+                return Err(runtime_error!(
+                    TypeMismatch,
+                    "num_format() precision must be between 0 and {}, got {}",
+                    MAX_PRECISION,
+                    p
+                ));
+            }
+            Some(p as usize)
+        }
+        None => None,
+    };
+    let separator = match opts.get("thousands") {
+        Some(Value::Boolean(true)) => Some(",".to_string()),
+        Some(Value::Boolean(false)) | None => None,
+        Some(Value::String(s)) => Some(s.to_string()),
+        Some(other) => {
+            return Err(runtime_error!(
+                TypeMismatch,
+                "num_format() thousands must be a boolean or string, got {}",
+                value_to_string(other)
+            ));
+        }
+    };
+    let style = match opts.get("style") {
+        Some(style) => expect_string("num_format", style)?,
+        None => "fixed",
+    };
+
+    let text = match (style, value) {
+        ("fixed", Value::Number(n)) => match precision {
+            Some(p) if p > 0 => format!("{:.*}", p, *n as f64),
+            _ => n.to_string(),
+        },
+        ("fixed", Value::Float(f)) => match precision {
+            Some(p) => format!("{:.*}", p, f),
+            None => format!("{:?}", f),
+        },
+        ("sci", Value::Number(n)) => scientific(*n as f64, precision),
+        ("sci", Value::Float(f)) => scientific(*f, precision),
+        ("fixed" | "sci", other) => {
+            return Err(runtime_error!(
+                TypeMismatch,
+                "num_format() expects a number, got {}",
+                value_to_string(other)
+            ));
+        }
+        (other, _) => {
+            return Err(runtime_error!(
+                TypeMismatch,
+                "num_format() style must be 'fixed' or 'sci', got '{}'",
+                other
+            ));
+        }
+    };
+
+    Ok(match separator {
+        Some(sep) => group_thousands(&text, &sep),
+        None => text,
+    })
+}
+
+fn scientific(f: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(p) => format!("{:.*e}", p, f),
+        None => format!("{:e}", f),
+    }
+}
+
+/// Inserts `sep` between every three digits of the integer part of a
+/// fixed-point number string.
+fn group_thousands(text: &str, sep: &str) -> String {
+    let (sign, rest) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let (int_part, frac_part) = match rest.find('.') {
+        Some(dot) => rest.split_at(dot),
+        None => (rest, ""),
+    };
+    let mut grouped = String::new();
+    for (i, digit) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push_str(sep);
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, frac_part)
+}
+
+/// Multi-line rendering of nested arrays and maps, two spaces per level,
+/// with map keys sorted and strings quoted so nesting is unambiguous.
+fn pretty_string(value: &Value, depth: usize) -> String {
+    let indent = "  ".repeat(depth + 1);
+    let closing = "  ".repeat(depth);
+    match value {
+        Value::Array(arr) if !arr.is_empty() => {
+            let items: Vec<String> = arr
+                .iter()
+                .map(|item| format!("{}{}", indent, pretty_string(item, depth + 1)))
+                .collect();
+            format!("[\n{}\n{}]", items.join(",\n"), closing)
+        }
+        Value::Map(map) if !map.is_empty() => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let items: Vec<String> = keys
+                .into_iter()
+                .map(|key| format!("{}{}: {}", indent, key, pretty_string(&map[key], depth + 1)))
+                .collect();
+            format!("{{\n{}\n{}}}", items.join(",\n"), closing)
+        }
+        Value::String(s) if depth > 0 => format!("{:?}", s),
+        other => value_to_string(other),
     }
 }
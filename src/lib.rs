@@ -1,23 +1,2219 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+pub mod analysis;
 pub mod ast;
+pub mod benchmark;
+pub mod bigint;
+pub mod builtins;
+pub mod cache;
+pub mod capi;
+pub(crate) mod csv;
+pub(crate) mod datetime;
+pub mod diagnostic;
+pub mod dump;
 pub mod environment;
+pub mod error;
+pub mod fold;
+pub mod format;
+pub mod gc;
+pub mod hash;
+pub mod hooks;
+#[cfg(feature = "net")]
+pub(crate) mod http;
 pub mod interpreter;
-pub(crate) mod parser;
-pub(crate) mod scanner;
+pub mod lint;
+mod macros;
+pub(crate) mod memory;
+pub mod numeric;
+pub mod parser;
+pub mod profile;
+pub(crate) mod random;
+pub mod resolver;
+pub mod scanner;
+pub mod snapshot;
+pub mod symbol;
+pub mod testing;
 pub mod tokens;
+pub mod typecheck;
+pub mod wasm;
 
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use environment::Value;
+use error::RunError;
+
+/// Scans, parses and runs `source` in a fresh interpreter, returning scan
+/// errors or the first runtime error instead of aborting the host process.
+pub fn run(source: &str) -> Result<(), RunError> {
+    execute(&compile(source)?)
+}
+
+/// Like `run`, but returns the value of the script's last statement when it
+/// is an expression (`total;`), and nil otherwise.
+pub fn run_source(source: &str) -> Result<Value, RunError> {
+    let program = compile(source)?;
+    Ok(interpreter::Interpreter::new().interpret_value(&program)?)
+}
+
+/// What a script printed, along with how it ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutput {
+    pub result: Result<Value, RunError>,
+    /// Everything written by `print` and the printing builtins, including
+    /// output from before an error.
+    pub output: String,
+}
+
+/// Like `run_source`, but collects the script's output instead of writing it
+/// to stdout.
+pub fn run_source_captured(source: &str) -> RunOutput {
+    let buffer = SharedBuffer::default();
+    let result = compile(source).and_then(|program| {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_output(buffer.clone());
+        Ok(interpreter.interpret_value(&program)?)
+    });
+    let output = String::from_utf8_lossy(&buffer.0.borrow()).into_owned();
+    RunOutput { result, output }
+}
+
+/// A writer whose bytes stay readable through its clones.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Like `run`, but reads the source from `reader` while parsing it, so a
+/// large file or a pipe is never held in memory as a whole. Scan errors take
+/// precedence over parse errors, as in `run`.
+pub fn run_reader(reader: impl Read + 'static) -> Result<(), RunError> {
+    let scan_errors = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&scan_errors);
+    let tokens = scanner::Scanner::from_reader(reader)
+        .filter_map(move |token| token.map_err(|error| sink.borrow_mut().push(error)).ok());
+    let parsed = parser::Parser::from_stream(tokens).parse();
+    let scan_errors = scan_errors.take();
+    if !scan_errors.is_empty() {
+        return Err(RunError::Scan(scan_errors));
+    }
+    execute(&prepare(parsed.map_err(RunError::Parse)?))
+}
+
+/// Scans and parses `source` into its syntax tree, as written: names are
+/// not resolved and constants are not folded. Every scan error, or else
+/// every parse error, is returned together.
+pub fn parse_program(source: &str) -> Result<ast::Program, RunError> {
+    let tokens = scanner::Scanner::new(source)
+        .scan_tokens()
+        .map_err(RunError::Scan)?;
+    parser::Parser::new(tokens).parse().map_err(RunError::Parse)
+}
+
+/// Scans and parses `source` into a program ready to interpret.
+pub(crate) fn compile(source: &str) -> Result<ast::Program, RunError> {
+    parse_program(source).map(prepare)
+}
+
+fn prepare(mut program: ast::Program) -> ast::Program {
+    // Undefined names are still reported when reached, as runtime errors.
+    resolver::resolve(&mut program);
+    fold::fold_constants(&mut program);
+    program
+}
+
+pub(crate) fn execute(program: &ast::Program) -> Result<(), RunError> {
+    interpreter::Interpreter::new().interpret(program)?;
+    Ok(())
+}
+
+/// Scans and parses `source`, then runs `linter` over it without executing
+/// anything.
+pub fn lint(source: &str, linter: &lint::Linter) -> Result<Vec<diagnostic::Diagnostic>, RunError> {
+    Ok(linter.check(&parse_program(source)?))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn runtime_error(source: &str) -> error::RuntimeError {
+        match run(source) {
+            Err(RunError::Runtime(error)) => error,
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn runtime_errors_are_returned() {
+        let error = runtime_error("let x = 1 / 0;");
+        assert_eq!(error.kind, error::RuntimeErrorKind::DivisionByZero);
+        assert_eq!(error.span, Some((8, 13)));
+
+        let error = runtime_error("print missing;");
+        assert_eq!(error.kind, error::RuntimeErrorKind::UndefinedVariable);
+    }
+
+    #[test]
+    fn runtime_errors_record_the_call_stack() {
+        let error =
+            runtime_error("fn inner() { return 1 / 0; } fn outer() { return inner(); } outer();");
+        let names: Vec<&str> = error.trace.iter().map(|f| f.function.as_str()).collect();
+        assert_eq!(names, vec!["inner", "outer"]);
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_fields_and_redeclarations() {
+        let source = "let m = { a: 1 }; print m.b;";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+
+        assert!(interpreter::Interpreter::new().interpret(&program).is_ok());
+
+        let mut strict = interpreter::Interpreter::new();
+        strict.set_strict(true);
+        let error = strict.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::UnknownField);
+
+        let tokens = scanner::Scanner::new("let x = 1; { let x = 2; } let x = 3;")
+            .scan_tokens()
+            .unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+        let error = strict.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::Redeclaration);
+        assert_eq!(error.span, Some((26, 36)));
+    }
+
+    #[test]
+    fn nested_assignment_updates_the_original() {
+        let source = "let c = { staff: [{ role: \"a\" }], tags: { x: [1, 2] } };\n\
+                      c.staff[0].role = \"b\";\n\
+                      c[\"tags\"].x[1] = 3;\n\
+                      assert_eq(c.staff[0].role, \"b\");\n\
+                      assert_eq(c.tags.x, [1, 3]);\n\
+                      let i = 0;\n\
+                      fn next() { i = i + 1; return i - 1; }\n\
+                      let grid = [[0, 0], [0, 0]];\n\
+                      grid[next()][next()] = 5;\n\
+                      assert_eq(grid, [[0, 5], [0, 0]]);\n\
+                      assert_eq(i, 2);";
+        assert_eq!(run(source), Ok(()));
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let source = "fn make_counter() {\n\
+                        let n = 0;\n\
+                        return fn() { n = n + 1; return n; };\n\
+                      }\n\
+                      let a = make_counter();\n\
+                      let b = make_counter();\n\
+                      a(); a();\n\
+                      assert_eq(a(), 3);\n\
+                      assert_eq(b(), 1);\n\
+                      assert_eq(make_counter()(), 1);\n\
+                      let getters = [];\n\
+                      for (i in [1, 2, 3]) { getters = getters + [fn() { return i; }]; }\n\
+                      assert_eq(getters[0](), 1);\n\
+                      assert_eq(getters[2](), 3);\n\
+                      let total = 0;\n\
+                      let add = fn(x) { total = total + x; };\n\
+                      let j = 0;\n\
+                      while (j < 3) { add(j); j = j + 1; }\n\
+                      assert_eq(total, 3);";
+        assert_eq!(run(source), Ok(()));
+    }
+
+    #[test]
+    fn same_compares_identity() {
+        let source = "fn f() { return 1; }\n\
+                      let g = f;\n\
+                      assert(same(f, g), \"copies of a function are the same\");\n\
+                      assert(!(f == g), \"functions are never structurally equal\");\n\
+                      fn make() { return fn() { return 1; }; }\n\
+                      assert(!same(make(), make()), \"each evaluation is distinct\");\n\
+                      let a = [1];\n\
+                      let b = a;\n\
+                      assert(a == b, \"equal contents\");\n\
+                      assert(!same(a, b), \"arrays are copied\");\n\
+                      assert(same(2, 2), \"scalars compare by value\");";
+        assert_eq!(run(source), Ok(()));
+    }
+
+    #[test]
+    fn numeric_rules_follow_the_table() {
+        let source = "assert_eq(7 / 2, 3);\n\
+                      assert_eq(-7 / 2, -3);\n\
+                      assert_eq(7 / 2.0, 3.5);\n\
+                      assert_eq(1 + 0.5, 1.5);\n\
+                      assert(1 == 1.0, \"int equals float\");\n\
+                      assert([1, { a: 2 }] == [1.0, { a: 2.0 }], \"nested too\");\n\
+                      assert(2 > 1.5, \"mixed comparison\");\n\
+                      assert_eq(\"n=\" + 3, \"n=3\");\n\
+                      assert_eq(3.0 + \"\", \"3.0\");\n\
+                      assert_eq(\"ab\" * 2, \"abab\");";
+        assert_eq!(run(source), Ok(()));
+        assert_eq!(
+            runtime_error("print 1.5 / 0;").kind,
+            error::RuntimeErrorKind::DivisionByZero
+        );
+        assert_eq!(
+            runtime_error("print \"a\" * 1.5;").kind,
+            error::RuntimeErrorKind::TypeMismatch
+        );
+    }
+
+    #[test]
+    fn overflow_mode_is_configurable() {
+        let source = "let big = 9223372036854775807;\nlet r = big + 1;\nprint r;\nprint -big - 2;";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+
+        let error = interpreter::Interpreter::new()
+            .interpret(&program)
+            .unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::IntegerOverflow);
+        assert_eq!(error.message, "Integer overflow in 9223372036854775807 + 1");
+
+        for mode in [
+            numeric::Overflow::Wrapping,
+            numeric::Overflow::Saturating,
+            numeric::Overflow::Promote,
+        ] {
+            let mut interpreter = interpreter::Interpreter::new();
+            interpreter.set_overflow(mode);
+            assert_eq!(interpreter.interpret(&program), Ok(()));
+        }
+    }
+
+    #[test]
+    fn promote_mode_computes_exact_big_integers() {
+        let source = "fn fact(n) { let r = 1; while (n > 1) { r = r * n; n = n - 1; } return r; }\n\
+                      let f = fact(30);\n\
+                      assert_eq(to_string(f), \"265252859812191058636308480000000\");\n\
+                      assert_eq(f / fact(28), 870);\n\
+                      assert(f > 9223372036854775807, \"big compares with int\");\n\
+                      assert_eq(to_string(-f + f), \"0\");\n\
+                      let m = -9223372036854775807 - 1;\n\
+                      assert_eq(to_string(-m), \"9223372036854775808\");\n\
+                      assert_eq(-m - 1, 9223372036854775807);";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_overflow(numeric::Overflow::Promote);
+        assert_eq!(interpreter.interpret(&program), Ok(()));
+    }
+
+    #[test]
+    fn strings_are_measured_in_characters() {
+        let source = "let s = \"你好, world\";\n\
+                      assert_eq(len(s), 9);\n\
+                      assert_eq(byte_len(s), 13);\n\
+                      assert_eq(s[1], \"好\");\n\
+                      assert_eq(s[8], \"d\");\n\
+                      assert_eq(index_of(s, \"w\"), 4);\n\
+                      assert(contains(s, \"好,\"), \"substring\");\n\
+                      assert_eq(slice(s, 0, 2), \"你好\");\n\
+                      let n = 0;\n\
+                      for (c in s) { n = n + 1; }\n\
+                      assert_eq(n, len(s));";
+        assert_eq!(run(source), Ok(()));
+        let error = runtime_error("print \"你好\"[2];");
+        assert_eq!(
+            error.kind,
+            error::RuntimeErrorKind::IndexOutOfBounds {
+                index: 2,
+                length: 2
+            }
+        );
+    }
+
+    #[test]
+    fn constants_are_folded_before_running() {
+        use ast::{ExprKind, StmtKind};
+
+        let source = "let a = 2 * 3 + 1; let s = \"a\" + \"b\" + 1; let z = 1 / 0;\n\
+                      if (1 < 2 and true) { print a; } else { print s; }\n\
+                      while (false) { print z; }";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let mut program = parser::Parser::new(tokens).parse().unwrap();
+        fold::fold_constants(&mut program);
+
+        let initializers: Vec<&ExprKind> = program.statements[..3]
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Let {
+                    initializer: Some(initializer),
+                    ..
+                } => &initializer.kind,
+                other => panic!("expected a let, got {:?}", other),
+            })
+            .collect();
+        assert!(matches!(initializers[0], ExprKind::Number(7)));
+        assert!(matches!(initializers[1], ExprKind::String(s) if s == "ab1"));
+        assert!(matches!(initializers[2], ExprKind::Binary { .. }));
+        assert!(matches!(
+            &program.statements[3].kind,
+            StmtKind::Block(body) if matches!(body[0].kind, StmtKind::Print(_))
+        ));
+        assert!(matches!(&program.statements[4].kind, StmtKind::Block(body) if body.is_empty()));
+    }
+
+    #[test]
+    fn long_string_repeats_are_not_folded() {
+        use ast::{ExprKind, StmtKind};
+
+        let source = "let a = \"ab\" * 3;\nif (false) { print \"ab\" * 4611686018427387904; }";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let mut program = parser::Parser::new(tokens).parse().unwrap();
+        fold::fold_constants(&mut program);
+        assert!(matches!(
+            &program.statements[0].kind,
+            StmtKind::Let { initializer: Some(e), .. } if matches!(&e.kind, ExprKind::String(s) if s == "ababab")
+        ));
+        assert!(analysis::check("print \"ab\" * 200000000;", &lint::Linter::new()).is_empty());
+        assert_eq!(
+            run("if (false) { print \"ab\" * 4611686018427387904; }"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn shared_values_are_copied_on_write() {
+        let source = "let a = [1, [2, 3]]; let b = a; b[1][0] = 9;\n\
+                      assert_eq(a, [1, [2, 3]]); assert_eq(b, [1, [9, 3]]);\n\
+                      let m = { k: [1] }; fn poke(x) { x.k[0] = 2; return x; }\n\
+                      assert_eq(poke(m).k, [2]); assert_eq(m.k, [1]);\n\
+                      let f = freeze([[1]]); let g = f; assert_eq(g, [[1]]);\n\
+                      let c = { k: [1] }; fn bad() { c.k[5] = 2; }\n\
+                      let r = pcall(bad); assert_eq(r.ok, false); assert_eq(c, { k: [1] });";
+        assert_eq!(run(source), Ok(()));
+    }
+
+    #[test]
+    fn reading_a_variable_shares_its_storage() {
+        use environment::{Environment, Value};
+        use std::rc::Rc;
+
+        let env = Environment::new();
+        env.borrow_mut()
+            .define("xs".into(), Value::array((0..1000).map(Value::Number)));
+        let (Some(Value::Array(a)), Some(Value::Array(b))) =
+            (env.borrow().get("xs"), env.borrow().get_at(0, 0))
+        else {
+            panic!("expected arrays");
+        };
+        assert!(Rc::ptr_eq(&a, &b));
+    }
+
+    #[test]
+    fn closure_cycles_are_collected() {
+        // Each call leaves a scope holding a function that captured it.
+        let source = "fn make(n) { fn count(k) { if (k > 0) { return count(k - 1); } return n; } return count; }\n\
+                      let i = 0;\n\
+                      while (i < 50) { make(i); i = i + 1; }\n\
+                      let kept = make(7);\n\
+                      assert(gc() >= 50);\n\
+                      assert(kept(3) == 7);\n\
+                      assert(gc_stats().collections == 1);";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let mut program = parser::Parser::new(tokens).parse().unwrap();
+        resolver::resolve(&mut program);
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.interpret(&program).unwrap();
+        assert_eq!(interpreter.collect_garbage(), 0);
+        assert_eq!(interpreter.gc_stats().collections, 2);
+    }
+
+    #[test]
+    fn streamed_source_scans_like_a_string() {
+        /// Hands out one byte per read, splitting every UTF-8 sequence.
+        struct Trickle(Vec<u8>, usize);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let Some(&byte) = self.0.get(self.1) else {
+                    return Ok(0);
+                };
+                buf[0] = byte;
+                self.1 += 1;
+                Ok(1)
+            }
+        }
+
+        let source = "let s = \"héllo → ✓\"; // ç\nprint s + 1.5;\n".repeat(400);
+        let summary = |t: &tokens::TokenWithSpan| (t.token.clone(), t.span, t.line, t.column);
+        let expected: Vec<_> = scanner::Scanner::new(&source)
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(summary)
+            .collect();
+        let streamed: Vec<_> = scanner::Scanner::from_reader(Trickle(source.into_bytes(), 0))
+            .map(|token| summary(&token.unwrap()))
+            .collect();
+        assert_eq!(streamed, expected);
+
+        let script = "let x = [1, 2];\n".repeat(2000) + "assert_eq(len(x), 2);";
+        assert_eq!(run_reader(Trickle(script.into_bytes(), 0)), Ok(()));
+        match run_reader(Trickle(b"let x = \"\xff\";".to_vec(), 0)) {
+            Err(RunError::Scan(errors)) => {
+                assert_eq!(errors[0].message, "Source is not valid UTF-8")
+            }
+            other => panic!("expected a scan error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn program_cache_reuses_parsed_programs() {
+        let mut cache = cache::ProgramCache::new(2);
+        let first = cache.get("let a = 1;").unwrap();
+        assert!(Rc::ptr_eq(&first, &cache.get("let a = 1;").unwrap()));
+        assert_eq!(cache.run("assert_eq(1 + 1, 2);"), Ok(()));
+        assert!(matches!(cache.run("let = ;"), Err(RunError::Parse(_))));
+        assert_eq!(cache.len(), 2);
+
+        // The third distinct program evicts the least recently used one.
+        cache.get("let a = 1;").unwrap();
+        cache.get("let b = 2;").unwrap();
+        assert!(Rc::ptr_eq(&first, &cache.get("let a = 1;").unwrap()));
+        assert_eq!(cache.len(), 2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 4));
+    }
+
+    #[test]
+    fn run_source_returns_the_last_value_and_output() {
+        let value = run_source("fn sq(x) { return x * x; } sq(7);").unwrap();
+        assert_eq!(value, Value::Number(49));
+        assert_eq!(run_source("let a = 1;"), Ok(Value::Nil));
+
+        let captured = run_source_captured("print \"hi\"; print_pretty([1]); 1 / 0;");
+        assert_eq!(captured.output, "hi\n[\n  1\n]\n");
+        assert!(matches!(captured.result, Err(RunError::Runtime(_))));
+    }
+
+    #[test]
+    fn output_streams_can_be_redirected() {
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let errors = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = interpreter::Interpreter::new();
+        let sink = Rc::clone(&printed);
+        interpreter.set_output_callback(move |text| sink.borrow_mut().push(text.to_string()));
+        interpreter.set_error_output(SharedBuffer(Rc::clone(&errors)));
+
+        let program = compile("print \"out\"; eprint(\"bad\", 1);").unwrap();
+        interpreter.interpret(&program).unwrap();
+        assert_eq!(*printed.borrow(), ["out\n"]);
+        assert_eq!(*errors.borrow(), b"bad 1\n");
+    }
+
+    #[test]
+    fn hosts_read_and_write_globals() {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_global("limit", Value::Number(4));
+        let program = compile(
+            "let total = 0; fn add(n) { let i = n; total = total + i; } for (n in range(1, limit + 1)) { add(n); }",
+        )
+        .unwrap();
+        interpreter.interpret(&program).unwrap();
+        assert_eq!(interpreter.get_global("total"), Some(Value::Number(10)));
+        assert_eq!(interpreter.get_global("i"), None);
+        assert_eq!(interpreter.get_global("missing"), None);
+    }
+
+    #[test]
+    fn successive_programs_share_globals() {
+        let mut interpreter = interpreter::Interpreter::new();
+        for source in [
+            "let count = 1;",
+            "fn bump() { count = count + 1; }",
+            "bump(); bump();",
+        ] {
+            interpreter.interpret(&compile(source).unwrap()).unwrap();
+        }
+        let program = compile("count;").unwrap();
+        assert_eq!(interpreter.interpret_value(&program), Ok(Value::Number(3)));
+
+        let failing = compile("{ let inner = 1; fn f() { missing(); } f(); }").unwrap();
+        assert!(interpreter.interpret(&failing).is_err());
+        let program = compile("let after = count; after;").unwrap();
+        assert_eq!(interpreter.interpret_value(&program), Ok(Value::Number(3)));
+        assert_eq!(interpreter.get_global("inner"), None);
+    }
+
+    #[test]
+    fn step_limit_stops_runaway_scripts() {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_step_limit(Some(1000));
+        let program =
+            compile("fn spin() { while (true) {} } let r = pcall(spin); print r;").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::StepLimitExceeded);
+        assert_eq!(interpreter.remaining_steps(), Some(0));
+
+        interpreter.set_step_limit(Some(3));
+        let program = compile("let a = 1; let b = 2; a + b;").unwrap();
+        assert_eq!(interpreter.interpret_value(&program), Ok(Value::Number(3)));
+        assert_eq!(interpreter.remaining_steps(), Some(0));
+    }
+
+    #[test]
+    fn call_depth_limit_stops_runaway_recursion() {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_step_limit(Some(1000));
+        interpreter.set_max_call_depth(Some(50));
+        let program = compile("fn f(n) { return f(n + 1); } pcall(f, 0);").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::CallDepthExceeded);
+        assert_eq!(error.trace.len(), 50);
+
+        // The default limit stops it too, before a main thread's stack runs
+        // out even in a debug build.
+        let kind = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(|| runtime_error("fn f(n) { return f(n + 1); } f(0);").kind)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(kind, error::RuntimeErrorKind::CallDepthExceeded);
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_max_call_depth(Some(3));
+        let program =
+            compile("fn f(n) { if (n == 0) { return 0; } return f(n - 1); } f(2);").unwrap();
+        assert_eq!(interpreter.interpret_value(&program), Ok(Value::Number(0)));
+    }
+
+    #[test]
+    fn memory_limit_stops_growing_scripts() {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_memory_limit(Some(1 << 20));
+        let program = compile(
+            "let chunks = []; while (true) { chunks = chunks + [repeat(\"x\", 1000) + len(chunks)]; }",
+        )
+        .unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::MemoryLimitExceeded);
+
+        // Shared storage is counted once, and dropped values not at all.
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_memory_limit(Some(1 << 20));
+        let program = compile(
+            "let big = repeat(\"x\", 400000); let copies = [big, big, big, big]; \
+             for (i in range(0, 20)) { let temp = repeat(\"y\", 400000); }",
+        )
+        .unwrap();
+        interpreter.interpret(&program).unwrap();
+        let used = interpreter.memory_usage();
+        assert!((400000..500000).contains(&used), "{}", used);
+    }
+
+    #[test]
+    fn memory_limit_refuses_huge_values_before_building_them() {
+        for source in [
+            "range(0, 100000000);",
+            "range(100000000, 0, -1);",
+            "pad_left(\"x\", 100000000);",
+            "pad_right(\"x\", 100000000, \"-\");",
+            "let s = \"x\"; s * 100000000;",
+            "let n = 100000000; n * \"x\";",
+            "repeat(\"x\", 100000000);",
+        ] {
+            let mut interpreter = interpreter::Interpreter::new();
+            interpreter.set_memory_limit(Some(1_000_000));
+            let error = interpreter
+                .interpret(&compile(source).unwrap())
+                .unwrap_err();
+            assert_eq!(
+                error.kind,
+                error::RuntimeErrorKind::MemoryLimitExceeded,
+                "{}",
+                source
+            );
+        }
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_memory_limit(Some(1_000_000));
+        let program = compile(
+            "let keep = repeat(\"x\", 600000); len(range(0, 1000)) + len(pad_left(\"x\", 1000));",
+        )
+        .unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program),
+            Ok(Value::Number(2000))
+        );
+        let program = compile("repeat(\"y\", 500000);").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::MemoryLimitExceeded);
+    }
+
+    #[test]
+    fn hosts_can_cancel_a_running_script() {
+        let mut interpreter = interpreter::Interpreter::new();
+        let handle = interpreter.cancel_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.cancel();
+        });
+        let program = compile("fn spin() { while (true) { sleep(5); } } pcall(spin);").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(error.kind, error::RuntimeErrorKind::Cancelled);
+
+        // The cancellation was used up, so the interpreter runs again.
+        assert!(!interpreter.cancel_handle().is_cancelled());
+        let program = compile("1 + 1;").unwrap();
+        assert_eq!(interpreter.interpret_value(&program), Ok(Value::Number(2)));
+    }
+
+    #[test]
+    fn hooks_see_statements_calls_and_assignments() {
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl hooks::Hooks for Recorder {
+            fn on_statement(&mut self, span: ast::Span) {
+                self.0.borrow_mut().push(format!("stmt {}", span.0));
+            }
+
+            fn on_call(&mut self, function: &str, arguments: &[Value], _call_site: ast::Span) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("call {} {:?}", function, arguments));
+            }
+
+            fn on_return(&mut self, function: &str, result: Result<&Value, &error::RuntimeError>) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("return {} {:?}", function, result.ok()));
+            }
+
+            fn on_assign(&mut self, name: &str, value: &Value, span: ast::Span) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("assign {} {:?} at {}", name, value, span.0));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_hooks(Recorder(Rc::clone(&events)));
+        let program = compile("let a = [1];\nfn f(x) { return x + 1; }\na[0] = f(1);").unwrap();
+        interpreter.interpret(&program).unwrap();
+        assert_eq!(
+            *events.borrow(),
+            [
+                "stmt 0",
+                "assign a Array([Number(1)]) at 0",
+                "stmt 13",
+                "stmt 39",
+                "call f [Number(1)]",
+                "stmt 23",
+                "return f Some(Number(2))",
+                "assign a Array([Number(2)]) at 39",
+            ]
+        );
+    }
+
+    #[test]
+    fn tracer_logs_statements_calls_and_returns() {
+        let source = "fn f(a) {\n  return a * 2;\n}\nprint f(2);";
+        let buffer = SharedBuffer::default();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_output(SharedBuffer::default());
+        interpreter.set_hooks(hooks::Tracer::new(buffer.clone(), "main.rl", source));
+        interpreter.interpret(&compile(source).unwrap()).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buffer.0.borrow()),
+            "[trace] main.rl:1:1  fn f(a) {\n\
+             [trace] main.rl:4:1  print f(2);\n\
+             [trace]   call f(2) at main.rl:4:7\n\
+             [trace]   main.rl:2:3  return a * 2;\n\
+             [trace]   f returned 4\n"
+        );
+    }
+
+    #[test]
+    fn profiler_counts_calls_and_lines() {
+        let source = "fn fib(n) {\n  if (n < 2) return n;\n  return fib(n - 1) + fib(n - 2);\n}\n\
+                      fn main() { return fib(5); }\nprint main();";
+        let profiler = profile::Profiler::new();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_output(SharedBuffer::default());
+        interpreter.set_hooks(profiler.clone());
+        interpreter.interpret(&compile(source).unwrap()).unwrap();
+        let report = profiler.report(source);
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .map(|line| line.split_whitespace().take(2).collect())
+            .collect();
+        assert_eq!(rows[0], ["function", "calls"]);
+        assert!(rows.contains(&vec!["fib", "15"]), "{}", report);
+        assert!(rows.contains(&vec!["main", "1"]), "{}", report);
+        let lines = report.split("\n\n").nth(1).unwrap();
+        assert!(
+            lines.starts_with(
+                " line        hits\n    2          15  if (n < 2) return n;\n    3           7"
+            ),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_blocks_run_in_isolation() {
+        let source = "let items = [1, 2];\nprint \"top\";\n\
+                      test \"mutates\" { items[0] = 9; items = concat(items, [3]); print items; }\n\
+                      test \"starts fresh\" { assert_eq(items, [1, 2]); return; assert(false); }\n\
+                      test \"fails\" { print \"before\"; assert(len(items) > 2, \"too short\"); }\n\
+                      let test = 1;";
+        let program = compile(source).unwrap();
+        let mut interpreter = interpreter::Interpreter::new();
+        let mut outcomes = Vec::new();
+        testing::run_tests(&mut interpreter, &program, |outcome| outcomes.push(outcome)).unwrap();
+        let summary: Vec<(&str, bool, &str)> = outcomes
+            .iter()
+            .map(|o| (o.name.as_str(), o.passed(), o.output.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("mutates", true, "[9, 2, 3]\n"),
+                ("starts fresh", true, ""),
+                ("fails", false, "before\n"),
+            ]
+        );
+        let error = outcomes[2].result.as_ref().unwrap_err();
+        assert_eq!(error.message, "Assertion failed: too short");
+
+        // An ordinary run skips the tests.
+        let output = SharedBuffer::default();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_output(output.clone());
+        interpreter.interpret(&program).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.0.borrow()), "top\n");
+    }
+
+    #[test]
+    fn bench_blocks_are_timed() {
+        let source = "let runs = 0;\nprint \"top\";\n\
+                      bench \"counts\" { runs = runs + 1; print runs; }\n\
+                      bench \"fails\" { if (runs > 0) print 1 / 0; runs = runs + 1; }\n\
+                      fn bench(n) { return n; }";
+        let program = compile(source).unwrap();
+        let mut interpreter = interpreter::Interpreter::new();
+        let time = std::time::Duration::from_millis(5);
+        let mut measurements = Vec::new();
+        benchmark::run_benches(&mut interpreter, &program, time, |m| measurements.push(m)).unwrap();
+        let names: Vec<&str> = measurements.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["counts", "fails"]);
+        let timing = measurements[0].result.as_ref().unwrap();
+        assert!(timing.iterations >= 1);
+        assert!(timing.ns_per_op() > 0.0);
+        // "fails" starts again from the top level's `runs = 0` and fails on
+        // its second run.
+        let Value::Number(runs) = interpreter.get_global("runs").unwrap() else {
+            panic!("runs is not a number");
+        };
+        assert_eq!(runs, 1);
+        let error = measurements[1].result.as_ref().unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::DivisionByZero);
+    }
+
+    #[test]
+    fn hosts_call_script_functions() {
+        let mut interpreter = interpreter::Interpreter::new();
+        let program = compile(
+            "let calls = 0; fn handle(event, n) { calls = calls + 1; return event + \":\" + n; }",
+        )
+        .unwrap();
+        interpreter.interpret(&program).unwrap();
+        for n in 0..3 {
+            let result = interpreter.call("handle", &["tick".into(), n.into()]);
+            assert_eq!(result, Ok(Value::from(format!("tick:{}", n))));
+        }
+        assert_eq!(interpreter.get_global("calls"), Some(Value::Number(3)));
+
+        let error = interpreter.call("handle", &[]).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::ArgumentCount);
+        let error = interpreter.call("calls", &[]).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::TypeMismatch);
+        let error = interpreter.call("missing", &[]).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::UndefinedVariable);
+    }
+
+    #[test]
+    fn snapshots_restore_globals_and_closures() {
+        let mut interpreter = interpreter::Interpreter::new();
+        let setup = "let scores = {\"a\": 1}; \
+                     fn counter() { let n = 0; return fn() { n = n + 1; return n; }; } \
+                     let next = counter(); next();";
+        interpreter.interpret(&compile(setup).unwrap()).unwrap();
+        let snapshot = interpreter.snapshot();
+
+        let change =
+            compile("scores.a = 99; scores.b = 2; next(); next(); let extra = 1;").unwrap();
+        interpreter.interpret(&change).unwrap();
+        interpreter.restore(&snapshot);
+        let program = compile("[scores, next()];").unwrap();
+        let expected = Value::array([Value::map([("a".to_string(), Value::Number(1))]), 2.into()]);
+        assert_eq!(interpreter.interpret_value(&program), Ok(expected.clone()));
+        assert_eq!(interpreter.get_global("extra"), None);
+
+        // The snapshot is untouched by restoring it, even into another
+        // interpreter.
+        let mut other = interpreter::Interpreter::new();
+        other.restore(&snapshot);
+        assert_eq!(other.interpret_value(&program), Ok(expected));
+    }
+
+    #[test]
+    fn hosts_choose_the_builtins() {
+        let mut interpreter = interpreter::Interpreter::new();
+        let builtins = interpreter.builtins_mut();
+        assert!(builtins.remove("exit"));
+        assert!(builtins.rename("len", "size"));
+        builtins.register("double", |args| match args {
+            [Value::Number(n)] => Ok(Value::Number(n * 2)),
+            _ => Err(error::RuntimeError::new("double() expects a number")),
+        });
+        let program = compile("[size([1, 2, 3]), double(21)];").unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program),
+            Ok(Value::array([3.into(), 42.into()]))
+        );
+        for source in ["exit(1);", "len([]);"] {
+            let error = interpreter
+                .interpret(&compile(source).unwrap())
+                .unwrap_err();
+            assert_eq!(error.kind, error::RuntimeErrorKind::UndefinedVariable);
+        }
+
+        let mut builtins = builtins::Builtins::empty();
+        assert!(builtins.add_standard("show", "print"));
+        assert!(!builtins.add_standard("show", "no_such_builtin"));
+        interpreter.set_builtins(builtins);
+        let error = interpreter
+            .interpret(&compile("size([]);").unwrap())
+            .unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::UndefinedVariable);
+    }
+
+    #[test]
+    fn capabilities_can_be_revoked() {
+        use builtins::{Builtins, Capability};
+
+        let mut builtins = Builtins::standard();
+        assert_eq!(
+            builtins.contains("read_file"),
+            Capability::FileSystem.is_compiled_in()
+        );
+        builtins.add_standard("slurp", "read_file");
+        builtins.revoke(Capability::FileSystem);
+        assert!(!builtins.contains("read_file"));
+        assert!(!builtins.contains("slurp"));
+        assert!(builtins.contains("print"));
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_builtins(builtins);
+        let program = compile("slurp(\"/etc/passwd\");").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(error.kind, error::RuntimeErrorKind::UndefinedVariable);
+    }
+
+    #[test]
+    fn plugins_add_builtin_packs() {
+        struct Store(Rc<RefCell<std::collections::HashMap<String, Value>>>);
+
+        impl builtins::Plugin for Store {
+            fn register(&self, builtins: &mut builtins::Builtins) {
+                let rows = Rc::clone(&self.0);
+                builtins.register("store_put", move |args| match args {
+                    [Value::String(key), value] => {
+                        rows.borrow_mut().insert(key.to_string(), value.clone());
+                        Ok(Value::Nil)
+                    }
+                    _ => Err(error::RuntimeError::new(
+                        "store_put() expects a key and a value",
+                    )),
+                });
+                let rows = Rc::clone(&self.0);
+                builtins.register("store_get", move |args| match args {
+                    [Value::String(key)] => Ok(rows.borrow().get(&**key).cloned().into()),
+                    _ => Err(error::RuntimeError::new("store_get() expects a key")),
+                });
+            }
+        }
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.load_plugin(&Store(Rc::default()));
+        interpreter.load_plugin(&|builtins: &mut builtins::Builtins| {
+            builtins.register("answer", |_| Ok(Value::Number(42)))
+        });
+        let program =
+            compile("store_put(\"k\", answer()); [store_get(\"k\"), store_get(\"x\")];").unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program),
+            Ok(Value::array([42.into(), Value::Nil]))
+        );
+    }
+
+    #[test]
+    fn playground_runs_report_json() {
+        assert_eq!(
+            wasm::run_json("print 1 + 2; 6;"),
+            r#"{"output":"3\n","result":"6","errors":[]}"#
+        );
+        assert_eq!(
+            wasm::run_json("print \"hi\"; 1 / 0;"),
+            r#"{"output":"hi\n","result":null,"errors":[{"severity":"error","code":"E0104","message":"Division by zero","span":{"start":12,"end":17,"line":1,"column":13},"notes":[]}]}"#
+        );
+    }
+
+    #[test]
+    fn c_api_round_trips_values() {
+        use capi::*;
+        use std::ffi::{CStr, CString, c_void};
+
+        unsafe extern "C" fn add(
+            offset: *mut c_void,
+            args: *const *const RuilianValue,
+            argc: usize,
+        ) -> *mut RuilianValue {
+            let mut sum = unsafe { *(offset as *const i64) };
+            for i in 0..argc {
+                let mut n = 0;
+                if !unsafe { ruilian_value_as_int(*args.add(i), &mut n) } {
+                    return std::ptr::null_mut();
+                }
+                sum += n;
+            }
+            ruilian_value_int(sum)
+        }
+
+        let c = |text: &str| CString::new(text).unwrap();
+        let mut offset: i64 = 100;
+        unsafe {
+            let interpreter = ruilian_new();
+            let name = c("add");
+            ruilian_register_fn(
+                interpreter,
+                name.as_ptr(),
+                add,
+                &mut offset as *mut i64 as _,
+            );
+            let tags = ruilian_value_array();
+            let tag = ruilian_value_string(c("x").as_ptr());
+            assert_eq!(ruilian_value_push(tags, tag), 0);
+            ruilian_set_global(interpreter, c("tags").as_ptr(), tags);
+
+            let mut result = std::ptr::null_mut();
+            let source =
+                c("let total = add(1, 2); let out = {\"total\": total, \"tags\": len(tags)}; out;");
+            assert_eq!(ruilian_eval(interpreter, source.as_ptr(), &mut result), 0);
+            assert_eq!(ruilian_value_type(result), RUILIAN_MAP);
+            let total = ruilian_value_get(result, c("total").as_ptr());
+            let mut n = 0;
+            assert!(ruilian_value_as_int(total, &mut n));
+            assert_eq!(n, 103);
+            let text = ruilian_value_to_string(tags);
+            assert_eq!(CStr::from_ptr(text).to_str(), Ok("[x]"));
+
+            assert_eq!(
+                ruilian_eval(interpreter, c("add(\"no\");").as_ptr(), &mut result),
+                1
+            );
+            let message = CStr::from_ptr(ruilian_last_error(interpreter));
+            assert!(message.to_str().unwrap().contains("add() failed"));
+            assert!(ruilian_get_global(interpreter, c("missing").as_ptr()).is_null());
+
+            let source = c("let frozen = freeze([1]);");
+            assert_eq!(
+                ruilian_eval(interpreter, source.as_ptr(), std::ptr::null_mut()),
+                0
+            );
+            let frozen = ruilian_get_global(interpreter, c("frozen").as_ptr());
+            assert_eq!(ruilian_value_type(frozen), RUILIAN_ARRAY);
+            assert_eq!(ruilian_value_push(frozen, tag), 2);
+            assert_eq!(ruilian_value_push(total, tag), 1);
+            ruilian_value_free(frozen);
+
+            ruilian_string_free(text);
+            for value in [tags, tag, result, total] {
+                ruilian_value_free(value);
+            }
+            ruilian_free(interpreter);
+        }
+    }
+
+    #[test]
+    fn input_can_come_from_the_host() {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_output(io::sink());
+        interpreter.set_input(&b"first\r\nsecond"[..]);
+        let program = compile("[input(\"? \"), input(), input()];").unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program).unwrap(),
+            value!(["first", "second", nil])
+        );
+
+        let mut lines = vec!["".to_string()].into_iter();
+        interpreter.set_input_callback(move || lines.next());
+        assert_eq!(
+            interpreter.interpret_value(&program).unwrap(),
+            value!(["", nil, nil])
+        );
+    }
+
+    #[test]
+    fn parser_api_never_panics() {
+        assert_eq!(
+            parse_program("let a = 1; print a;")
+                .unwrap()
+                .statements
+                .len(),
+            2
+        );
+        assert!(matches!(
+            parse_program("let a = ;"),
+            Err(RunError::Parse(_))
+        ));
+        assert!(matches!(
+            parse_program("let a = #;"),
+            Err(RunError::Scan(_))
+        ));
+
+        assert!(
+            parser::Parser::new(Vec::new())
+                .parse()
+                .unwrap()
+                .statements
+                .is_empty()
+        );
+        let mut tokens = scanner::Scanner::new("print 1 +").scan_tokens().unwrap();
+        tokens.pop();
+        let errors = parser::Parser::new(tokens.clone()).parse().unwrap_err();
+        assert_eq!(errors[0].span, (9, 9));
+        tokens.truncate(1);
+        assert!(parser::Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn userdata_round_trips_through_scripts() {
+        use environment::{MethodTable, UserData};
+        use std::cell::Cell;
+
+        let methods = Rc::new(
+            MethodTable::new()
+                .with("add", |this, args| {
+                    let counter = this.downcast_ref::<Cell<i64>>().unwrap();
+                    if let [Value::Number(n)] = args {
+                        counter.set(counter.get() + n);
+                    }
+                    Ok(Value::Number(counter.get()))
+                })
+                .with("get", |this, _| {
+                    Ok(Value::Number(
+                        this.downcast_ref::<Cell<i64>>().unwrap().get(),
+                    ))
+                }),
+        );
+        let counter = UserData::new("Counter", Cell::new(0i64)).with_methods(methods);
+
+        let program = parse_program(
+            "let c = counter; c.add(2); counter.add(3);\n\
+             assert(c == counter); assert_eq(to_string(c), \"<Counter>\");\n\
+             counter.get();",
+        )
+        .unwrap();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_global("counter", Value::UserData(counter.clone()));
+        assert_eq!(interpreter.interpret_value(&program), Ok(Value::Number(5)));
+        assert_eq!(counter.downcast_ref::<Cell<i64>>().unwrap().get(), 5);
+
+        let program = parse_program("counter.reset();").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(error.message, "<Counter> has no method 'reset'");
+    }
+
+    #[test]
+    fn value_macro_builds_nested_values() {
+        let n = 2;
+        let built = value!({
+            "name": "Alice",
+            "scores": [1, -n, n * 3, 2.5],
+            "tags": [[], {}, nil],
+            "meta": { "ok": true, "boss": nil, "nick": None::<&str>, },
+        });
+        let expected = run_source(
+            "let v = { name: \"Alice\", scores: [1, -2, 6, 2.5], tags: [[], {}, nil],\n\
+             meta: { ok: true, boss: nil, nick: nil } }; v;",
+        )
+        .unwrap();
+        assert_eq!(built, expected);
+        assert_eq!(value!(nil), Value::Nil);
+        assert_eq!(value!("x".to_string()), Value::string("x"));
+    }
+
+    #[test]
+    fn identifiers_are_interned() {
+        let tokens = scanner::Scanner::new("let total = 1; total = total + 1;")
+            .scan_tokens()
+            .unwrap();
+        let names: Vec<&symbol::Symbol> = tokens
+            .iter()
+            .filter_map(|t| match &t.token {
+                tokens::Token::Identifier(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|name| std::rc::Rc::ptr_eq(name, names[0])));
+    }
+
+    #[test]
+    fn resolver_assigns_slots_and_reports_early_uses() {
+        use ast::StmtKind;
+
+        let parse = |source: &str| {
+            let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+            parser::Parser::new(tokens).parse().unwrap()
+        };
+        let mut program = parse("print later; let later = 1; fn f() { return missing + later; }");
+        let messages: Vec<(String, Option<&str>)> = resolver::resolve(&mut program)
+            .into_iter()
+            .map(|d| (d.message, d.code))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "'later' is used before it is defined".to_string(),
+                    Some("E0004")
+                ),
+                ("Undefined variable 'missing'".to_string(), Some("E0003")),
+            ]
+        );
+
+        let mut program = parse("let g = 0; { let a = 1; { let b = a; print b; } }");
+        assert!(resolver::resolve(&mut program).is_empty());
+        let StmtKind::Block(outer) = &program.statements[1].kind else {
+            panic!("expected a block");
+        };
+        let StmtKind::Block(inner) = &outer[1].kind else {
+            panic!("expected a block");
+        };
+        let StmtKind::Let {
+            initializer: Some(initializer),
+            ..
+        } = &inner[0].kind
+        else {
+            panic!("expected a let");
+        };
+        assert!(matches!(
+            initializer.kind,
+            ast::ExprKind::Variable {
+                slot: ast::Slot::Local { depth: 1, index: 0 },
+                ..
+            }
+        ));
+
+        let source = "let total = 0;\n\
+                      fn add(n) { let doubled = n * 2; total = total + doubled; }\n\
+                      for (i in [1, 2, 3]) { let j = i; add(j); }\n\
+                      { fn helper() { return later; } let later = 5; total = total + helper(); }\n\
+                      assert_eq(total, 17);";
+        assert_eq!(run(source), Ok(()));
+    }
+
+    #[test]
+    fn out_of_bounds_index_reports_index_and_length() {
+        let error = runtime_error("let a = [1, 2]; print a[-1];");
+        assert_eq!(
+            error.kind,
+            error::RuntimeErrorKind::IndexOutOfBounds {
+                index: -1,
+                length: 2
+            }
+        );
+        assert_eq!(error.span, Some((22, 27)));
+    }
+
+    #[test]
+    fn exit_unwinds_with_its_code() {
+        let error = runtime_error("exit(3); print 1;");
+        assert_eq!(error.exit_code(), Some(3));
+    }
+
+    #[test]
+    fn oversized_strings_are_refused() {
+        for source in [
+            "print \"ab\" * 4611686018427387904;",
+            "print repeat(\"a\", 9223372036854775807);",
+            "print pad_left(\"x\", 9223372036854775807, \" \");",
+            "print pad_right(\"x\", 9223372036854775807);",
+        ] {
+            assert!(
+                runtime_error(source).message.contains("too long"),
+                "{}",
+                source
+            );
+        }
+        let output = run_source_captured(
+            "print \"ab\" * 2; print pad_left(\"x\", 3, \"é\"); print pad_right(\"long\", 2);",
+        );
+        assert_eq!(output.output, "abab\nééx\nlong\n");
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn http_requests_cannot_inject_lines() {
+        for (source, message) in [
+            (
+                "http_get(\"http://127.0.0.1:1/a\r\nX-Evil:1\");",
+                "invalid path",
+            ),
+            ("http_get(\"http://127.0.0.1:1/a b\");", "invalid path"),
+            (
+                "http_get(\"http://127.0.0.1:1/\", {\"X-A\": \"1\r\nX-B: 2\"});",
+                "invalid value",
+            ),
+            (
+                "http_get(\"http://127.0.0.1:1/\", {\"X-A\r\nX-B\": \"2\"});",
+                "invalid header name",
+            ),
+            (
+                "http_post(\"http://127.0.0.1:1/\", \"\", {\"X:A\": \"1\"});",
+                "invalid header name",
+            ),
+        ] {
+            assert!(
+                runtime_error(source).message.contains(message),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn num_format_rejects_bad_options() {
+        use error::RuntimeErrorKind::TypeMismatch;
+        for source in [
+            "num_format(1.5, {precision: 9223372036854775807});",
+            "num_format(1.5, {precision: -1});",
+            "num_format(1.5, {precision: 101});",
+            "num_format(1.5, {style: \"money\"});",
+        ] {
+            assert_eq!(runtime_error(source).kind, TypeMismatch, "{}", source);
+        }
+        for source in ["num_format();", "num_format(1, {}, 2);"] {
+            let error = runtime_error(source);
+            assert_eq!(
+                error.kind,
+                error::RuntimeErrorKind::ArgumentCount,
+                "{}",
+                source
+            );
+        }
+        let output = run_source_captured("print num_format(1.5, {precision: 100});");
+        assert_eq!(output.output.len(), "1.".len() + 100 + 1);
+    }
+
+    #[test]
+    fn string_index_errors_name_strings() {
+        let error = runtime_error("let s = \"hello\"; print s[10];");
+        assert_eq!(error.message, "String index 10 out of bounds for length 5");
+        let error = runtime_error("let a = [1]; print a[1];");
+        assert_eq!(error.message, "Array index 1 out of bounds for length 1");
+    }
+
+    #[test]
+    fn deep_recursion_fits_in_the_stack() {
+        // A debug build with the main thread's usual 8 MiB; evaluation
+        // recursing through frames bloated by the builtins overflowed at
+        // a depth of a few dozen.
+        let source = "fn f(n) { if (n == 0) { return 0; } return f(n - 1) + 1; }\nprint f(400);";
+        let printed = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(move || run_source_captured(source).output)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(printed, "400\n");
+    }
+
+    #[test]
+    fn exit_codes_must_fit_in_a_byte() {
+        assert_eq!(runtime_error("exit(255);").exit_code(), Some(255));
+        let error = runtime_error("exit(256);");
+        assert_eq!(error.kind, error::RuntimeErrorKind::TypeMismatch);
+        assert_eq!(error.exit_code(), None);
+        assert!(runtime_error("exit(-1);").exit_code().is_none());
+    }
+
+    #[test]
+    fn tokens_record_line_and_column() {
+        let source = "let a = 1;\n  print \"é\" + a;";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let plus = &tokens[7];
+        assert_eq!(plus.token, tokens::Token::Plus);
+        assert_eq!((plus.line, plus.column), (2, 13));
+        let position = tokens::Position::from_offset(source, plus.span.0);
+        assert_eq!((position.line, position.column), (2, 13));
+    }
+
+    #[test]
+    fn parse_errors_are_returned() {
+        match run("let x = (1 + ;") {
+            Err(RunError::Parse(errors)) => assert_eq!(errors[0].span, (13, 14)),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parser_recovers_to_report_several_errors() {
+        match run("let = 1;\nfn f() { print ; let y = 2; }\nlet ok = 3;\nprint (;") {
+            Err(RunError::Parse(errors)) => {
+                let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+                assert_eq!(
+                    messages,
+                    vec![
+                        "Expected variable name after 'let'",
+                        "Expected expression, found Semicolon",
+                        "Expected expression, found Semicolon",
+                    ]
+                );
+            }
+            other => panic!("expected parse errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn diagnostics_underline_the_span() {
+        let source = "let a = 1;\nlet b = a / 0;";
+        let error = runtime_error(source);
+        let rendered = diagnostic::Diagnostic::from(&error).render(source, false);
+        assert_eq!(
+            rendered,
+            "error[E0104]: Division by zero\n --> 2:9\n  |\n2 | let b = a / 0;\n  |         ^^^^^"
+        );
+    }
+
+    #[test]
+    fn diagnostics_name_the_script() {
+        let source = "let a = 1;\nlet b = a / 0;";
+        let error = runtime_error(source);
+        let rendered =
+            diagnostic::Diagnostic::from(&error).render_in(Some("main.rl"), source, false);
+        assert!(rendered.contains(" --> main.rl:2:9\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn diagnostics_encode_as_json() {
+        let source = "let s = \"a\";\nfn f() { return s - 1; }\nf();";
+        let error = runtime_error(source);
+        assert_eq!(
+            diagnostic::Diagnostic::from(&error).to_json(source),
+            "{\"severity\":\"error\",\"code\":\"E0102\",\
+             \"message\":\"Cannot subtract Number(1) from String(\\\"a\\\")\",\
+             \"span\":{\"start\":29,\"end\":34,\"line\":2,\"column\":17},\
+             \"notes\":[{\"message\":\"in f() called\",\
+             \"span\":{\"start\":38,\"end\":41,\"line\":3,\"column\":1}}]}"
+        );
+    }
+
+    #[test]
+    fn warning_pass_flags_unused_and_unreachable_code() {
+        let source = "fn f(a, _b) { let x = 1; return 2; print a; }\nlet y = f(1, 2);";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+        let messages: Vec<String> = analysis::warnings(&program)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Unused variable 'x'",
+                "Unreachable code",
+                "Unused variable 'y'"
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_parameters_are_underlined_alone() {
+        let source = "let g = fn(a, b) { return a; };\nfn h(c) {}\nprint g(1, 2) + h(3);";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+        let warnings: Vec<(String, Option<ast::Span>)> = analysis::warnings(&program)
+            .into_iter()
+            .map(|d| (d.message, d.span))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                ("Unused parameter 'b'".to_string(), Some((14, 15))),
+                ("Unused parameter 'c'".to_string(), Some((37, 38))),
+            ]
+        );
+    }
+
+    #[test]
+    fn linter_rules_can_be_toggled() {
+        let source =
+            "fn f() {}\nlet x = 1;\nif (1 < 2) { let x = f == f; }\nwhile (true) {}\nlet x = 2;";
+        let mut linter = lint::Linter::new();
+        let messages = |linter: &lint::Linter| -> Vec<String> {
+            lint(source, linter)
+                .unwrap()
+                .into_iter()
+                .map(|d| d.message)
+                .collect()
+        };
+        assert_eq!(
+            messages(&linter),
+            vec![
+                "Condition is always the same value",
+                "Functions never compare equal, even to themselves",
+                "Empty block"
+            ]
+        );
+
+        linter
+            .enable(lint::Rule::ShadowedName)
+            .enable(lint::Rule::Redeclaration)
+            .disable(lint::Rule::from_name("empty-block").unwrap());
+        assert_eq!(
+            messages(&linter),
+            vec![
+                "Condition is always the same value",
+                "'x' shadows a binding from an enclosing scope",
+                "Functions never compare equal, even to themselves",
+                "'x' is already declared in this scope"
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_see_outer_bindings_until_shadowed() {
+        let source = "let x = 1; { fn f() { return x; } print f(); let x = 2; print f(); }";
+        assert_eq!(run_source_captured(source).output, "1\n2\n");
+
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let unresolved = parser::Parser::new(tokens).parse().unwrap();
+        let buffer = SharedBuffer::default();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_output(buffer.clone());
+        interpreter.interpret(&unresolved).unwrap();
+        assert_eq!(*buffer.0.borrow(), b"1\n2\n");
+
+        assert!(analysis::check(source, &lint::Linter::new()).is_empty());
+    }
+
+    #[test]
+    fn check_reports_every_pass() {
+        let linter = lint::Linter::new();
+        let codes = |source: &str| -> Vec<&str> {
+            analysis::check(source, &linter)
+                .into_iter()
+                .filter_map(|d| d.code)
+                .collect()
+        };
+        assert_eq!(
+            codes("fn f(a) {}\nprint f == f;\nprint \"s\" - 1;\nprint y;"),
+            vec!["W0001", "W0105", "E0201", "E0003"]
+        );
+        assert_eq!(codes("let a = 1 @;\nprint ;"), vec!["E0001", "E0002"]);
+        assert!(codes("let a = 1;\nprint a;").is_empty());
+    }
+
+    #[test]
+    fn programs_dump_as_a_tree_or_json() {
+        let source = "let x = -1;\nfn f(a, b) { return a + \"s\"; }";
+        let program = parse_program(source).unwrap();
+        assert_eq!(
+            dump::tree(&program, source),
+            "Program\n  statements:\n    Let name=x @1:1\n      \
+             initializer: Unary operator=- @1:9\n        right: Number value=1 @1:10\n    \
+             Function name=f params=(a, b) @2:1\n      body:\n        Return @2:14\n          \
+             value: Binary operator=+ @2:21\n            left: Variable name=a @2:21\n            \
+             right: String value=\"s\" @2:25\n"
+        );
+        assert_eq!(
+            dump::json(&parse_program("f(true);").unwrap(), "f(true);"),
+            "{\"kind\":\"Program\",\"statements\":[{\"kind\":\"Expr\",\
+             \"span\":{\"start\":0,\"end\":8,\"line\":1,\"column\":1},\
+             \"expression\":{\"kind\":\"Call\",\
+             \"span\":{\"start\":0,\"end\":7,\"line\":1,\"column\":1},\
+             \"callee\":{\"kind\":\"Variable\",\
+             \"span\":{\"start\":0,\"end\":1,\"line\":1,\"column\":1},\"name\":\"f\"},\
+             \"arguments\":[{\"kind\":\"Boolean\",\
+             \"span\":{\"start\":2,\"end\":6,\"line\":1,\"column\":3},\"value\":true}]}}]}"
+        );
+    }
+
+    #[test]
+    fn formatter_prints_canonical_layout() {
+        let source = "fn f(a){if(a){return -a[0]*(1+2);}else return {};}\n\n\n\
+                      let m = {\n  \"k\": [1,2]};\n({}).x = f(1) - (2 - 3);";
+        let expected = "fn f(a) {\n    if (a) {\n        return -a[0] * (1 + 2);\n    \
+                        } else return {};\n}\n\nlet m = {\n    \"k\": [1, 2]\n};\n\
+                        ({}.x = f(1) - (2 - 3));\n";
+        assert_eq!(format::format(source).unwrap(), expected);
+        assert_eq!(format::format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn comments_survive_formatting() {
+        let mut scanner = scanner::Scanner::new("let a = 1; // one  \nprint a;").with_comments();
+        scanner.scan_tokens().unwrap();
+        assert_eq!(
+            scanner.take_comments(),
+            [tokens::Comment {
+                text: "// one".to_string(),
+                span: (11, 17),
+            }]
+        );
+
+        let source = "// head\n\nfn f() { // opens\n  return [\n    1, // one\n    2\n  ];\n  \
+                      // closing\n}\nf(1, // moved\n  2); // tail\n";
+        assert_eq!(
+            format::format(source).unwrap(),
+            "// head\n\nfn f() {\n    // opens\n    return [\n        1, // one\n        2\n    \
+             ];\n    // closing\n}\nf(1, 2);\n// moved\n// tail\n"
+        );
+
+        let source = "fn f() { return 1; } // about f\n\
+                      if (x > 0) { print \"yes\"; } else { print \"no\"; } // tail\n";
+        assert_eq!(
+            format::format(source).unwrap(),
+            "fn f() {\n    return 1;\n} // about f\nif (x > 0) {\n    print \"yes\";\n} else {\n    \
+             print \"no\";\n} // tail\n"
+        );
+    }
+
+    #[test]
+    fn type_checker_flags_certain_failures() {
+        let source = "fn f(a, b) { return a - b; }\nlet s = \"x\";\nlet n = 3;\n\
+                      print s - 1; n(); f(1); print s * 2; let t = s; t = 4; print t - 1;";
+        let tokens = scanner::Scanner::new(source).scan_tokens().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+        let messages: Vec<String> = typecheck::check(&program)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Cannot subtract a string and a number",
+                "Cannot call a number",
+                "'f' takes 2 argument(s) but 1 were given"
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_errors_carry_byte_spans() {
+        match run("let é = 1; let s = \"open") {
+            Err(RunError::Scan(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert_eq!(errors[0].span, (4, 6));
+                assert_eq!((errors[0].line, errors[0].column), (1, 5));
+                assert_eq!(errors[1].message, "Unterminated string");
+                assert_eq!(errors[1].span, (20, 25));
+            }
+            other => panic!("expected scan errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn conversions_between_numbers_and_strings() {
+        let output = run_source_captured(
+            "print to_number(\"42\"); print to_number(\" 2.5 \"); print to_number(\"abc\"); \
+             print to_number(true); print to_string(42) + \"!\"; print to_string([1, \"a\"]);",
+        );
+        assert_eq!(output.output, "42\n2.5\nnil\n1\n42!\n[1, a]\n");
+        let error = runtime_error("to_number([1]);");
+        assert_eq!(error.message, "to_number() cannot convert [1]");
+    }
+
+    #[test]
+    fn seeded_random_numbers_repeat() {
+        let output = run_source_captured(
+            "random_seed(7); let a = [random(), random_int(1, 6), random_int(1, 6)]; \
+             random_seed(7); let b = [random(), random_int(1, 6), random_int(1, 6)]; \
+             print a == b; let r = random(); print r >= 0 and r < 1; print random_int(3, 3);",
+        );
+        assert_eq!(output.output, "true\ntrue\n3\n");
+        let error = runtime_error("random_int(5, 1);");
+        assert_eq!(
+            error.message,
+            "random_int() lower bound 5 exceeds upper bound 1"
+        );
+        let error = runtime_error("random_seed(\"x\");");
+        assert_eq!(error.message, "random_seed() expects an integer seed");
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn files_are_written_appended_and_read() {
+        let path = std::env::temp_dir().join(format!("ruilian-files-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let output = run_source_captured(&format!(
+            "write_file(\"{0}\", \"one \"); append_file(\"{0}\", \"two\"); print read_file(\"{0}\");",
+            path
+        ));
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(output.output, "one two\n");
+
+        let error = runtime_error(&format!("read_file(\"{}\");", path));
+        assert_eq!(error.kind, error::RuntimeErrorKind::Io);
+        assert!(error.is_catchable());
+    }
+
+    #[test]
+    fn times_format_and_parse_as_utc() {
+        let output = run_source_captured(
+            "print now() > 1600000000; print time_format(1700000000, \"%Y-%m-%dT%H:%M:%S\"); \
+             print time_parse(\"2023-11-14 22:13:20\", \"%Y-%m-%d %H:%M:%S\"); \
+             print time_parse(\"1970-01-02\", \"%Y-%m-%d\"); print time_parse(\"soon\", \"%Y\");",
+        );
+        assert_eq!(
+            output.output,
+            "true\n2023-11-14T22:13:20\n1700000000\n86400\nnil\n"
+        );
+    }
+
+    #[test]
+    fn clock_measures_sleeps() {
+        let output = run_source_captured(
+            "let start = clock(); sleep(20); let took = clock() - start; print took >= 0.02 and took < 5;",
+        );
+        assert_eq!(output.output, "true\n");
+        let error = runtime_error("sleep(-1);");
+        assert_eq!(
+            error.message,
+            "sleep() expects a non-negative number of milliseconds, got -1"
+        );
+    }
+
+    #[test]
+    fn assertions_raise_catchable_errors() {
+        let output = run_source_captured(
+            "assert(1 < 2, \"fine\"); assert_eq([1, {a: 2}], [1, {a: 2}]); \
+             print pcall(fn() { assert(1 > 2, \"math broke\"); }); \
+             print pcall(fn() { assert_eq(1, 2); });",
+        );
+        assert_eq!(
+            output.output,
+            "{ok: false, error: Assertion failed: math broke}\n\
+             {ok: false, error: Assertion failed: left: 1, right: 2}\n"
+        );
+        let error = runtime_error("assert(false);");
+        assert_eq!(error.kind, error::RuntimeErrorKind::AssertionFailed);
+    }
+
+    #[cfg(feature = "process")]
+    #[test]
+    fn environment_variables_are_read() {
+        // Cargo sets this for the test binary; setting variables is left
+        // untested since other test threads read the environment.
+        let output = run_source_captured(
+            "print getenv(\"CARGO_PKG_NAME\"); print getenv(\"RUILIAN_SURELY_UNSET\");",
+        );
+        assert_eq!(output.output, "ruilian\nnil\n");
+        let error = runtime_error("setenv(\"A=B\", 1);");
+        assert_eq!(error.message, "setenv() got an invalid variable name 'A=B'");
+    }
+
+    #[test]
+    fn scripts_read_their_arguments() {
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_args(vec!["a".to_string(), "b c".to_string()]);
+        let program = compile("[len(args()), args()[1]];").unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program),
+            Ok(value!([2, "b c"]))
+        );
+        assert_eq!(run_source_captured("print args();").output, "[]\n");
+    }
+
+    #[test]
+    fn format_fills_positional_and_named_placeholders() {
+        let output = run_source_captured(
+            "print format(\"x={} y={}\", 1, \"two\"); \
+             print format(\"{name} is {age}\", {name: \"Al\", age: 3}); \
+             print format(\"{{}} {}\", [1, 2]);",
+        );
+        assert_eq!(output.output, "x=1 y=two\nAl is 3\n{} [1, 2]\n");
+        for (source, message) in [
+            ("format(\"{} {}\", 1);", "format() is missing argument 1"),
+            (
+                "format(\"{missing}\", {a: 1});",
+                "format() has no value for '{missing}'",
+            ),
+            (
+                "format(\"{\", 1);",
+                "format() has an unclosed '{' in template",
+            ),
+        ] {
+            assert_eq!(runtime_error(source).message, message);
+        }
+        assert_eq!(
+            runtime_error("format();").kind,
+            error::RuntimeErrorKind::ArgumentCount
+        );
+    }
+
+    #[test]
+    fn range_counts_up_and_down() {
+        let output = run_source_captured(
+            "print range(3); print range(2, 5); print range(10, 0, -3); print range(5, 2); \
+             let total = 0; for (i in range(1, 4)) { total = total + i; } print total;",
+        );
+        assert_eq!(
+            output.output,
+            "[0, 1, 2]\n[2, 3, 4]\n[10, 7, 4, 1]\n[]\n6\n"
+        );
+        assert_eq!(
+            runtime_error("range(0, 5, 0);").message,
+            "range() step must not be zero"
+        );
+        assert_eq!(
+            runtime_error("range(1.5);").message,
+            "range() expects an integer, got 1.5"
+        );
+    }
+
+    #[test]
+    fn zip_pairs_up_to_the_shorter_array() {
+        let output = run_source_captured(
+            "print zip([1, 2, 3], [\"a\", \"b\"]); print zip([], [1]); \
+             for (pair in zip([1, 2], [3, 4])) { print pair[0] * pair[1]; }",
+        );
+        assert_eq!(output.output, "[[1, a], [2, b]]\n[]\n3\n8\n");
+        assert_eq!(
+            runtime_error("zip(1, [1]);").message,
+            "zip() expects an array, got 1"
+        );
+    }
+
+    #[test]
+    fn reverse_unique_and_flatten_build_new_arrays() {
+        let output = run_source_captured(
+            "let a = [1, 2, 3]; print reverse(a); print a; print reverse(\"abc\"); \
+             print unique([1, 1, [1], [1], 2, 1]); \
+             print flatten([1, [2, [3, [4]]]]); print flatten_deep([1, [2, [3, [4]]]]);",
+        );
+        assert_eq!(
+            output.output,
+            "[3, 2, 1]\n[1, 2, 3]\ncba\n[1, [1], 2]\n[1, 2, [3, [4]]]\n[1, 2, 3, 4]\n"
+        );
+        assert_eq!(
+            run_source_captured("print len(unique([1, \"1\"]));").output,
+            "2\n"
+        );
+    }
+
+    #[test]
+    fn slice_and_concat_take_arrays_apart_and_together() {
+        let output = run_source_captured(
+            "print slice([1, 2, 3, 4], 1, 3); print slice([1, 2, 3, 4], -2); \
+             print slice([1, 2, 3], 2, 1); print concat([1], [2, 3], [], [4]); print concat();",
+        );
+        assert_eq!(output.output, "[2, 3]\n[3, 4]\n[]\n[1, 2, 3, 4]\n[]\n");
+        assert_eq!(
+            runtime_error("concat([1], 2);").message,
+            "concat() expects an array, got 2"
+        );
+    }
+
+    #[test]
+    fn index_of_and_contains_compare_by_value() {
+        let output = run_source_captured(
+            "print index_of([1, 2, [3]], [3]); print index_of([1, 2], 5); \
+             print contains([1, {a: 1}], {a: 1}); print contains([1], \"1\");",
+        );
+        assert_eq!(output.output, "2\n-1\ntrue\nfalse\n");
+        assert_eq!(
+            runtime_error("index_of(1, 1);").message,
+            "index_of() expects an array, got 1"
+        );
+    }
+
+    #[test]
+    fn sort_by_and_group_by_call_the_key_function() {
+        let output = run_source_captured(
+            "print sort_by([\"ccc\", \"a\", \"bb\"], fn(s) { return len(s); }); \
+             let stable = sort_by([[2, 0], [1, 1], [2, 2]], fn(x) { return x[0]; }); print stable; \
+             let groups = group_by([\"ab\", \"c\", \"de\"], fn(s) { return len(s); }); \
+             print groups[\"1\"]; print groups[\"2\"];",
+        );
+        assert_eq!(
+            output.output,
+            "[a, bb, ccc]\n[[1, 1], [2, 0], [2, 2]]\n[c]\n[ab, de]\n"
+        );
+        assert_eq!(
+            runtime_error("sort_by([1, \"a\"], fn(x) { return x; });").message,
+            "Cannot compare sort keys a and 1"
+        );
+        assert_eq!(
+            runtime_error("group_by([1], 2);").message,
+            "group_by() expects a function, got 2"
+        );
+    }
+
+    #[test]
+    fn merge_overrides_and_deep_merge_recurses() {
+        let output = run_source_captured(
+            "let base = {a: 1, b: {c: 1, d: 2}}; let over = {b: {c: 5}, e: 3}; \
+             print merge(base, over) == {a: 1, b: {c: 5}, e: 3}; \
+             print deep_merge(base, over) == {a: 1, b: {c: 5, d: 2}, e: 3}; \
+             print base == {a: 1, b: {c: 1, d: 2}};",
+        );
+        assert_eq!(output.output, "true\ntrue\ntrue\n");
+        assert_eq!(
+            runtime_error("merge({}, 1);").message,
+            "merge() expects a map, got 1"
+        );
+    }
+
+    #[test]
+    fn deep_copies_are_independent_and_deep_equal() {
+        let output = run_source_captured(
+            "let x = {l: [1, 2]}; let y = deep_copy(x); y.l[0] = 9; print x.l[0]; print y.l[0]; \
+             print deep_equal([1, {a: [2]}], [1, {a: [2]}]); print deep_equal({a: 1}, {a: 1, b: 2}); \
+             let f = fn() { return 1; }; let g = fn() { return 2; }; \
+             print [deep_equal(f, g), same(f, g), deep_equal([f], deep_copy([f]))];",
+        );
+        assert_eq!(output.output, "1\n9\ntrue\nfalse\n[false, false, true]\n");
+    }
+
+    #[test]
+    fn chars_and_bytes_split_strings() {
+        let output = run_source_captured(
+            "print chars(\"héllo\"); print bytes(\"hé\"); print byte_len(\"hé\"); print len(\"hé\");",
+        );
+        assert_eq!(output.output, "[h, é, l, l, o]\n[104, 195, 169]\n3\n2\n");
+        assert_eq!(
+            runtime_error("chars(1);").message,
+            "chars() expects a string, got 1"
+        );
+    }
+
+    #[test]
+    fn strings_repeat_with_star_or_repeat() {
+        let output = run_source_captured(
+            "print \"-\" * 5; print 3 * \"ab\"; print repeat(\"é\", 2); print len(repeat(\"xy\", 0));",
+        );
+        assert_eq!(output.output, "-----\nababab\néé\n0\n");
+        assert_eq!(
+            runtime_error("let n = -1; print \"a\" * n;").message,
+            "Cannot repeat a string -1 times"
+        );
+        assert_eq!(
+            runtime_error("repeat(\"a\", 1.5);").message,
+            "repeat() expects an integer count, got 1.5"
+        );
+    }
+
+    #[test]
+    fn pad_aligns_text_to_a_width() {
+        let output = run_source_captured(
+            "print pad_left(\"7\", 3, \"0\"); print pad_right(\"ab\", 4) + \"|\"; \
+             print pad_left(42, 4); print pad_left(\"long\", 2);",
+        );
+        assert_eq!(output.output, "007\nab  |\n  42\nlong\n");
+        assert_eq!(
+            runtime_error("pad_left(\"a\", -1);").message,
+            "pad_left() expects a non-negative width, got -1"
+        );
+        assert_eq!(
+            runtime_error("pad_right(\"a\", 3, \"ab\");").message,
+            "pad_right() fill must be a single character"
+        );
+    }
+
+    #[test]
+    fn csv_round_trips_quoted_fields() {
+        // Script strings have no escapes, so the quoted text comes from the
+        // host.
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_global(
+            "text",
+            Value::string("name,note\r\nal,\"b, c\"\n\"x\",\"say \"\"hi\"\"\"\n"),
+        );
+        let program = compile("let rows = csv_parse(text); [rows, csv_stringify(rows)];").unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program),
+            Ok(value!([
+                [["name", "note"], ["al", "b, c"], ["x", "say \"hi\""]],
+                "name,note\nal,\"b, c\"\nx,\"say \"\"hi\"\"\"\n"
+            ]))
+        );
+        let program = compile("csv_parse(text, true)[1].note;").unwrap();
+        assert_eq!(
+            interpreter.interpret_value(&program),
+            Ok(Value::string("say \"hi\""))
+        );
+
+        interpreter.set_global("text", Value::string("a,\"b"));
+        let program = compile("csv_parse(text);").unwrap();
+        let error = interpreter.interpret(&program).unwrap_err();
+        assert!(
+            error.message.starts_with("csv_parse(): "),
+            "{}",
+            error.message
+        );
+        assert_eq!(
+            runtime_error("csv_stringify([1]);").message,
+            "csv_stringify() rows must all be arrays or all be maps"
+        );
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn http_post_sends_headers_and_reads_the_response() {
+        use std::io::{BufRead, BufReader, Read};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            let mut body = [0; 5];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+            (head, body)
+        });
+        let output = run_source_captured(&format!(
+            "let r = http_post(\"http://127.0.0.1:{}/items\", \"hello\", {{\"X-Token\": \"abc\"}}); \
+             print r.status; print r.body;",
+            port
+        ));
+        let (head, body) = server.join().unwrap();
+        assert_eq!(output.output, "201\nok\n");
+        assert!(head.starts_with("POST /items HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("\r\nX-Token: abc\r\n"), "{}", head);
+        assert_eq!(&body, b"hello");
+    }
+
+    #[cfg(all(feature = "process", unix))]
+    #[test]
+    fn exec_captures_output_and_exit_code() {
+        let output = run_source_captured(
+            "let r = exec(\"sh\", [\"-c\", \"printf out; printf err >&2; exit 3\"]); \
+             print [r.stdout, r.stderr, r.code];",
+        );
+        assert_eq!(output.output, "[out, err, 3]\n");
+        let error = runtime_error("exec(\"/nonexistent/cmd\");");
+        assert_eq!(error.kind, error::RuntimeErrorKind::Io);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn directories_are_made_listed_and_removed() {
+        let dir = std::env::temp_dir().join(format!("ruilian-dirs-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let output = run_source_captured(&format!(
+            "let d = \"{}\"; mkdir(d + \"/sub\"); write_file(d + \"/sub/f.txt\", \"x\"); \
+             print [exists(d), is_dir(d), is_dir(d + \"/sub/f.txt\"), exists(d + \"/nope\")]; \
+             print list_dir(d + \"/sub\"); print pcall(fn() {{ remove(d + \"/sub\"); }}).ok; \
+             remove(d + \"/sub/f.txt\"); remove(d + \"/sub\"); remove(d); print exists(d);",
+            dir
+        ));
+        assert_eq!(
+            output.output,
+            "[true, true, false, false]\n[f.txt]\nfalse\nfalse\n"
+        );
+    }
+
+    #[test]
+    fn pcall_turns_errors_into_results() {
+        let output = run_source_captured(
+            "fn div(a, b) { return a / b; } print pcall(div, 6, 3); print pcall(div, 1, 0); \
+             print pcall(div, 1); let r = pcall(fn() { assert(false, \"nope\"); }); print r.ok;",
+        );
+        assert_eq!(
+            output.output,
+            "{ok: true, value: 2}\n{ok: false, error: Division by zero}\n\
+             {ok: false, error: Expected 2 arguments but got 1}\nfalse\n"
+        );
+        // Exits are not errors to catch.
+        assert_eq!(
+            runtime_error("pcall(fn() { exit(2); }); print 1;").exit_code(),
+            Some(2)
+        );
+        assert_eq!(
+            runtime_error("pcall(1);").message,
+            "pcall() expects a function, got 1"
+        );
+    }
+
+    #[test]
+    fn globals_and_locals_reflect_the_scopes() {
+        let output = run_source_captured(
+            "let g = 1; fn f(a) { let b = 2; return [locals(), globals()]; } let seen = f(5); \
+             print [seen[0].a, seen[0].b, seen[0].g]; print [len(seen[1]), seen[1].g];",
+        );
+        assert_eq!(output.output, "[5, 2, 1]\n[2, 1]\n");
+    }
+
+    #[test]
+    fn functions_describe_themselves() {
+        let output = run_source_captured(
+            "fn add(x, y) { return x + y; } print [arity(add), fn_name(add), fn_params(add)]; \
+             let id = fn(q) { return q; }; print [arity(id), fn_name(id)];",
+        );
+        assert_eq!(output.output, "[2, add, [x, y]]\n[1, anonymous]\n");
+        assert_eq!(
+            runtime_error("arity(1);").message,
+            "arity() expects a function, got 1"
+        );
+    }
+
+    #[test]
+    fn frozen_values_refuse_assignment() {
+        let output = run_source_captured(
+            "let c = freeze({a: [1], n: 2}); print [is_frozen(c), is_frozen({}), c.a[0] + c.n]; \
+             let copy = deep_copy(c); copy.n = 9; print [copy.n, is_frozen(copy), c.n];",
+        );
+        assert_eq!(output.output, "[true, false, 3]\n[9, false, 2]\n");
+        for (source, message) in [
+            (
+                "let c = freeze({n: 2}); c.n = 3;",
+                "Cannot assign field 'n' on a frozen value",
+            ),
+            (
+                "let a = freeze([1, 2]); a[0] = 5;",
+                "Cannot assign into a frozen value",
+            ),
+        ] {
+            let error = runtime_error(source);
+            assert_eq!(error.kind, error::RuntimeErrorKind::FrozenValue);
+            assert_eq!(error.message, message);
+        }
+    }
+
+    #[test]
+    fn num_format_controls_precision_style_and_grouping() {
+        let output = run_source_captured(
+            "print num_format(1234567.891, {precision: 2, thousands: true}); \
+             print num_format(-1234567, {thousands: \"_\"}); print num_format(999, {thousands: true}); \
+             print num_format(3, {precision: 2}); print num_format(1.25); \
+             print num_format(0.000123, {style: \"sci\"});",
+        );
+        assert_eq!(
+            output.output,
+            "1,234,567.89\n-1_234_567\n999\n3.00\n1.25\n1.23e-4\n"
+        );
+    }
+
+    #[test]
+    fn pretty_indents_nested_values_with_sorted_keys() {
+        let output = run_source_captured(
+            "print pretty({b: [1, {c: 2}], a: \"x\"}); print pretty([]); print_pretty({k: 1});",
+        );
+        assert_eq!(
+            output.output,
+            "{\n  a: \"x\",\n  b: [\n    1,\n    {\n      c: 2\n    }\n  ]\n}\n[]\n{\n  k: 1\n}\n"
+        );
+    }
+
+    #[test]
+    fn redeclarations_replace_bindings_unless_flagged() {
+        let source = "let x = 1; fn f() { let x = 2; return x; } let x = f() + 1; print x;";
+        assert_eq!(run_source_captured(source).output, "3\n");
+        assert_eq!(lint(source, &lint::Linter::new()), Ok(Vec::new()));
+
+        let mut linter = lint::Linter::new();
+        linter
+            .enable(lint::Rule::ShadowedName)
+            .enable(lint::Rule::Redeclaration);
+        let found: Vec<(String, Option<ast::Span>)> = lint(source, &linter)
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.message, d.span))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "'x' shadows a binding from an enclosing scope".to_string(),
+                    Some((20, 30))
+                ),
+                (
+                    "'x' is already declared in this scope".to_string(),
+                    Some((43, 59))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_reach_any_mix_of_indexes_and_fields() {
+        let output = run_source_captured(
+            "let m = {rows: [{v: 1}, {v: 2}]}; let k = \"rows\"; \
+             m[k][1].v = m[k][0].v + 10; print m.rows[1].v; \
+             let a = [[1], [2]]; let b = a; b[0][0] = 9; print [a[0][0], b[0][0]];",
+        );
+        assert_eq!(output.output, "11\n[1, 9]\n");
+        assert_eq!(
+            runtime_error("let m = {rows: []}; m.rows[5].v = 1;").message,
+            "Array index 5 out of bounds for length 0"
+        );
+        assert_eq!(
+            runtime_error("let m = {}; m.nope.v = 1;").message,
+            "Cannot assign to field 'v' on non-map value"
+        );
+    }
+
+    #[test]
+    fn script_functions_shadow_builtins() {
+        let path = std::env::temp_dir().join(format!("ruilian-shadow-{}.txt", std::process::id()));
+        std::fs::write(&path, "keep me").unwrap();
+        let output = run_source_captured(&format!(
+            "fn reverse(s) {{ return \"mine\"; }} print reverse(\"ab\"); \
+             fn remove(p) {{ return \"kept \" + len(p); }} print remove(\"{}\") != nil; \
+             fn f() {{ let len = fn(x) {{ return 99; }}; return len(\"a\"); }} print f(); \
+             print len(\"ab\");",
+            path.to_str().unwrap()
+        ));
+        let kept = path.exists();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(output.output, "mine\ntrue\n99\n2\n");
+        assert!(kept);
+    }
+
+    #[cfg(feature = "net")]
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn http_host_header_names_a_non_default_port() {
+        let head = |url: &str| {
+            let url = http::parse_url(url).unwrap();
+            http::request_head("GET", &url, &[], None)
+        };
+        assert_eq!(
+            head("http://localhost:8080/a?b=1"),
+            "GET /a?b=1 HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\
+             User-Agent: ruilian\r\n\r\n"
+        );
+        assert!(head("http://example.com:80").contains("\r\nHost: example.com\r\n"));
+        assert!(head("http://example.com").starts_with("GET / HTTP/1.1\r\nHost: example.com\r\n"));
     }
 }
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{BinOp, Expr, ExprKind, Program, Span, Stmt, StmtKind};
+use crate::diagnostic::Diagnostic;
+use crate::symbol::Symbol;
+
+/// A single lint check that can be switched on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// `let` of a name already bound in an enclosing scope.
+    ShadowedName,
+    /// `let` of a name already bound in the same scope, which silently
+    /// replaces the earlier binding.
+    Redeclaration,
+    /// A `{ }` block with no statements.
+    EmptyBlock,
+    /// An `if`/`while` condition that is a constant expression.
+    ConstantCondition,
+    /// `==`/`!=` against a function, which never compares equal.
+    FunctionEquality,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::ShadowedName,
+        Rule::Redeclaration,
+        Rule::EmptyBlock,
+        Rule::ConstantCondition,
+        Rule::FunctionEquality,
+    ];
+
+    /// The kebab-case name used on the command line and in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::ShadowedName => "shadowed-name",
+            Rule::Redeclaration => "redeclaration",
+            Rule::EmptyBlock => "empty-block",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::FunctionEquality => "function-equality",
+        }
+    }
+
+    /// The diagnostic code attached to warnings from this rule.
+    pub fn code(self) -> &'static str {
+        match self {
+            Rule::ShadowedName => "W0101",
+            Rule::Redeclaration => "W0102",
+            Rule::EmptyBlock => "W0103",
+            Rule::ConstantCondition => "W0104",
+            Rule::FunctionEquality => "W0105",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+
+    /// Whether `Linter::new` turns the rule on. Shadowing and redeclaration
+    /// are common on purpose, so those two are opt-in.
+    pub fn enabled_by_default(self) -> bool {
+        !matches!(self, Rule::ShadowedName | Rule::Redeclaration)
+    }
+}
+
+/// Runs the enabled lint rules over a parsed program. Starts with the rules
+/// that are `enabled_by_default`.
+#[derive(Debug, Clone)]
+pub struct Linter {
+    enabled: HashSet<Rule>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Linter {
+            enabled: Rule::ALL
+                .into_iter()
+                .filter(|rule| rule.enabled_by_default())
+                .collect(),
+        }
+    }
+
+    pub fn enable(&mut self, rule: Rule) -> &mut Self {
+        self.enabled.insert(rule);
+        self
+    }
+
+    pub fn disable(&mut self, rule: Rule) -> &mut Self {
+        self.enabled.remove(&rule);
+        self
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        self.enabled.contains(&rule)
+    }
+
+    pub fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let mut visitor = LintVisitor {
+            linter: self,
+            scopes: vec![HashMap::new()],
+            diagnostics: Vec::new(),
+        };
+        visitor.statements(&program.statements);
+        visitor.diagnostics.sort_by_key(|d| d.span);
+        visitor.diagnostics
+    }
+}
+
+struct LintVisitor<'l> {
+    linter: &'l Linter,
+    /// Names bound in each open scope, mapped to whether they are functions.
+    scopes: Vec<HashMap<String, bool>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl LintVisitor<'_> {
+    fn report(&mut self, rule: Rule, message: String, span: Span) {
+        if self.linter.is_enabled(rule) {
+            self.diagnostics.push(
+                Diagnostic::warning(message, Some(span))
+                    .with_code(rule.code())
+                    .with_note(format!("lint rule '{}'", rule.name()), None),
+            );
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<bool> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn bind(&mut self, name: &str, is_function: bool) {
+        let scope = self.scopes.last_mut().expect("no open scope");
+        scope.insert(name.to_string(), is_function);
+    }
+
+    fn in_scope(&mut self, bindings: &[Symbol], statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        for name in bindings {
+            self.bind(name, false);
+        }
+        self.statements(statements);
+        self.scopes.pop();
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn check_shadowing(&mut self, name: &str, span: Span) {
+        if self
+            .scopes
+            .last()
+            .is_some_and(|scope| scope.contains_key(name))
+        {
+            self.report(
+                Rule::Redeclaration,
+                format!("'{}' is already declared in this scope", name),
+                span,
+            );
+            return;
+        }
+        let outer = self.scopes[..self.scopes.len() - 1]
+            .iter()
+            .any(|scope| scope.contains_key(name));
+        if outer {
+            self.report(
+                Rule::ShadowedName,
+                format!("'{}' shadows a binding from an enclosing scope", name),
+                span,
+            );
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Expr, allow_true: bool) {
+        let is_literal_true = matches!(condition.kind, ExprKind::Boolean(true));
+        if is_constant(condition) && !(allow_true && is_literal_true) {
+            self.report(
+                Rule::ConstantCondition,
+                "Condition is always the same value".to_string(),
+                condition.span,
+            );
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.expression(expr),
+            StmtKind::Let { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.check_shadowing(name, stmt.span);
+                self.bind(name, false);
+            }
+            StmtKind::Block(statements) => {
+                if statements.is_empty() {
+                    self.report(Rule::EmptyBlock, "Empty block".to_string(), stmt.span);
+                }
+                self.in_scope(&[], statements);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.check_condition(condition, false);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expression(condition);
+                // `while (true)` is the usual way to write a loop that exits
+                // through `return`, so it is not worth a warning.
+                self.check_condition(condition, true);
+                self.statement(body);
+            }
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.scopes.push(HashMap::new());
+                self.check_shadowing(variable, stmt.span);
+                self.bind(variable, false);
+                self.statement(body);
+                self.scopes.pop();
+            }
+            StmtKind::Function {
+                name, params, body, ..
+            } => {
+                self.check_shadowing(name, stmt.span);
+                self.bind(name, true);
+                self.in_scope(params, body);
+            }
+            StmtKind::Return { value } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StmtKind::Test { body, .. } | StmtKind::Bench { body, .. } => self.in_scope(&[], body),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(_)
+            | ExprKind::Float(_)
+            | ExprKind::String(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Nil
+            | ExprKind::Variable { .. } => {}
+            ExprKind::Assign { value, .. } => self.expression(value),
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                if matches!(operator, BinOp::EqualEqual | BinOp::BangEqual)
+                    && (self.is_function(left) || self.is_function(right))
+                {
+                    self.report(
+                        Rule::FunctionEquality,
+                        "Functions never compare equal, even to themselves".to_string(),
+                        expr.span,
+                    );
+                }
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Unary { right, .. } => self.expression(right),
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Array(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            ExprKind::Map(pairs) => {
+                for (_, value) in pairs {
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::IndexAssign {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            ExprKind::Dot { object, .. } => self.expression(object),
+            ExprKind::DotAssign { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            ExprKind::Lambda { params, body, .. } => self.in_scope(params, body),
+        }
+    }
+
+    fn is_function(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Variable { name, .. } => self.lookup(name) == Some(true),
+            ExprKind::Lambda { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Whether an expression is built only from literals, so its value can never
+/// change between runs.
+fn is_constant(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Number(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Boolean(_)
+        | ExprKind::Nil => true,
+        ExprKind::Array(elements) => elements.iter().all(is_constant),
+        ExprKind::Map(pairs) => pairs.iter().all(|(_, value)| is_constant(value)),
+        ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+            is_constant(left) && is_constant(right)
+        }
+        ExprKind::Unary { right, .. } => is_constant(right),
+        _ => false,
+    }
+}
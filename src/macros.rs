@@ -0,0 +1,66 @@
+/// Builds a `Value` from JSON-like syntax, for Rust code that hands nested
+/// values to scripts or checks what they returned:
+///
+/// ```
+/// use ruilian::{environment::Value, value};
+///
+/// let person = value!({ "name": "Alice", "scores": [1, 2, 3], "boss": nil });
+/// assert_eq!(person, Value::map([
+///     ("name".to_string(), Value::string("Alice")),
+///     ("scores".to_string(), Value::array([1, 2, 3].map(Value::Number))),
+///     ("boss".to_string(), Value::Nil),
+/// ]));
+/// ```
+///
+/// `nil`, arrays and maps nest freely; map keys are string literals. Any
+/// other element is a Rust expression converted with `Value::from`, so
+/// `value!([n + 1, name.as_str(), 2.5, false])` works too.
+#[macro_export]
+macro_rules! value {
+    // Array elements, munched one at a time into `[$($done,)*]`.
+    (@array [$($done:expr,)*]) => {
+        $crate::environment::Value::array([$($done,)*])
+    };
+    (@array [$($done:expr,)*] nil $(, $($rest:tt)*)?) => {
+        $crate::value!(@array [$($done,)* $crate::environment::Value::Nil,] $($($rest)*)?)
+    };
+    (@array [$($done:expr,)*] [$($inner:tt)*] $(, $($rest:tt)*)?) => {
+        $crate::value!(@array [$($done,)* $crate::value!([$($inner)*]),] $($($rest)*)?)
+    };
+    (@array [$($done:expr,)*] {$($inner:tt)*} $(, $($rest:tt)*)?) => {
+        $crate::value!(@array [$($done,)* $crate::value!({$($inner)*}),] $($($rest)*)?)
+    };
+    (@array [$($done:expr,)*] $next:expr $(, $($rest:tt)*)?) => {
+        $crate::value!(@array [$($done,)* $crate::environment::Value::from($next),] $($($rest)*)?)
+    };
+
+    // Map entries, munched the same way.
+    (@map [$($done:expr,)*]) => {
+        $crate::environment::Value::map([$($done,)*])
+    };
+    (@map [$($done:expr,)*] $key:literal : nil $(, $($rest:tt)*)?) => {
+        $crate::value!(@map [$($done,)* ($key.to_string(), $crate::environment::Value::Nil),] $($($rest)*)?)
+    };
+    (@map [$($done:expr,)*] $key:literal : [$($inner:tt)*] $(, $($rest:tt)*)?) => {
+        $crate::value!(@map [$($done,)* ($key.to_string(), $crate::value!([$($inner)*])),] $($($rest)*)?)
+    };
+    (@map [$($done:expr,)*] $key:literal : {$($inner:tt)*} $(, $($rest:tt)*)?) => {
+        $crate::value!(@map [$($done,)* ($key.to_string(), $crate::value!({$($inner)*})),] $($($rest)*)?)
+    };
+    (@map [$($done:expr,)*] $key:literal : $value:expr $(, $($rest:tt)*)?) => {
+        $crate::value!(@map [$($done,)* ($key.to_string(), $crate::environment::Value::from($value)),] $($($rest)*)?)
+    };
+
+    (nil) => {
+        $crate::environment::Value::Nil
+    };
+    ([$($elements:tt)*]) => {
+        $crate::value!(@array [] $($elements)*)
+    };
+    ({$($entries:tt)*}) => {
+        $crate::value!(@map [] $($entries)*)
+    };
+    ($other:expr) => {
+        $crate::environment::Value::from($other)
+    };
+}
@@ -1,220 +1,698 @@
-mod ast;
-mod environment;
-mod interpreter;
-mod parser;
-mod scanner;
-mod tokens;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::time::Duration;
 
-use interpreter::Interpreter;
-use parser::Parser;
-use scanner::Scanner;
+use ruilian::ast::Program;
+use ruilian::diagnostic::{Diagnostic, Severity};
+use ruilian::environment::Value;
+use ruilian::hooks::Tracer;
+use ruilian::interpreter::{Interpreter, value_to_string};
+use ruilian::lint::{Linter, Rule};
+use ruilian::parser::Parser;
+use ruilian::profile::Profiler;
+use ruilian::scanner::Scanner;
+use ruilian::tokens::Token;
+use ruilian::{analysis, benchmark, dump, fold, format, resolver, testing};
 
-fn main() {
-    let source = r#"
-        print "=== Array Tests ===";
-        
-        // Basic array creation
-        let numbers = [1, 2, 3, 4, 5];
-        print numbers;
-        
-        // Array of strings
-        let names = ["Alice", "Bob", "Charlie"];
-        print names;
-        
-        // Mixed array (using integer instead of float)
-        let mixed = [1, "hello", true, 314];
-        print mixed;
-        
-        // Array indexing
-        print "First number: " + numbers[0];
-        print "Second name: " + names[1];
-        
-        // Array length
-        print "Numbers length: " + len(numbers);
-        print "Names length: " + len(names);
-        
-        // Iterating over array with while loop
-        let i = 0;
-        while (i < len(numbers)) {
-            print "numbers[" + i + "] = " + numbers[i];
-            i = i + 1;
-        }
-        
-        // Array in function
-        fn sum_array(arr) {
-            let total = 0;
-            let i = 0;
-            while (i < len(arr)) {
-                total = total + arr[i];
-                i = i + 1;
+const USAGE: &str = "\
+Usage: ruilian [OPTIONS] [run] FILE [ARGS]...
+       ruilian [OPTIONS] COMMAND ...
+
+Commands:
+    run [--watch] FILE [ARGS]...
+                          Run a script (the default), or rerun it on
+                          every change with --watch
+    repl                  Read and run statements interactively
+    check PATH...         Report problems without running the scripts
+    tokens FILE           Print the tokens of a script
+    ast FILE              Print the syntax tree of a script
+    fmt [--check] PATH... Rewrite scripts in canonical layout, or with
+                          --check only list those that are not
+    test PATH...          Run the test blocks of scripts
+    bench PATH...         Time the bench blocks of scripts in ns/op
+
+Options:
+    --strict              Make reading a missing map key or field an error
+    --trace               Print each statement, call and return as it runs
+    --profile             After a run, report time per function and the
+                          most executed lines
+    --color WHEN          Color diagnostics: auto, always or never
+    --json                Print diagnostics, and ast's tree, as JSON
+    -h, --help            Print this message
+
+check also takes --allow RULE and --warn RULE to turn lint rules off or on.";
+
+/// The command line was malformed.
+const EXIT_USAGE: i32 = 2;
+/// The script could not be scanned, parsed or resolved, so none of it ran.
+const EXIT_COMPILE_ERROR: i32 = 65;
+/// The script file could not be read.
+const EXIT_NO_INPUT: i32 = 66;
+/// The script stopped on a runtime error it did not catch.
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Color {
+    /// Only when stderr is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// The flags every command accepts.
+#[derive(Debug, Default)]
+struct Options {
+    strict: bool,
+    trace: bool,
+    /// Report where a run spent its time; only `run` profiles.
+    profile: bool,
+    color: Color,
+    json: bool,
+}
+
+impl Options {
+    /// Applies `arg` if it is one of the shared flags, taking its value from
+    /// `rest` when it has one. Returns false for anything else.
+    fn apply(&mut self, arg: &str, rest: &mut impl Iterator<Item = String>) -> bool {
+        match arg {
+            "--strict" => self.strict = true,
+            "--trace" => self.trace = true,
+            "--profile" => self.profile = true,
+            "--json" => self.json = true,
+            "--color" => {
+                self.color = match rest.next().as_deref() {
+                    Some("auto") => Color::Auto,
+                    Some("always") => Color::Always,
+                    Some("never") => Color::Never,
+                    other => usage_error(&format!(
+                        "--color expects auto, always or never, got '{}'",
+                        other.unwrap_or_default()
+                    )),
+                }
             }
-            return total;
-        }
-        
-        print "Sum of numbers: " + sum_array(numbers);
-        
-        // Empty array
-        let empty = [];
-        print "Empty array: " + empty;
-        print "Empty array length: " + len(empty);
-        
-        // Array truthiness
-        if (empty) {
-            print "Empty array is truthy";
-        } else {
-            print "Empty array is falsy";
-        }
-        
-        if (numbers) {
-            print "Non-empty array is truthy";
-        }
-        
-        print "=== Array tests complete ===";
-
-
-        print "=== For Loop Tests ===";
-        
-        // Basic array iteration
-        let numbers = [1, 2, 3, 4, 5];
-        for (num in numbers) {
-            print "Number: " + num;
-        }
-        
-        // String iteration
-        let word = "hello";
-        for (char in word) {
-            print "Character: " + char;
-        }
-        
-        // Nested loops
-        let matrix = [[1, 2], [3, 4], [5, 6]];
-        for (row in matrix) {
-            print "Row: " + row;
-            for (element in row) {
-                print "  Element: " + element;
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
             }
+            _ => return false,
+        }
+        true
+    }
+
+    fn color(&self) -> bool {
+        match self.color {
+            Color::Auto => std::io::stderr().is_terminal(),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+
+    fn interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_strict(self.strict);
+        interpreter
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("ruilian: {}\n\n{}", message, USAGE);
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Reads shared flags from `args` up to the first other argument, which is
+/// returned. Any other flag is a usage error.
+fn next_operand(args: &mut impl Iterator<Item = String>, options: &mut Options) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if options.apply(&arg, args) {
+            continue;
+        }
+        if arg.starts_with('-') {
+            usage_error(&format!("unknown option '{}'", arg));
+        }
+        return Some(arg);
+    }
+    None
+}
+
+fn read_source(path: &str) -> String {
+    match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", path, error);
+            std::process::exit(EXIT_NO_INPUT);
+        }
+    }
+}
+
+/// Prints a diagnostic to stderr, either rendered against the source or, for
+/// `--json`, as one JSON object per line. `path` names the script in the
+/// rendered location.
+fn emit(diagnostic: &Diagnostic, path: &str, source: &str, options: &Options) {
+    if options.json {
+        eprintln!("{}", diagnostic.to_json(source));
+    } else {
+        eprintln!(
+            "{}",
+            diagnostic.render_in(Some(path), source, options.color())
+        );
+    }
+}
+
+/// Scans and parses `source`, returning every scan error or else every
+/// parse error if it fails.
+fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let tokens = Scanner::new(source)
+        .scan_tokens()
+        .map_err(|errors| errors.iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+    Parser::new(tokens)
+        .parse()
+        .map_err(|errors| errors.iter().map(Diagnostic::from).collect())
+}
+
+/// `ruilian run [--watch] FILE [ARGS]...` runs a script, passing it the
+/// arguments after its path. With `--watch` it keeps running the script
+/// again whenever the file changes, until interrupted.
+fn run(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let mut watch = false;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if options.apply(&arg, &mut args) {
+            continue;
+        }
+        match arg.as_str() {
+            "--watch" => watch = true,
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            _ => {
+                path = Some(arg);
+                break;
+            }
+        }
+    }
+    let Some(path) = path else {
+        usage_error("run expects a script to run");
+    };
+    let script_args: Vec<String> = args.collect();
+    if watch {
+        run_watched(&path, &script_args, &options);
+    }
+    std::process::exit(run_once(&path, &script_args, &options));
+}
+
+/// Runs the script at `path` once, reporting any errors, and returns the
+/// exit code for the run.
+fn run_once(path: &str, script_args: &[String], options: &Options) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", path, error);
+            return EXIT_NO_INPUT;
+        }
+    };
+    let source = source.as_str();
+
+    let report = |diagnostic: Diagnostic| emit(&diagnostic, path, source, options);
+
+    let mut program = match parse(source) {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            diagnostics.into_iter().for_each(report);
+            return EXIT_COMPILE_ERROR;
         }
-        
-        // Sum with for loop
-        let numbers = [10, 20, 30, 40, 50];
-        let sum = 0;
-        for (n in numbers) {
-            sum = sum + n;
-        }
-        print "Sum: " + sum;
-        
-        // Empty array
-        let empty = [];
-        for (item in empty) {
-            print "This won't print";
-        }
-        print "Empty loop completed";
-        
-        print "=== For Loop Tests Complete ===";
-
-
-         print "=== Hash Map Tests ===";
-    
-    // Basic map creation
-    let person = { "name": "Alice", "age": 30 };
-    print person;
-    print "Name: " + person["name"];
-    print "Age: " + person["age"];
-    
-    // Map mutation
-    person["age"] = 31;
-    print "Updated age: " + person["age"];
-    
-    // Add new key
-    person["city"] = "New York";
-    print "City: " + person["city"];
-    print "Full person: " + person;
-    
-    // Nested structures
-    let company = {
-        "name": "Tech Corp",
-        "employees": [
-            { "name": "Alice", "role": "Engineer" },
-            { "name": "Bob", "role": "Designer" }
-        ]
     };
-    print "Company: " + company;
-    print "First employee: " + company["employees"][0];
-    
-    // Iterate over map keys (we'll need to add keys() method later)
-    print "Keys in person:";
-    // TODO: Add map.keys() method
-    
-    // Default value for missing keys
-    print "Missing key: " + person["nonexistent"];
-    
-    // Empty map
-    let empty = {};
-    print "Empty map: " + empty;
-    
-    print "=== Hash Map Tests Complete ===";
-
-    
-    print "=== Dot Notation Tests ===";
-    
-    // Basic dot notation
-    let person = { "name": "Alice", "age": 30 };
-    print "Name via bracket: " + person["name"];
-    print "Name via dot: " + person.name;
-    print "Age via dot: " + person.age;
-    
-    // Dot assignment
-    person.age = 31;
-    print "Updated age: " + person.age;
-    
-    // Add new field with dot notation
-    person.city = "New York";
-    print "City: " + person.city;
-    
-    // Nested structures with dot notation
-    let company = {
-        "name": "Tech Corp",
-        "address": {
-            "street": "123 Main St",
-            "city": "Boston"
+
+    let errors = resolver::resolve(&mut program);
+    if !errors.is_empty() {
+        errors.into_iter().for_each(report);
+        return EXIT_COMPILE_ERROR;
+    }
+
+    for warning in analysis::warnings(&program) {
+        report(warning);
+    }
+    fold::fold_constants(&mut program);
+
+    let mut interpreter = options.interpreter();
+    interpreter.set_args(script_args.to_vec());
+    let profiler = Profiler::new();
+    let tracer = || Tracer::new(std::io::stderr(), path, source);
+    match (options.trace, options.profile) {
+        (true, true) => interpreter.set_hooks((tracer(), profiler.clone())),
+        (true, false) => interpreter.set_hooks(tracer()),
+        (false, true) => interpreter.set_hooks(profiler.clone()),
+        (false, false) => {}
+    }
+    let code = match interpreter.interpret(&program) {
+        Ok(()) => 0,
+        Err(error) => match error.exit_code() {
+            Some(code) => code,
+            None => {
+                report((&error).into());
+                EXIT_RUNTIME_ERROR
+            }
         },
-        "employees": [
-            { "name": "Alice", "role": "Engineer" },
-            { "name": "Bob", "role": "Designer" }
-        ]
     };
-    
-    print "Company: " + company.name;
-    print "Address: " + company.address.street + ", " + company.address.city;
-    print "First employee: " + company.employees[0].name;
-    
-    // Mix bracket and dot notation
-    print "First employee role: " + company.employees[0]["role"];
-    
-    // Chained dot notation
-    company.employees[0].role = "Senior Engineer";
-    print "Updated role: " + company.employees[0].role;
-    
-    // Default value for missing fields
-    print "Missing field: " + person.nonexistent;  // Should print 0
-    
-    // Functions as methods (later)
-    // person.greet = fn() { return "Hello, " + this.name; };
-    // print person.greet();
-    
-    print "=== Dot Notation Tests Complete ===";
-    "#;
-
-    println!("=== Running Tests ===\n");
-
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens();
-
-    let mut parser = Parser::new(tokens);
-    let program = parser.parse();
-
-    let mut interpreter = Interpreter::new();
-    interpreter.interpret(&program);
-
-    println!("\n=== Tests Complete ===");
+    if options.profile {
+        eprint!("\n{}", profiler.report(source));
+    }
+    code
+}
+
+/// How often `--watch` looks at the script's modification time.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `run --watch`: runs the script, then polls its modification time and
+/// clears the screen and runs it again each time the file changes. Scripts
+/// cannot import other files, so the script itself is all there is to
+/// watch. An `exit()` in the script ends the run, not the watch.
+fn run_watched(path: &str, script_args: &[String], options: &Options) -> ! {
+    let modified = || {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    loop {
+        let seen = modified();
+        print!("\x1b[2J\x1b[H");
+        std::io::stdout().flush().ok();
+        let code = run_once(path, script_args, options);
+        eprintln!(
+            "[watch] exited with {}; waiting for {} to change",
+            code, path
+        );
+        while modified() == seen {
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// Whether `source` stops inside an unclosed bracket, so the REPL should
+/// read another line before running it.
+fn is_unfinished(source: &str) -> bool {
+    let Ok(tokens) = Scanner::new(source).scan_tokens() else {
+        return false;
+    };
+    let depth = tokens.iter().fold(0i64, |depth, token| match token.token {
+        Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth + 1,
+        Token::RightParen | Token::RightBracket | Token::RightBrace => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+}
+
+/// `ruilian repl` reads statements from stdin and runs each as it is
+/// entered, all in one global scope, printing the value of a final
+/// expression statement.
+fn repl(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    if let Some(arg) = next_operand(&mut args, &mut options) {
+        usage_error(&format!("repl takes no operands, got '{}'", arg));
+    }
+    let mut interpreter = options.interpreter();
+    let stdin = std::io::stdin();
+    let mut entry = String::new();
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush().ok();
+        let mut line = String::new();
+        if matches!(stdin.lock().read_line(&mut line), Ok(0) | Err(_)) {
+            println!();
+            std::process::exit(0);
+        }
+        entry.push_str(&line);
+        if is_unfinished(&entry) {
+            continue;
+        }
+        let source = std::mem::take(&mut entry);
+        if source.trim().is_empty() {
+            continue;
+        }
+
+        let report = |diagnostic: Diagnostic| emit(&diagnostic, "<repl>", &source, &options);
+        let mut program = match parse(&source) {
+            Ok(program) => program,
+            Err(diagnostics) => {
+                diagnostics.into_iter().for_each(report);
+                continue;
+            }
+        };
+        // Earlier entries define globals the resolver cannot see, so
+        // undefined names are left to fail when they are reached.
+        resolver::resolve(&mut program);
+        fold::fold_constants(&mut program);
+        if options.trace {
+            interpreter.set_hooks(Tracer::new(std::io::stderr(), "<repl>", source.as_str()));
+        }
+        match interpreter.interpret_value(&program) {
+            Ok(Value::Nil) => {}
+            Ok(value) => println!("{}", value_to_string(&value)),
+            Err(error) => {
+                if let Some(code) = error.exit_code() {
+                    std::process::exit(code);
+                }
+                report((&error).into());
+            }
+        }
+    }
+}
+
+/// `ruilian tokens FILE` prints the tokens the scanner produces for a
+/// script, one per line: where it starts, the token, and the source text it
+/// was scanned from.
+fn tokens(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let Some(path) = next_operand(&mut args, &mut options) else {
+        usage_error("tokens expects a script to scan");
+    };
+    if let Some(arg) = next_operand(&mut args, &mut options) {
+        usage_error(&format!("tokens expects one script, got '{}'", arg));
+    }
+    let source = read_source(&path);
+    let tokens = match Scanner::new(&source).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => {
+            for error in &errors {
+                emit(&error.into(), &path, &source, &options);
+            }
+            std::process::exit(EXIT_COMPILE_ERROR);
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    for token in &tokens {
+        let position = format!("{}:{}", token.line, token.column);
+        let kind = format!("{:?}", token.token);
+        // Kept to one line, as a string literal can span several.
+        let lexeme = source[token.span.0..token.span.1].replace('\n', "\\n");
+        let line = format!("{:<8} {:<24} {}", position, kind, lexeme);
+        let _ = writeln!(stdout, "{}", line.trim_end());
+    }
+    std::process::exit(0);
+}
+
+/// `ruilian ast [--json] FILE` prints the syntax tree a script parses
+/// into, as an indented tree or, with `--json`, as one JSON object.
+fn ast(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let Some(path) = next_operand(&mut args, &mut options) else {
+        usage_error("ast expects a script to parse");
+    };
+    if let Some(arg) = next_operand(&mut args, &mut options) {
+        usage_error(&format!("ast expects one script, got '{}'", arg));
+    }
+    let source = read_source(&path);
+    let program = match parse(&source) {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                emit(diagnostic, &path, &source, &options);
+            }
+            std::process::exit(EXIT_COMPILE_ERROR);
+        }
+    };
+    if options.json {
+        println!("{}", dump::json(&program, &source));
+    } else {
+        print!("{}", dump::tree(&program, &source));
+    }
+    std::process::exit(0);
+}
+
+/// `ruilian fmt [--check] PATH...` rewrites each script, or each `.rl`
+/// file under a directory, in canonical layout. With `--check` it changes
+/// nothing, naming the files that are not formatted and exiting with 1 if
+/// there are any.
+fn fmt(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let mut check = false;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        if options.apply(&arg, &mut args) {
+            continue;
+        }
+        match arg.as_str() {
+            "--check" => check = true,
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        usage_error("fmt expects a script to format");
+    }
+
+    let (mut failed, mut unformatted) = (false, false);
+    for path in paths.iter().flat_map(|path| scripts_at(path)) {
+        let source = read_source(&path);
+        let formatted = match format::format(&source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                for diagnostic in Diagnostic::from_run_error(&error) {
+                    emit(&diagnostic, &path, &source, &options);
+                }
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            unformatted = true;
+        } else if let Err(error) = std::fs::write(&path, formatted) {
+            eprintln!("Could not write {}: {}", path, error);
+            failed = true;
+        }
+    }
+    std::process::exit(if failed {
+        EXIT_COMPILE_ERROR
+    } else if unformatted {
+        1
+    } else {
+        0
+    });
+}
+
+/// The scripts `path` names: the file itself, or every `.rl` file under
+/// the directory, in path order.
+fn scripts_at(path: &str) -> Vec<String> {
+    fn walk(dir: &Path, scripts: &mut Vec<String>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                walk(&path, scripts);
+            } else if path.extension().is_some_and(|extension| extension == "rl") {
+                scripts.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    if !Path::new(path).is_dir() {
+        return vec![path.to_string()];
+    }
+    let mut scripts = Vec::new();
+    walk(Path::new(path), &mut scripts);
+    scripts.sort();
+    scripts
+}
+
+/// `ruilian check [--allow RULE | --warn RULE]... PATH...` reports
+/// everything `analysis::check` finds in each script, or each `.rl` file
+/// under a directory, without running anything. Exits with
+/// `EXIT_COMPILE_ERROR` if there were errors and with 1 if there were only
+/// warnings, so editors and CI can tell the two apart.
+fn check(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let mut linter = Linter::new();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        if options.apply(&arg, &mut args) {
+            continue;
+        }
+        match arg.as_str() {
+            "--allow" | "--warn" => {
+                let name = args.next().unwrap_or_default();
+                let Some(rule) = Rule::from_name(&name) else {
+                    let known: Vec<&str> = Rule::ALL.iter().map(|rule| rule.name()).collect();
+                    usage_error(&format!(
+                        "unknown lint rule '{}' (expected one of: {})",
+                        name,
+                        known.join(", ")
+                    ));
+                };
+                if arg == "--allow" {
+                    linter.disable(rule);
+                } else {
+                    linter.enable(rule);
+                }
+            }
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        usage_error("check expects a script to check");
+    }
+
+    let (mut errors, mut warnings) = (0, 0);
+    for path in paths.iter().flat_map(|path| scripts_at(path)) {
+        let source = read_source(&path);
+        for diagnostic in analysis::check(&source, &linter) {
+            match diagnostic.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+            }
+            emit(&diagnostic, &path, &source, &options);
+        }
+    }
+    std::process::exit(match (errors, warnings) {
+        (0, 0) => 0,
+        (0, _) => 1,
+        _ => EXIT_COMPILE_ERROR,
+    });
+}
+
+/// Parses and resolves a script for `test` or `bench`, reporting any errors
+/// and returning `None` if there were some.
+fn load(path: &str, source: &str, options: &Options) -> Option<Program> {
+    let report = |diagnostic: Diagnostic| emit(&diagnostic, path, source, options);
+    let mut program = match parse(source) {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            diagnostics.into_iter().for_each(report);
+            return None;
+        }
+    };
+    let errors = resolver::resolve(&mut program);
+    if !errors.is_empty() {
+        errors.into_iter().for_each(report);
+        return None;
+    }
+    fold::fold_constants(&mut program);
+    Some(program)
+}
+
+/// `ruilian test PATH...` runs the `test` blocks of each script, or of each
+/// `.rl` file under a directory, printing a line per test and a summary.
+/// A failing test's output and error follow its line. Exits with
+/// `EXIT_COMPILE_ERROR` if a script could not be compiled and with 1 if a
+/// test failed or a script's top level did.
+fn test(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let mut paths = Vec::new();
+    while let Some(path) = next_operand(&mut args, &mut options) {
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        usage_error("test expects a script to test");
+    }
+
+    let (mut passed, mut failed, mut broken) = (0, 0, false);
+    for path in paths.iter().flat_map(|path| scripts_at(path)) {
+        let source = read_source(&path);
+        let report = |diagnostic: Diagnostic| emit(&diagnostic, &path, &source, &options);
+        let Some(program) = load(&path, &source, &options) else {
+            broken = true;
+            continue;
+        };
+
+        let mut interpreter = options.interpreter();
+        let ran = testing::run_tests(&mut interpreter, &program, |outcome| {
+            let status = if outcome.passed() { "ok" } else { "FAILED" };
+            println!("{}: {} ... {}", path, outcome.name, status);
+            if let Err(error) = &outcome.result {
+                print!("{}", outcome.output);
+                std::io::stdout().flush().ok();
+                report(error.into());
+                failed += 1;
+            } else {
+                passed += 1;
+            }
+        });
+        if let Err(error) = ran {
+            println!("{}: top level ... FAILED", path);
+            report((&error).into());
+            failed += 1;
+        }
+    }
+    println!("\n{} passed, {} failed", passed, failed);
+    std::process::exit(if broken {
+        EXIT_COMPILE_ERROR
+    } else if failed > 0 {
+        1
+    } else {
+        0
+    });
+}
+
+/// `ruilian bench PATH...` times the `bench` blocks of each script, or of
+/// each `.rl` file under a directory, printing the time per run of each
+/// body and how many runs it was averaged over. Exits with
+/// `EXIT_COMPILE_ERROR` if a script could not be compiled and with 1 if a
+/// benchmark or a script's top level failed.
+fn bench(mut args: impl Iterator<Item = String>, mut options: Options) -> ! {
+    let mut paths = Vec::new();
+    while let Some(path) = next_operand(&mut args, &mut options) {
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        usage_error("bench expects a script to time");
+    }
+
+    let (mut failed, mut broken) = (false, false);
+    for path in paths.iter().flat_map(|path| scripts_at(path)) {
+        let source = read_source(&path);
+        let report = |diagnostic: Diagnostic| emit(&diagnostic, &path, &source, &options);
+        let Some(program) = load(&path, &source, &options) else {
+            broken = true;
+            continue;
+        };
+
+        let mut interpreter = options.interpreter();
+        let time = benchmark::DEFAULT_TIME;
+        let ran = benchmark::run_benches(&mut interpreter, &program, time, |measurement| {
+            let label = format!("{}: {}", path, measurement.name);
+            match &measurement.result {
+                Ok(timing) => println!(
+                    "{:<40} {:>14.1} ns/op {:>12} runs",
+                    label,
+                    timing.ns_per_op(),
+                    timing.iterations
+                ),
+                Err(error) => {
+                    println!("{:<40} {:>14}", label, "FAILED");
+                    std::io::stdout().flush().ok();
+                    report(error.into());
+                    failed = true;
+                }
+            }
+        });
+        if let Err(error) = ran {
+            println!("{:<40} {:>14}", format!("{}: top level", path), "FAILED");
+            report((&error).into());
+            failed = true;
+        }
+    }
+    std::process::exit(if broken {
+        EXIT_COMPILE_ERROR
+    } else if failed {
+        1
+    } else {
+        0
+    });
+}
+
+fn main() {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    let Some(command) = next_operand(&mut args, &mut options) else {
+        usage_error("expected a command or a script to run");
+    };
+    match command.as_str() {
+        "run" => run(args, options),
+        "repl" => repl(args, options),
+        "check" => check(args, options),
+        "tokens" => tokens(args, options),
+        "ast" => ast(args, options),
+        "fmt" => fmt(args, options),
+        "test" => test(args, options),
+        "bench" => bench(args, options),
+        _ => run(std::iter::once(command).chain(args), options),
+    }
 }
@@ -0,0 +1,169 @@
+//! Approximate accounting of the memory a script holds, for
+//! `Interpreter::set_memory_limit`.
+//!
+//! Counting every byte as it is allocated and freed would need hooks in
+//! `Rc` itself, so the meter works in two steps. Each expression that can
+//! build a value charges the value's own size (a string's bytes, an array's
+//! slots, a map's entries), which is cheap and only ever overestimates what
+//! the script keeps. Once the charges could have pushed usage past the
+//! limit, the interpreter measures what is actually reachable from its
+//! scopes and the calls in progress, counting storage shared between values
+//! once, and fails only if that is over the limit.
+//!
+//! Temporaries held only by the interpreter's own stack (half-built
+//! argument lists and the like) are not seen by a measurement, and neither
+//! are environments kept alive only by a closure cycle the collector has
+//! not freed yet, so the limit bounds what a script holds on to rather than
+//! its peak allocation.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+use crate::environment::{Environment, Value};
+
+/// Charges between measurements are allowed to reach at least this fraction
+/// of the limit, so a script sitting just under it is not measured after
+/// every allocation.
+const MIN_ALLOWANCE_DIVISOR: usize = 16;
+
+type EnvRef = Rc<RefCell<Environment>>;
+
+#[derive(Debug, Default)]
+pub(crate) struct Meter {
+    limit: Option<usize>,
+    /// Bytes charged since the last measurement.
+    charged: usize,
+    /// How much may be charged before the next measurement is due.
+    allowance: usize,
+}
+
+impl Meter {
+    pub(crate) fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        self.charged = 0;
+        // Measure on the first charge, since the host may have set globals.
+        self.allowance = 0;
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Records `bytes` of new allocation, returning whether usage should be
+    /// measured now.
+    pub(crate) fn charge(&mut self, bytes: usize) -> bool {
+        if self.limit.is_none() {
+            return false;
+        }
+        self.charged = self.charged.saturating_add(bytes);
+        self.charged > self.allowance
+    }
+
+    /// Whether `bytes` more could be charged without a measurement falling
+    /// due.
+    pub(crate) fn allows(&self, bytes: usize) -> bool {
+        self.charged.saturating_add(bytes) <= self.allowance
+    }
+
+    /// Takes a measurement of `used` bytes into account, returning whether
+    /// it is within the limit.
+    pub(crate) fn measured(&mut self, used: usize) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        self.charged = 0;
+        self.allowance = limit
+            .saturating_sub(used)
+            .max(limit / MIN_ALLOWANCE_DIVISOR);
+        used <= limit
+    }
+}
+
+/// What creating `value` allocated, not counting the values inside it,
+/// which were charged when they were created.
+pub(crate) fn shallow_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.len(),
+        Value::Array(items) => items.len() * size_of::<Value>(),
+        Value::Map(entries) => entries.len() * ENTRY_SIZE,
+        Value::BigInt(n) => n.heap_size(),
+        Value::Frozen(inner) => size_of::<Value>() + shallow_size(inner),
+        _ => 0,
+    }
+}
+
+/// A map entry: the key's `String` and the value, with the table's own
+/// overhead folded into a round number.
+const ENTRY_SIZE: usize = size_of::<String>() + size_of::<Value>() + 8;
+
+/// Bytes reachable from `roots` and `extra`: every scope in their chains,
+/// every value those hold, and the closures of functions among them.
+/// Storage shared by several values is counted once.
+pub(crate) fn measure(roots: &[&EnvRef], extra: Option<&Value>) -> usize {
+    let mut meter = Measure::default();
+    for root in roots {
+        meter.environment(root);
+    }
+    if let Some(value) = extra {
+        meter.pending.push(value.clone());
+    }
+    meter.drain();
+    meter.total
+}
+
+#[derive(Default)]
+struct Measure {
+    /// Addresses of shared allocations already counted.
+    seen: HashSet<*const ()>,
+    /// Values still to visit. An explicit stack, so deeply nested arrays
+    /// cannot overflow the host's.
+    pending: Vec<Value>,
+    total: usize,
+}
+
+impl Measure {
+    fn first_visit<T: ?Sized>(&mut self, rc: &Rc<T>) -> bool {
+        self.seen.insert(Rc::as_ptr(rc) as *const ())
+    }
+
+    fn environment(&mut self, env: &EnvRef) {
+        let mut next = Some(Rc::clone(env));
+        while let Some(env) = next {
+            if !self.first_visit(&env) {
+                return;
+            }
+            let env = env.borrow();
+            let values = env.slot_values();
+            self.total +=
+                size_of::<Environment>() + values.len() * (size_of::<Value>() + ENTRY_SIZE);
+            self.pending.extend(values.iter().cloned());
+            next = env.enclosing().cloned();
+        }
+    }
+
+    fn drain(&mut self) {
+        while let Some(value) = self.pending.pop() {
+            match &value {
+                Value::String(s) if self.first_visit(s) => self.total += s.len(),
+                Value::Array(items) if self.first_visit(items) => {
+                    self.total += shallow_size(&value);
+                    self.pending.extend(items.iter().cloned());
+                }
+                Value::Map(entries) if self.first_visit(entries) => {
+                    self.total += entries.keys().map(String::len).sum::<usize>();
+                    self.total += shallow_size(&value);
+                    self.pending.extend(entries.values().cloned());
+                }
+                Value::Frozen(inner) => {
+                    self.total += size_of::<Value>();
+                    self.pending.push((**inner).clone());
+                }
+                Value::Function(function) => self.environment(&function.closure),
+                Value::BigInt(_) => self.total += shallow_size(&value),
+                _ => {}
+            }
+        }
+    }
+}
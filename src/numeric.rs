@@ -0,0 +1,209 @@
+//! Arithmetic rules for script numbers, shared by the operators, `==`, and
+//! the builtins that compare numbers.
+//!
+//! | operands      | `+` `-` `*`  | `/`                              | `<` `==` … |
+//! |---------------|--------------|----------------------------------|------------|
+//! | int, int      | int          | int, truncated toward zero       | exact      |
+//! | int, float    | float        | float                            | as floats  |
+//! | float, float  | float        | float                            | as floats  |
+//!
+//! An int mixed with a float is converted to the nearest float first, so
+//! `1 == 1.0` holds and `7 / 2` is `3` while `7 / 2.0` is `3.5`. Dividing by
+//! zero (int or float) is a runtime error rather than infinity.
+//!
+//! Int results that do not fit in 64 bits are handled according to the
+//! interpreter's [`Overflow`] mode; float arithmetic follows IEEE 754. Under
+//! `Overflow::Promote` such results become big integers, which behave like
+//! ints in every row of the table and turn back into ints whenever the value
+//! fits again.
+//!
+//! The operators also accept a few non-numeric operands, handled by the
+//! interpreter before these rules apply: `+` with a string on either side
+//! converts the other side with `to_string` and concatenates (ints print as
+//! `3`, floats always keep a fractional part, as in `3.0`); `+` on two arrays
+//! concatenates them; `*` of a string and an int repeats the string. Any
+//! other combination is a type error.
+
+use std::cmp::Ordering;
+
+use crate::bigint::BigInt;
+use crate::environment::Value;
+use crate::error::{RuntimeError, RuntimeErrorKind};
+
+/// What integer arithmetic does when a result does not fit in an `i64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Raise an `IntegerOverflow` runtime error.
+    #[default]
+    Checked,
+    /// Wrap around in two's complement.
+    Wrapping,
+    /// Clamp to `i64::MIN` or `i64::MAX`.
+    Saturating,
+    /// Continue with an arbitrary-precision big integer.
+    Promote,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Subtract => "-",
+            Op::Multiply => "*",
+            Op::Divide => "/",
+        }
+    }
+}
+
+/// A pair of numeric operands after promotion to a common type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operands {
+    Int(i64, i64),
+    Big(BigInt, BigInt),
+    Float(f64, f64),
+}
+
+/// Promotes two values to a common numeric type, or `None` if either is
+/// not a number.
+pub fn promote(left: &Value, right: &Value) -> Option<Operands> {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => Some(Operands::Int(*a, *b)),
+        (Value::Float(a), Value::Float(b)) => Some(Operands::Float(*a, *b)),
+        (Value::Float(a), b) => Some(Operands::Float(*a, to_f64(b)?)),
+        (a, Value::Float(b)) => Some(Operands::Float(to_f64(a)?, *b)),
+        (a, b) => Some(Operands::Big(to_big(a)?, to_big(b)?)),
+    }
+}
+
+fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => Some(*n as f64),
+        Value::BigInt(n) => Some(n.to_f64()),
+        _ => None,
+    }
+}
+
+fn to_big(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Number(n) => Some(BigInt::from_i64(*n)),
+        Value::BigInt(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+/// A big integer result as a value: a plain int whenever it fits.
+pub fn from_big(n: BigInt) -> Value {
+    match n.to_i64() {
+        Some(n) => Value::Number(n),
+        None => Value::BigInt(n),
+    }
+}
+
+pub fn arithmetic(op: Op, operands: Operands, overflow: Overflow) -> Result<Value, RuntimeError> {
+    let zero_divisor = match &operands {
+        Operands::Int(_, b) => *b == 0,
+        Operands::Big(_, b) => b.is_zero(),
+        Operands::Float(_, b) => *b == 0.0,
+    };
+    if op == Op::Divide && zero_divisor {
+        return Err(RuntimeError::with_kind(
+            RuntimeErrorKind::DivisionByZero,
+            "Division by zero",
+        ));
+    }
+    match operands {
+        Operands::Int(a, b) => int_arithmetic(op, a, b, overflow),
+        Operands::Big(a, b) => Ok(from_big(big_arithmetic(op, &a, &b))),
+        Operands::Float(a, b) => Ok(Value::Float(match op {
+            Op::Add => a + b,
+            Op::Subtract => a - b,
+            Op::Multiply => a * b,
+            Op::Divide => a / b,
+        })),
+    }
+}
+
+fn int_arithmetic(op: Op, a: i64, b: i64, overflow: Overflow) -> Result<Value, RuntimeError> {
+    let result = match overflow {
+        Overflow::Checked | Overflow::Promote => {
+            let checked = match op {
+                Op::Add => a.checked_add(b),
+                Op::Subtract => a.checked_sub(b),
+                Op::Multiply => a.checked_mul(b),
+                Op::Divide => a.checked_div(b),
+            };
+            match checked {
+                Some(n) => n,
+                None if overflow == Overflow::Promote => {
+                    let (a, b) = (BigInt::from_i64(a), BigInt::from_i64(b));
+                    return Ok(from_big(big_arithmetic(op, &a, &b)));
+                }
+                None => {
+                    return Err(overflow_error(format!("{} {} {}", a, op.symbol(), b)));
+                }
+            }
+        }
+        Overflow::Wrapping => match op {
+            Op::Add => a.wrapping_add(b),
+            Op::Subtract => a.wrapping_sub(b),
+            Op::Multiply => a.wrapping_mul(b),
+            Op::Divide => a.wrapping_div(b),
+        },
+        Overflow::Saturating => match op {
+            Op::Add => a.saturating_add(b),
+            Op::Subtract => a.saturating_sub(b),
+            Op::Multiply => a.saturating_mul(b),
+            Op::Divide => a.saturating_div(b),
+        },
+    };
+    Ok(Value::Number(result))
+}
+
+fn big_arithmetic(op: Op, a: &BigInt, b: &BigInt) -> BigInt {
+    match op {
+        Op::Add => a.add(b),
+        Op::Subtract => a.sub(b),
+        Op::Multiply => a.mul(b),
+        Op::Divide => a.div(b),
+    }
+}
+
+/// `-n` for an int, under the same overflow rules as the binary operators.
+pub fn negate(n: i64, overflow: Overflow) -> Result<Value, RuntimeError> {
+    let result = match overflow {
+        Overflow::Checked => n
+            .checked_neg()
+            .ok_or_else(|| overflow_error(format!("-({})", n)))?,
+        Overflow::Wrapping => n.wrapping_neg(),
+        Overflow::Saturating => n.saturating_neg(),
+        Overflow::Promote => match n.checked_neg() {
+            Some(n) => n,
+            None => return Ok(from_big(BigInt::from_i64(n).negated())),
+        },
+    };
+    Ok(Value::Number(result))
+}
+
+fn overflow_error(expression: String) -> RuntimeError {
+    RuntimeError::with_kind(
+        RuntimeErrorKind::IntegerOverflow,
+        format!("Integer overflow in {}", expression),
+    )
+}
+
+/// Orders two numbers, or `None` if either is NaN.
+pub fn compare(operands: Operands) -> Option<Ordering> {
+    match operands {
+        Operands::Int(a, b) => Some(a.cmp(&b)),
+        Operands::Big(a, b) => Some(a.cmp(&b)),
+        Operands::Float(a, b) => a.partial_cmp(&b),
+    }
+}
@@ -1,54 +1,185 @@
-use crate::ast::{BinOp, Expr, LogicalOp, Program, Stmt, UnaryOp};
+use std::rc::Rc;
+
+use crate::ast::{BinOp, Expr, ExprKind, LogicalOp, Program, Slot, Span, Stmt, StmtKind, UnaryOp};
+use crate::error::ParseError;
+use crate::symbol::Symbol;
 use crate::tokens::{Token, TokenWithSpan};
 
+type ParseResult<T> = Result<T, ParseError>;
+
+/// Builds a `Program` from tokens, as produced by a `Scanner`. Syntax errors
+/// are returned by `parse`, never panicked on, whatever the tokens are.
 pub struct Parser {
+    /// The tokens still needed: the last one consumed, the next one and,
+    /// when it exists, the one after that. A parser made with `new` holds
+    /// every token here from the start.
     tokens: Vec<TokenWithSpan>,
+    /// Where the rest of the tokens come from, for a parser made with
+    /// `from_stream`.
+    stream: Option<Box<dyn Iterator<Item = TokenWithSpan>>>,
     current: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<TokenWithSpan>) -> Self {
-        Parser { tokens, current: 0 }
+    /// A parser over `tokens`. They normally end with `Token::EOF`; if not,
+    /// the input is treated as ending after the last one.
+    pub fn new(mut tokens: Vec<TokenWithSpan>) -> Self {
+        if !tokens.last().is_some_and(|last| last.token == Token::EOF) {
+            tokens.push(eof_after(tokens.last()));
+        }
+        Parser {
+            tokens,
+            stream: None,
+            current: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// A parser that pulls tokens from `tokens` as it needs them, such as a
+    /// streaming `Scanner` with its errors filtered out, and drops each
+    /// statement's tokens once it is parsed. A stream that stops without
+    /// `Token::EOF` is treated as ending there.
+    pub fn from_stream(tokens: impl Iterator<Item = TokenWithSpan> + 'static) -> Self {
+        let mut parser = Parser {
+            tokens: Vec::new(),
+            stream: Some(Box::new(tokens)),
+            current: 0,
+            errors: Vec::new(),
+        };
+        parser.fill();
+        parser
+    }
+
+    /// Pulls tokens from the stream until the token after the next one is
+    /// buffered, which is as far ahead as the grammar looks.
+    fn fill(&mut self) {
+        while self.tokens.len() <= self.current + 1 {
+            let Some(stream) = &mut self.stream else {
+                return;
+            };
+            match stream.next() {
+                Some(token) => {
+                    let eof = token.token == Token::EOF;
+                    self.tokens.push(token);
+                    if eof {
+                        self.stream = None;
+                    }
+                }
+                None => {
+                    self.tokens.push(eof_after(self.tokens.last()));
+                    self.stream = None;
+                }
+            }
+        }
+    }
+
+    /// Drops the tokens of statements already parsed, keeping the last one
+    /// consumed for span calculations.
+    fn discard_parsed(&mut self) {
+        if self.stream.is_some() && self.current > 1 {
+            self.tokens.drain(..self.current - 1);
+            self.current = 1;
+        }
     }
 
-    pub fn parse(&mut self) -> Program {
+    /// Parses the whole token stream. After a syntax error the parser skips
+    /// to the next statement boundary and carries on, so every error in the
+    /// source is reported at once.
+    pub fn parse(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            if let Some(stmt) = self.declaration() {
+            if let Some(stmt) = self.recovering_declaration() {
                 statements.push(stmt);
             }
         }
-        Program { statements }
+        if self.errors.is_empty() {
+            Ok(Program { statements })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    // === declaration -> function_decl | let_decl | statement ===
-    fn declaration(&mut self) -> Option<Stmt> {
-        if self.matches(&[Token::Fn]) {
-            self.function_declaration()
-        } else if self.matches(&[Token::Let]) {
-            if let Ok(stmt) = self.let_declaration() {
-                Some(stmt)
-            } else {
+    /// Parses one declaration, or records the error and resynchronizes.
+    fn recovering_declaration(&mut self) -> Option<Stmt> {
+        self.discard_parsed();
+        let before = self.current;
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(error) => {
+                self.errors.push(error);
                 self.synchronize();
+                if self.current == before {
+                    // Nothing was consumed (e.g. a stray `}`); skip it so
+                    // parsing always makes progress.
+                    self.advance();
+                }
                 None
             }
-        } else {
-            self.statement()
         }
     }
 
+    // === declaration -> function_decl | let_decl | test_decl | bench_decl | statement ===
+    fn declaration(&mut self) -> ParseResult<Stmt> {
+        let start = self.peek_start();
+        // `fn (` starts an anonymous function, which is an expression.
+        let kind = if self.check(&Token::Fn) && !self.check_next(&Token::LeftParen) {
+            self.advance();
+            self.function_declaration()?
+        } else if self.check_named_block("test") {
+            self.advance();
+            let (name, body) = self.named_block("test")?;
+            StmtKind::Test { name, body }
+        } else if self.check_named_block("bench") {
+            self.advance();
+            let (name, body) = self.named_block("benchmark")?;
+            StmtKind::Bench { name, body }
+        } else if self.matches(&[Token::Let]) {
+            self.let_declaration()?
+        } else {
+            return self.statement();
+        };
+        Ok(Stmt {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
     // === function_decl -> "function" IDENTIFIER "(" parameters? ")" block ===
-    fn function_declaration(&mut self) -> Option<Stmt> {
-        let name = self
-            .consume_identifier()
-            .expect("Expect function name after 'function'.");
-        self.consume(Token::LeftParen, "Expect '(' after function name.");
+    fn function_declaration(&mut self) -> ParseResult<StmtKind> {
+        let name = self.expect_identifier("Expect function name after 'fn'.")?;
+        self.consume(Token::LeftParen, "Expect '(' after function name.")?;
+        let (params, param_spans, body) = self.function_rest()?;
+        Ok(StmtKind::Function {
+            name,
+            params: params.into(),
+            param_spans: param_spans.into(),
+            body: Rc::new(body),
+        })
+    }
 
+    // === test_decl -> "test" STRING block ===
+    // === bench_decl -> "bench" STRING block ===
+    /// The name and body of a test or benchmark, after its keyword.
+    fn named_block(&mut self, what: &str) -> ParseResult<(String, Rc<Vec<Stmt>>)> {
+        let Token::StringLiteral(name) = self.advance().token.clone() else {
+            unreachable!("checked by check_named_block");
+        };
+        self.consume(
+            Token::LeftBrace,
+            &format!("Expect '{{' after {} name.", what),
+        )?;
+        Ok((name, Rc::new(self.block_body()?)))
+    }
+
+    /// Parameters, their spans and body of a function, after its opening `(`.
+    fn function_rest(&mut self) -> ParseResult<(Vec<Symbol>, Vec<Span>, Vec<Stmt>)> {
         let mut params = Vec::new();
+        let mut param_spans = Vec::new();
         if !self.check(&Token::RightParen) {
             loop {
-                let param = self.consume_identifier().expect("Expect parameter name.");
-                params.push(param);
+                params.push(self.expect_identifier("Expect parameter name.")?);
+                param_spans.push(self.previous().span);
 
                 if !self.matches(&[Token::Comma]) {
                     break;
@@ -56,32 +187,29 @@ impl Parser {
             }
         }
 
-        self.consume(Token::RightParen, "Expect ')' after parameters.");
-        self.consume(Token::LeftBrace, "Expect '{' before function body.");
-        let body = self.block_body();
-
-        Some(Stmt::Function { name, params, body })
+        self.consume(Token::RightParen, "Expect ')' after parameters.")?;
+        self.consume(Token::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.block_body()?;
+        Ok((params, param_spans, body))
     }
 
-    fn let_declaration(&mut self) -> Result<Stmt, String> {
-        let name = match self.consume_identifier() {
-            Some(name) => name,
-            None => return Err("Expected variable name after 'let'".to_string()),
-        };
+    fn let_declaration(&mut self) -> ParseResult<StmtKind> {
+        let name = self.expect_identifier("Expected variable name after 'let'")?;
 
         let initializer = if self.matches(&[Token::Equals]) {
-            Some(self.expression())
+            Some(self.expression()?)
         } else {
             None
         };
 
-        self.consume(Token::Semicolon, "Expected ';' after variable declaration");
-        Ok(Stmt::Let { name, initializer })
+        self.consume(Token::Semicolon, "Expected ';' after variable declaration")?;
+        Ok(StmtKind::Let { name, initializer })
     }
 
     // === statement -> return | if | while | for | block | print | expr_stmt ===
-    fn statement(&mut self) -> Option<Stmt> {
-        if self.matches(&[Token::Return]) {
+    fn statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.peek_start();
+        let kind = if self.matches(&[Token::Return]) {
             self.return_statement()
         } else if self.matches(&[Token::If]) {
             self.if_statement()
@@ -90,184 +218,192 @@ impl Parser {
         } else if self.matches(&[Token::For]) {
             self.for_statement()
         } else if self.matches(&[Token::LeftBrace]) {
-            Some(self.block())
+            self.block()
         } else if self.matches(&[Token::Print]) {
             self.print_statement()
         } else {
             self.expression_statement()
-        }
+        }?;
+        Ok(Stmt {
+            kind,
+            span: self.span_from(start),
+        })
     }
 
-    fn for_statement(&mut self) -> Option<Stmt> {
-        self.consume(Token::LeftParen, "Expect '(' after 'for'.");
+    fn for_statement(&mut self) -> ParseResult<StmtKind> {
+        self.consume(Token::LeftParen, "Expect '(' after 'for'.")?;
 
-        let variable = self
-            .consume_identifier()
-            .expect("Expect variable name in for loop.");
+        let variable = self.expect_identifier("Expect variable name in for loop.")?;
 
-        self.consume(Token::In, "Expect 'in' after variable.");
+        self.consume(Token::In, "Expect 'in' after variable.")?;
 
-        let iterable = self.expression();
+        let iterable = self.expression()?;
 
-        self.consume(Token::RightParen, "Expect ')' after iterable.");
+        self.consume(Token::RightParen, "Expect ')' after iterable.")?;
 
-        let body = self
-            .statement()
-            .expect("Expect statement for for loop body.");
+        let body = self.statement()?;
 
-        Some(Stmt::For {
+        Ok(StmtKind::For {
             variable,
             iterable: Box::new(iterable),
             body: Box::new(body),
         })
     }
 
-    fn return_statement(&mut self) -> Option<Stmt> {
+    fn return_statement(&mut self) -> ParseResult<StmtKind> {
         let value = if !self.check(&Token::Semicolon) {
-            Some(self.expression())
+            Some(self.expression()?)
         } else {
             None
         };
 
-        self.consume(Token::Semicolon, "Expect ';' after return value.");
-        Some(Stmt::Return { value })
+        self.consume(Token::Semicolon, "Expect ';' after return value.")?;
+        Ok(StmtKind::Return { value })
     }
 
-    fn if_statement(&mut self) -> Option<Stmt> {
-        self.consume(Token::LeftParen, "Expect '(' after 'if'.");
-        let condition = self.expression();
-        self.consume(Token::RightParen, "Expect ')' after if condition.");
+    fn if_statement(&mut self) -> ParseResult<StmtKind> {
+        self.consume(Token::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(Token::RightParen, "Expect ')' after if condition.")?;
 
-        let then_branch = self.statement().expect("Expect statement for if body.");
+        let then_branch = self.statement()?;
         let else_branch = if self.matches(&[Token::Else]) {
-            Some(Box::new(
-                self.statement().expect("Expect statement for else body."),
-            ))
+            Some(Box::new(self.statement()?))
         } else {
             None
         };
 
-        Some(Stmt::If {
+        Ok(StmtKind::If {
             condition,
             then_branch: Box::new(then_branch),
             else_branch,
         })
     }
 
-    fn while_statement(&mut self) -> Option<Stmt> {
-        self.consume(Token::LeftParen, "Expect '(' after 'while'.");
-        let condition = self.expression();
-        self.consume(Token::RightParen, "Expect ')' after while condition.");
+    fn while_statement(&mut self) -> ParseResult<StmtKind> {
+        self.consume(Token::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(Token::RightParen, "Expect ')' after while condition.")?;
 
-        let body = self.statement().expect("Expect statement for while body.");
-        Some(Stmt::While {
+        let body = self.statement()?;
+        Ok(StmtKind::While {
             condition,
             body: Box::new(body),
         })
     }
 
-    fn block(&mut self) -> Stmt {
-        Stmt::Block(self.block_body())
+    fn block(&mut self) -> ParseResult<StmtKind> {
+        Ok(StmtKind::Block(self.block_body()?))
     }
 
-    fn block_body(&mut self) -> Vec<Stmt> {
+    fn block_body(&mut self) -> ParseResult<Vec<Stmt>> {
         let mut statements = Vec::new();
         while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            if let Some(decl) = self.declaration() {
-                statements.push(decl);
+            if let Some(stmt) = self.recovering_declaration() {
+                statements.push(stmt);
             }
         }
-        self.consume(Token::RightBrace, "Expect '}' after block.");
-        statements
+        self.consume(Token::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
     }
 
-    fn print_statement(&mut self) -> Option<Stmt> {
-        let expr = self.expression();
-        self.consume(Token::Semicolon, "Expected ';' after value");
-        Some(Stmt::Print(expr))
+    fn print_statement(&mut self) -> ParseResult<StmtKind> {
+        let expr = self.expression()?;
+        self.consume(Token::Semicolon, "Expected ';' after value")?;
+        Ok(StmtKind::Print(expr))
     }
 
-    fn expression_statement(&mut self) -> Option<Stmt> {
-        let expr = self.expression();
-        self.consume(Token::Semicolon, "Expected ';' after expression");
-        Some(Stmt::Expr(expr))
+    fn expression_statement(&mut self) -> ParseResult<StmtKind> {
+        let expr = self.expression()?;
+        self.consume(Token::Semicolon, "Expected ';' after expression")?;
+        Ok(StmtKind::Expr(expr))
     }
 
     // === Expressions ==========================================================
-    fn expression(&mut self) -> Expr {
+    fn expression(&mut self) -> ParseResult<Expr> {
         self.assignment()
     }
 
-    fn assignment(&mut self) -> Expr {
-        let expr = self.logical_or();
+    fn assignment(&mut self) -> ParseResult<Expr> {
+        let expr = self.logical_or()?;
 
         if self.matches(&[Token::Equals]) {
-            let value = self.assignment();
-            match expr {
-                Expr::Variable(name) => Expr::Assign(name, Box::new(value)),
-                Expr::Index { object, index } => Expr::IndexAssign {
+            let value = self.assignment()?;
+            let span = (expr.span.0, value.span.1);
+            let kind = match expr.kind {
+                ExprKind::Variable { name, .. } => ExprKind::Assign {
+                    name,
+                    slot: Slot::Unresolved,
+                    value: Box::new(value),
+                },
+                ExprKind::Index { object, index } => ExprKind::IndexAssign {
                     object,
                     index,
                     value: Box::new(value),
                 },
-                Expr::Dot { object, field } => Expr::DotAssign {
+                ExprKind::Dot { object, field } => ExprKind::DotAssign {
                     object,
                     field,
                     value: Box::new(value),
                 },
-                _ => panic!("Invalid assignment target"),
-            }
+                _ => return Err(ParseError::new("Invalid assignment target", expr.span)),
+            };
+            Ok(Expr::new(kind, span))
         } else {
-            expr
+            Ok(expr)
         }
     }
 
-    fn logical_or(&mut self) -> Expr {
-        let mut expr = self.logical_and();
+    fn logical_or(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.logical_and()?;
         while self.matches(&[Token::Or]) {
-            let right = self.logical_and();
-            expr = Expr::Logical {
-                left: Box::new(expr),
-                operator: LogicalOp::Or,
-                right: Box::new(right),
-            };
+            let right = self.logical_and()?;
+            let span = (expr.span.0, right.span.1);
+            expr = Expr::new(
+                ExprKind::Logical {
+                    left: Box::new(expr),
+                    operator: LogicalOp::Or,
+                    right: Box::new(right),
+                },
+                span,
+            );
         }
-        expr
+        Ok(expr)
     }
 
-    fn logical_and(&mut self) -> Expr {
-        let mut expr = self.equality();
+    fn logical_and(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.equality()?;
         while self.matches(&[Token::And]) {
-            let right = self.equality();
-            expr = Expr::Logical {
-                left: Box::new(expr),
-                operator: LogicalOp::And,
-                right: Box::new(right),
-            };
+            let right = self.equality()?;
+            let span = (expr.span.0, right.span.1);
+            expr = Expr::new(
+                ExprKind::Logical {
+                    left: Box::new(expr),
+                    operator: LogicalOp::And,
+                    right: Box::new(right),
+                },
+                span,
+            );
         }
-        expr
+        Ok(expr)
     }
 
-    fn equality(&mut self) -> Expr {
-        let mut expr = self.comparison();
+    fn equality(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.comparison()?;
         while self.matches(&[Token::EqualEqual, Token::BangEqual]) {
             let operator = match self.previous().token {
                 Token::EqualEqual => BinOp::EqualEqual,
                 Token::BangEqual => BinOp::BangEqual,
                 _ => unreachable!(),
             };
-            let right = self.comparison();
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+            let right = self.comparison()?;
+            expr = binary(expr, operator, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn comparison(&mut self) -> Expr {
-        let mut expr = self.term();
+    fn comparison(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.term()?;
         while self.matches(&[
             Token::Greater,
             Token::GreaterEqual,
@@ -281,125 +417,166 @@ impl Parser {
                 Token::LessEqual => BinOp::LessEqual,
                 _ => unreachable!(),
             };
-            let right = self.term();
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+            let right = self.term()?;
+            expr = binary(expr, operator, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn term(&mut self) -> Expr {
-        let mut expr = self.factor();
+    fn term(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.factor()?;
         while self.matches(&[Token::Plus, Token::Minus]) {
             let operator = match self.previous().token {
                 Token::Plus => BinOp::Add,
                 Token::Minus => BinOp::Subtract,
                 _ => unreachable!(),
             };
-            let right = self.factor();
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
+            let right = self.factor()?;
+            expr = binary(expr, operator, right);
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.postfix()?;
+        while self.matches(&[Token::Star, Token::Slash]) {
+            let operator = match self.previous().token {
+                Token::Star => BinOp::Multiply,
+                Token::Slash => BinOp::Divide,
+                _ => unreachable!(),
             };
+            let right = self.postfix()?;
+            expr = binary(expr, operator, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn factor(&mut self) -> Expr {
-        let expr = self.unary();
+    fn postfix(&mut self) -> ParseResult<Expr> {
+        let expr = self.unary()?;
         self.finish_index(expr)
     }
 
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> ParseResult<Expr> {
+        let start = self.peek_start();
         if self.matches(&[Token::Bang, Token::Minus]) {
             let operator = match self.previous().token {
                 Token::Bang => UnaryOp::Not,
                 Token::Minus => UnaryOp::Negate,
                 _ => unreachable!(),
             };
-            let right = self.unary();
-            return Expr::Unary {
-                operator,
-                right: Box::new(right),
-            };
+            let right = self.unary()?;
+            let span = (start, right.span.1);
+            return Ok(Expr::new(
+                ExprKind::Unary {
+                    operator,
+                    right: Box::new(right),
+                },
+                span,
+            ));
         }
         self.primary()
     }
 
-    fn primary(&mut self) -> Expr {
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let start = self.peek_start();
         if self.matches(&[Token::True]) {
-            return Expr::Boolean(true);
+            return Ok(self.finish_expr(ExprKind::Boolean(true), start));
         }
         if self.matches(&[Token::False]) {
-            return Expr::Boolean(false);
+            return Ok(self.finish_expr(ExprKind::Boolean(false), start));
+        }
+        if self.matches(&[Token::Nil]) {
+            return Ok(self.finish_expr(ExprKind::Nil, start));
+        }
+
+        if let Token::Number(val) = self.peek().token {
+            self.advance();
+            return Ok(self.finish_expr(ExprKind::Number(val), start));
         }
 
-        if let Token::Number(val) = self.tokens[self.current].token {
+        if let Token::Float(val) = self.peek().token {
             self.advance();
-            return Expr::Number(val);
+            return Ok(self.finish_expr(ExprKind::Float(val), start));
         }
 
-        if let Token::StringLiteral(val) = &self.tokens[self.current].token {
+        if let Token::StringLiteral(val) = &self.peek().token {
             let s = val.clone();
             self.advance();
-            return Expr::String(s);
+            return Ok(self.finish_expr(ExprKind::String(s), start));
         }
 
         if self.matches(&[Token::LeftBrace]) {
-            return self.map_literal();
+            return self.map_literal(start);
         }
 
         if self.matches(&[Token::LeftBracket]) {
-            return self.array_literal();
+            return self.array_literal(start);
+        }
+
+        if self.matches(&[Token::Fn]) {
+            self.consume(Token::LeftParen, "Expect '(' after 'fn'.")?;
+            let (params, param_spans, body) = self.function_rest()?;
+            let kind = ExprKind::Lambda {
+                params: params.into(),
+                param_spans: param_spans.into(),
+                body: Rc::new(body),
+            };
+            return Ok(self.finish_expr(kind, start));
         }
 
-        if let Token::Identifier(name) = &self.tokens[self.current].token {
+        if let Token::Identifier(name) = &self.peek().token {
             let name_clone = name.clone();
             self.advance();
+            let callee = self.finish_expr(
+                ExprKind::Variable {
+                    name: name_clone,
+                    slot: Slot::Unresolved,
+                },
+                start,
+            );
 
             if self.check(&Token::LeftParen) {
                 self.advance();
-                let arguments = self.arguments();
-                self.consume(Token::RightParen, "Expected ')' after arguments");
-                return Expr::Call {
-                    callee: Box::new(Expr::Variable(name_clone)),
-                    arguments,
-                };
+                let arguments = self.arguments()?;
+                self.consume(Token::RightParen, "Expected ')' after arguments")?;
+                return Ok(self.finish_expr(
+                    ExprKind::Call {
+                        callee: Box::new(callee),
+                        arguments,
+                    },
+                    start,
+                ));
             }
-            return Expr::Variable(name_clone);
+            return Ok(callee);
         }
 
         if self.matches(&[Token::LeftParen]) {
-            let expr = self.expression();
-            self.consume(Token::RightParen, "Expected ')' after expression");
-            return expr;
+            let expr = self.expression()?;
+            self.consume(Token::RightParen, "Expected ')' after expression")?;
+            return Ok(expr);
         }
 
-        panic!(
-            "Expected expression, found {:?} at pos {}",
-            self.tokens[self.current].token, self.current
-        );
+        Err(self.error_at_peek(format!(
+            "Expected expression, found {:?}",
+            self.peek().token
+        )))
     }
 
-    fn map_literal(&mut self) -> Expr {
+    fn map_literal(&mut self, start: usize) -> ParseResult<Expr> {
         let mut pairs = Vec::new();
 
         if !self.check(&Token::RightBrace) {
             loop {
-                let key = match &self.tokens[self.current].token {
-                    Token::StringLiteral(s) => s.clone(),
+                let key = match &self.peek().token {
+                    Token::StringLiteral(s) => Symbol::from(s.as_str()),
                     Token::Identifier(name) => name.clone(),
-                    _ => panic!("Map key must be string or identifier"),
+                    _ => return Err(self.error_at_peek("Map key must be string or identifier")),
                 };
                 self.advance();
 
-                self.consume(Token::Colon, "Expect ':' after map key");
+                self.consume(Token::Colon, "Expect ':' after map key")?;
 
-                let value = self.expression();
+                let value = self.expression()?;
                 pairs.push((key, value));
 
                 if !self.matches(&[Token::Comma]) {
@@ -408,83 +585,104 @@ impl Parser {
             }
         }
 
-        self.consume(Token::RightBrace, "Expect '}' after map literal");
-        Expr::Map(pairs)
+        self.consume(Token::RightBrace, "Expect '}' after map literal")?;
+        Ok(self.finish_expr(ExprKind::Map(pairs), start))
     }
 
-    fn array_literal(&mut self) -> Expr {
+    fn array_literal(&mut self, start: usize) -> ParseResult<Expr> {
         let mut elements = Vec::new();
         if !self.check(&Token::RightBracket) {
             loop {
-                elements.push(self.expression());
+                elements.push(self.expression()?);
                 if !self.matches(&[Token::Comma]) {
                     break;
                 }
             }
         }
-        self.consume(Token::RightBracket, "Expected ']' after array elements");
-        Expr::Array(elements)
+        self.consume(Token::RightBracket, "Expected ']' after array elements")?;
+        Ok(self.finish_expr(ExprKind::Array(elements), start))
     }
 
-    fn finish_index(&mut self, mut object: Expr) -> Expr {
+    fn finish_index(&mut self, mut object: Expr) -> ParseResult<Expr> {
+        let start = object.span.0;
         loop {
             if self.matches(&[Token::LeftBracket]) {
-                let index = self.expression();
-                self.consume(Token::RightBracket, "Expected ']' after index");
+                let index = self.expression()?;
+                self.consume(Token::RightBracket, "Expected ']' after index")?;
 
                 if self.matches(&[Token::Equals]) {
-                    let value = self.expression();
-                    object = Expr::IndexAssign {
-                        object: Box::new(object),
-                        index: Box::new(index),
-                        value: Box::new(value),
-                    };
+                    let value = self.expression()?;
+                    object = self.finish_expr(
+                        ExprKind::IndexAssign {
+                            object: Box::new(object),
+                            index: Box::new(index),
+                            value: Box::new(value),
+                        },
+                        start,
+                    );
                 } else {
-                    object = Expr::Index {
-                        object: Box::new(object),
-                        index: Box::new(index),
-                    };
+                    object = self.finish_expr(
+                        ExprKind::Index {
+                            object: Box::new(object),
+                            index: Box::new(index),
+                        },
+                        start,
+                    );
                 }
+            } else if self.matches(&[Token::LeftParen]) {
+                let arguments = self.arguments()?;
+                self.consume(Token::RightParen, "Expected ')' after arguments")?;
+                object = self.finish_expr(
+                    ExprKind::Call {
+                        callee: Box::new(object),
+                        arguments,
+                    },
+                    start,
+                );
             } else if self.matches(&[Token::Dot]) {
-                let field = self
-                    .consume_identifier()
-                    .expect("Expect field name after '.'");
+                let field = self.expect_identifier("Expect field name after '.'")?;
 
                 if self.matches(&[Token::Equals]) {
-                    let value = self.expression();
-                    object = Expr::DotAssign {
-                        object: Box::new(object),
-                        field,
-                        value: Box::new(value),
-                    };
+                    let value = self.expression()?;
+                    object = self.finish_expr(
+                        ExprKind::DotAssign {
+                            object: Box::new(object),
+                            field,
+                            value: Box::new(value),
+                        },
+                        start,
+                    );
                 } else {
-                    object = Expr::Dot {
-                        object: Box::new(object),
-                        field,
-                    };
+                    object = self.finish_expr(
+                        ExprKind::Dot {
+                            object: Box::new(object),
+                            field,
+                        },
+                        start,
+                    );
                 }
             } else {
                 break;
             }
         }
-        object
+        Ok(object)
     }
 
-    fn arguments(&mut self) -> Vec<Expr> {
+    fn arguments(&mut self) -> ParseResult<Vec<Expr>> {
         let mut args = Vec::new();
         if !self.check(&Token::RightParen) {
             loop {
-                args.push(self.expression());
+                args.push(self.expression()?);
                 if !self.matches(&[Token::Comma]) {
                     break;
                 }
             }
         }
-        args
+        Ok(args)
     }
 
-    fn consume_identifier(&mut self) -> Option<String> {
-        if let Token::Identifier(name) = &self.tokens[self.current].token {
+    fn consume_identifier(&mut self) -> Option<Symbol> {
+        if let Token::Identifier(name) = &self.peek().token {
             let name = name.clone();
             self.advance();
             Some(name)
@@ -493,6 +691,30 @@ impl Parser {
         }
     }
 
+    fn expect_identifier(&mut self, message: &str) -> ParseResult<Symbol> {
+        self.consume_identifier()
+            .ok_or_else(|| self.error_at_peek(message))
+    }
+
+    // === span helpers =======================================================
+    /// Byte offset where the next unconsumed token starts.
+    fn peek_start(&self) -> usize {
+        self.peek().span.0
+    }
+
+    /// Span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        (start, self.previous().span.1)
+    }
+
+    fn finish_expr(&self, kind: ExprKind, start: usize) -> Expr {
+        Expr::new(kind, self.span_from(start))
+    }
+
+    fn error_at_peek(&self, message: impl Into<String>) -> ParseError {
+        ParseError::new(message, self.peek().span)
+    }
+
     // === scanner helpers ====================================================
     fn matches(&mut self, tokens: &[Token]) -> bool {
         for token in tokens {
@@ -514,35 +736,73 @@ impl Parser {
         }
     }
 
+    /// Like `check`, but for the token after the next one.
+    fn check_next(&self, token_type: &Token) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|next| &next.token == token_type)
+    }
+
+    /// Whether the next tokens are the identifier `word` and a string, as in
+    /// `test "name" { ... }`. Such words are not reserved, so `test` and
+    /// `bench` are still ordinary names anywhere else.
+    fn check_named_block(&self, word: &str) -> bool {
+        matches!(&self.peek().token, Token::Identifier(name) if &**name == word)
+            && self
+                .tokens
+                .get(self.current + 1)
+                .is_some_and(|next| matches!(next.token, Token::StringLiteral(_)))
+    }
+
+    /// Skips tokens until just past a `;` or just before a token that
+    /// starts a statement or closes a block.
     fn synchronize(&mut self) {
-        self.advance();
         while !self.is_at_end() {
-            if let Token::Semicolon = self.previous().token {
-                return;
+            match self.peek().token {
+                Token::Semicolon => {
+                    self.advance();
+                    return;
+                }
+                Token::RightBrace
+                | Token::Let
+                | Token::Print
+                | Token::If
+                | Token::While
+                | Token::For
+                | Token::Fn
+                | Token::Return => return,
+                _ => {
+                    self.advance();
+                }
             }
-            match self.tokens[self.current].token {
-                Token::Let | Token::Print | Token::If | Token::While | Token::Fn => return,
-                _ => self.advance(),
-            };
         }
     }
 
     fn advance(&mut self) -> &TokenWithSpan {
         if !self.is_at_end() {
             self.current += 1;
+            self.fill();
         }
         self.previous()
     }
 
+    /// The next unconsumed token, or the trailing EOF once input runs out.
+    fn peek(&self) -> &TokenWithSpan {
+        let last = self.tokens.len() - 1;
+        &self.tokens[self.current.min(last)]
+    }
+
+    /// The last consumed token, or the first token before any is consumed.
     fn previous(&self) -> &TokenWithSpan {
-        &self.tokens[self.current - 1]
+        &self.tokens[self.current.saturating_sub(1)]
     }
 
-    fn consume(&mut self, token: Token, message: &str) {
+    fn consume(&mut self, token: Token, message: &str) -> ParseResult<()> {
         if self.check(&token) {
             self.advance();
+            Ok(())
         } else {
-            panic!("{}", message);
+            Err(self.error_at_peek(message))
         }
     }
 
@@ -550,3 +810,26 @@ impl Parser {
         self.current >= self.tokens.len() || matches!(self.tokens[self.current].token, Token::EOF)
     }
 }
+
+/// An EOF token placed right after `last`, for token lists that lack one.
+fn eof_after(last: Option<&TokenWithSpan>) -> TokenWithSpan {
+    let (end, line, column) = last.map_or((0, 1, 1), |t| (t.span.1, t.line, t.column));
+    TokenWithSpan {
+        token: Token::EOF,
+        span: (end, end),
+        line,
+        column,
+    }
+}
+
+fn binary(left: Expr, operator: BinOp, right: Expr) -> Expr {
+    let span = (left.span.0, right.span.1);
+    Expr::new(
+        ExprKind::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        },
+        span,
+    )
+}
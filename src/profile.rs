@@ -0,0 +1,143 @@
+//! Where a script spends its time, for `ruilian --profile`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::ast::Span;
+use crate::environment::Value;
+use crate::error::RuntimeError;
+use crate::hash::FastHashMap;
+use crate::hooks::Hooks;
+
+/// How many of the most executed lines `Profiler::report` lists.
+const REPORTED_LINES: usize = 10;
+
+#[derive(Debug, Default)]
+struct FunctionStats {
+    calls: u64,
+    /// Time from call to return, counted once for recursive calls.
+    total: Duration,
+    /// `total` less the time spent in the functions it called.
+    own: Duration,
+}
+
+struct Frame {
+    function: String,
+    start: Instant,
+    /// Time spent so far in calls made by this one.
+    children: Duration,
+}
+
+#[derive(Default)]
+struct Profile {
+    functions: HashMap<String, FunctionStats>,
+    /// How many times the statement starting at each offset ran.
+    statements: FastHashMap<usize, u64>,
+    frames: Vec<Frame>,
+}
+
+/// Hooks that count calls, time functions and count how often each line
+/// runs. Clones share one profile, so a host installs a clone with
+/// `Interpreter::set_hooks` and asks the one it kept for the `report`.
+#[derive(Clone, Default)]
+pub struct Profiler(Rc<RefCell<Profile>>);
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The profile so far as a table of functions, slowest (by time spent
+    /// in the function itself) first, followed by the most executed lines
+    /// of `source`:
+    ///
+    /// ```text
+    /// function              calls       total        self
+    /// fib                     177     1.21ms      1.21ms
+    ///
+    ///  line        hits
+    ///     2         177  if (n < 2) return n;
+    /// ```
+    pub fn report(&self, source: &str) -> String {
+        let profile = self.0.borrow();
+        let mut out = String::new();
+
+        let mut functions: Vec<(&String, &FunctionStats)> = profile.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.own.cmp(&a.1.own).then_with(|| a.0.cmp(b.0)));
+        let _ = writeln!(
+            out,
+            "{:<20} {:>7} {:>11} {:>11}",
+            "function", "calls", "total", "self"
+        );
+        for (name, stats) in functions {
+            let _ = writeln!(
+                out,
+                "{:<20} {:>7} {:>11.2?} {:>11.2?}",
+                name, stats.calls, stats.total, stats.own
+            );
+        }
+
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let mut lines: HashMap<usize, u64> = HashMap::new();
+        for (&offset, &hits) in &profile.statements {
+            let line = line_starts.partition_point(|&start| start <= offset);
+            let count = lines.entry(line).or_default();
+            // A line holding several statements counts as often as the
+            // one run most.
+            *count = (*count).max(hits);
+        }
+        let mut lines: Vec<(usize, u64)> = lines.into_iter().collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\n{:>5} {:>11}", "line", "hits");
+        for (line, hits) in lines.into_iter().take(REPORTED_LINES) {
+            let text = source.lines().nth(line - 1).unwrap_or_default().trim();
+            let _ = writeln!(out, "{:>5} {:>11}  {}", line, hits, text);
+        }
+        out
+    }
+}
+
+impl Hooks for Profiler {
+    fn on_statement(&mut self, span: Span) {
+        *self.0.borrow_mut().statements.entry(span.0).or_default() += 1;
+    }
+
+    fn on_call(&mut self, function: &str, _arguments: &[Value], _call_site: Span) {
+        let mut profile = self.0.borrow_mut();
+        profile
+            .functions
+            .entry(function.to_string())
+            .or_default()
+            .calls += 1;
+        profile.frames.push(Frame {
+            function: function.to_string(),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn on_return(&mut self, _function: &str, _result: Result<&Value, &RuntimeError>) {
+        let mut profile = self.0.borrow_mut();
+        let Some(frame) = profile.frames.pop() else {
+            return;
+        };
+        let elapsed = frame.start.elapsed();
+        let recursive = profile
+            .frames
+            .iter()
+            .any(|outer| outer.function == frame.function);
+        if let Some(caller) = profile.frames.last_mut() {
+            caller.children += elapsed;
+        }
+        let stats = profile.functions.entry(frame.function).or_default();
+        stats.own += elapsed.saturating_sub(frame.children);
+        if !recursive {
+            stats.total += elapsed;
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::ast::{Expr, ExprKind, Program, Slot, Span, Stmt, StmtKind};
+use crate::builtins;
+use crate::diagnostic::Diagnostic;
+use crate::symbol::Symbol;
+
+/// Fills in the `Slot` of every variable in `program`, so the interpreter
+/// reads locals by position instead of searching the scope chain by name,
+/// and reports names that are used before they are defined or never defined
+/// at all.
+///
+/// The scopes mirror the interpreter's environments: one per block, one per
+/// call holding the parameters and the body's own bindings, and one per
+/// `for` iteration holding the loop variable. Top-level bindings stay
+/// `Slot::Global`, looked up by name, since the host can add to them.
+pub fn resolve(program: &mut Program) -> Vec<Diagnostic> {
+    let mut resolver = Resolver::default();
+    resolver.open_scope(declared_names(&program.statements));
+    resolver.statements(&mut program.statements);
+    resolver.close_scope();
+    resolver.diagnostics.sort_by_key(|d| d.span);
+    resolver.diagnostics
+}
+
+/// A function body waiting to be resolved once its enclosing scope is
+/// complete, so it can refer to names declared after it in that scope.
+struct PendingFunction<'a> {
+    params: &'a [Symbol],
+    body: &'a mut Vec<Stmt>,
+    /// How many bindings each open scope, outermost first, had where the
+    /// function was declared.
+    visible: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    /// Slot index of each name declared so far.
+    slots: HashMap<Symbol, usize>,
+    /// Names declared directly in this scope that have not been reached yet.
+    later: HashSet<Symbol>,
+    /// While a function declared in this scope is resolved, how many of
+    /// its bindings existed at the declaration.
+    visible: Option<usize>,
+    pending: Vec<PendingFunction<'a>>,
+}
+
+#[derive(Default)]
+struct Resolver<'a> {
+    /// The global scope first, then the open local scopes, innermost last.
+    scopes: Vec<Scope<'a>>,
+    /// How many function bodies enclose the code being resolved.
+    functions: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Resolver<'a> {
+    fn open_scope(&mut self, later: HashSet<Symbol>) {
+        self.scopes.push(Scope {
+            later,
+            ..Scope::default()
+        });
+    }
+
+    fn close_scope(&mut self) {
+        while let Some(function) = self.scopes.last_mut().and_then(|s| s.pending.pop()) {
+            let outer: Vec<Option<usize>> = self.scopes.iter().map(|s| s.visible).collect();
+            for (scope, &visible) in self.scopes.iter_mut().zip(&function.visible) {
+                scope.visible = Some(visible);
+            }
+            self.functions += 1;
+            self.open_scope(declared_names(function.body));
+            for param in function.params {
+                self.declare(param);
+            }
+            self.statements(function.body);
+            self.close_scope();
+            self.functions -= 1;
+            for (scope, visible) in self.scopes.iter_mut().zip(outer) {
+                scope.visible = visible;
+            }
+        }
+        self.scopes.pop().expect("scope stack underflow");
+    }
+
+    fn declare(&mut self, name: &Symbol) {
+        let scope = self.scopes.last_mut().expect("no open scope");
+        scope.later.remove(name);
+        let next = scope.slots.len();
+        scope.slots.entry(Symbol::clone(name)).or_insert(next);
+    }
+
+    /// The bindings of each open scope that a function declared here can
+    /// see when it is declared, for `PendingFunction::visible`.
+    fn visible(&self) -> Vec<usize> {
+        self.scopes
+            .iter()
+            .map(|scope| {
+                scope
+                    .visible
+                    .map_or(scope.slots.len(), |v| v.min(scope.slots.len()))
+            })
+            .collect()
+    }
+
+    fn pending(&mut self, params: &'a [Symbol], body: &'a mut Vec<Stmt>) {
+        let visible = self.visible();
+        let scope = self.scopes.last_mut().expect("no open scope");
+        scope.pending.push(PendingFunction {
+            params,
+            body,
+            visible,
+        });
+    }
+
+    fn resolve_name(&mut self, name: &str, span: Span) -> Slot {
+        let innermost = self.scopes.len() - 1;
+        let mut declared_later = None;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&index) = scope.slots.get(name) {
+                if depth == innermost {
+                    // Globals are looked up by name whenever they are read.
+                    return declared_later.map_or(Slot::Global, |_| Slot::Unresolved);
+                }
+                if scope.visible.is_some_and(|visible| index >= visible) {
+                    // Declared after the function that reads it, so until it
+                    // is, a call reads whichever outer binding has the name.
+                    declared_later.get_or_insert(Slot::Local { depth, index });
+                    continue;
+                }
+                return match declared_later {
+                    Some(_) => Slot::Unresolved,
+                    None => Slot::Local { depth, index },
+                };
+            }
+        }
+        if let Some(slot) = declared_later {
+            return if builtins::is_standard(name) {
+                Slot::Unresolved
+            } else {
+                slot
+            };
+        }
+        if self.scopes.iter().any(|scope| scope.later.contains(name)) {
+            // Inside a function the binding may well exist by the time the
+            // function is called, so leave it to the interpreter.
+            if self.functions == 0 {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("'{}' is used before it is defined", name),
+                        Some(span),
+                    )
+                    .with_code("E0004"),
+                );
+            }
+            return Slot::Unresolved;
+        }
+        if !builtins::is_standard(name) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("Undefined variable '{}'", name), Some(span))
+                    .with_code("E0003"),
+            );
+        }
+        Slot::Global
+    }
+
+    fn statements(&mut self, statements: &'a mut [Stmt]) {
+        for stmt in statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.expression(expr),
+            StmtKind::Let { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.declare(name);
+            }
+            StmtKind::Block(statements) => {
+                self.open_scope(declared_names(statements));
+                self.statements(statements);
+                self.close_scope();
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.open_scope(HashSet::new());
+                self.declare(variable);
+                self.statement(body);
+                self.close_scope();
+            }
+            StmtKind::Function {
+                name, params, body, ..
+            } => {
+                self.declare(name);
+                self.pending(params, Rc::make_mut(body));
+            }
+            StmtKind::Return { value } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            // A test or benchmark runs once the whole file has, so like a
+            // function body it sees every top-level name.
+            StmtKind::Test { body, .. } | StmtKind::Bench { body, .. } => {
+                self.pending(&[], Rc::make_mut(body));
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &'a mut Expr) {
+        let span = expr.span;
+        match &mut expr.kind {
+            ExprKind::Number(_)
+            | ExprKind::Float(_)
+            | ExprKind::String(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Nil => {}
+            ExprKind::Variable { name, slot } => *slot = self.resolve_name(name, span),
+            ExprKind::Assign { name, slot, value } => {
+                self.expression(value);
+                *slot = self.resolve_name(name, span);
+            }
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Unary { right, .. } => self.expression(right),
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Array(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            ExprKind::Map(pairs) => {
+                for (_, value) in pairs {
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::IndexAssign {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            ExprKind::Dot { object, .. } => self.expression(object),
+            ExprKind::DotAssign { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            ExprKind::Lambda { params, body, .. } => {
+                self.pending(params, Rc::make_mut(body));
+            }
+        }
+    }
+}
+
+/// Names bound directly by the statements of one scope.
+fn declared_names(statements: &[Stmt]) -> HashSet<Symbol> {
+    statements
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Let { name, .. } | StmtKind::Function { name, .. } => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
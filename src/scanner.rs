@@ -1,53 +1,279 @@
-use crate::tokens::{Token, TokenWithSpan};
+use std::io::{ErrorKind, Read};
 
+use crate::error::ScanError;
+use crate::symbol::Interner;
+use crate::tokens::{Comment, Token, TokenWithSpan};
+
+/// How many bytes a streaming scanner reads at a time.
+const CHUNK_SIZE: usize = 8192;
+
+/// Turns source text into tokens, either all at once from a string with
+/// `scan_tokens` or one at a time with `next_token`. A scanner made with
+/// `from_reader` reads its input as it goes and only keeps the text of
+/// the token being scanned, so the source never has to fit in memory.
 pub struct Scanner {
+    /// The chars read so far that may still be needed, starting with char
+    /// number `base` of the source. All positions below count chars from
+    /// the start of the source, not from the start of this buffer.
     source: Vec<char>,
+    /// Byte offset of each char in `source`, plus one past the end, so spans
+    /// can be reported in bytes while scanning works on chars.
+    byte_offsets: Vec<usize>,
+    base: usize,
+    /// Where the rest of the source comes from, until it runs out.
+    reader: Option<Box<dyn Read>>,
+    /// Bytes read but not yet decoded, the start of a split UTF-8 sequence.
+    undecoded: Vec<u8>,
+    /// Why reading stopped early, reported as a scan error by the next call
+    /// to `next_token`.
+    input_error: Option<String>,
     start: usize,
     current: usize,
     line: usize,
+    /// Char index where the current line begins, for computing columns.
+    line_start: usize,
+    /// Line and column where the token being scanned begins.
+    start_line: usize,
+    start_column: usize,
+    identifiers: Interner,
+    /// The comments scanned so far, if the scanner keeps them.
+    comments: Option<Vec<Comment>>,
+    /// Whether the EOF token has been returned by the iterator.
+    finished: bool,
 }
 
 impl Scanner {
+    /// A scanner over the whole of `source`.
     pub fn new(source: &str) -> Self {
+        let mut byte_offsets: Vec<usize> = source.char_indices().map(|(i, _)| i).collect();
+        byte_offsets.push(source.len());
         Scanner {
             source: source.chars().collect(),
+            byte_offsets,
+            ..Scanner::empty()
+        }
+    }
+
+    /// A scanner that reads its source from `reader` as tokens are asked
+    /// for. A read error, or input that is not UTF-8, ends the input with a
+    /// scan error at the point reached.
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        Scanner {
+            reader: Some(Box::new(reader)),
+            ..Scanner::empty()
+        }
+    }
+
+    fn empty() -> Self {
+        Scanner {
+            source: Vec::new(),
+            byte_offsets: vec![0],
+            base: 0,
+            reader: None,
+            undecoded: Vec::new(),
+            input_error: None,
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            identifiers: Interner::new(),
+            comments: None,
+            finished: false,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<TokenWithSpan> {
+    /// Makes the scanner keep the comments it skips, to be collected with
+    /// `take_comments`.
+    pub fn with_comments(mut self) -> Self {
+        self.comments = Some(Vec::new());
+        self
+    }
+
+    /// The comments scanned since the last call, in source order. Always
+    /// empty unless the scanner was made `with_comments`.
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        self.comments
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Scans the whole source. Scanning carries on past a bad character so
+    /// every scan error in the source is reported at once.
+    pub fn scan_tokens(&mut self) -> Result<Vec<TokenWithSpan>, Vec<ScanError>> {
         let mut tokens = Vec::new();
-        while !self.is_at_end() {
+        let mut errors = Vec::new();
+        loop {
+            match self.next_token() {
+                Ok(token) => {
+                    let eof = token.token == Token::EOF;
+                    tokens.push(token);
+                    if eof {
+                        break;
+                    }
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(tokens)
+    }
+
+    /// Scans the next token, skipping whitespace and comments. At the end
+    /// of the source this returns `Token::EOF`, and keeps doing so. After
+    /// an error, the following call carries on with the rest of the source.
+    pub fn next_token(&mut self) -> Result<TokenWithSpan, ScanError> {
+        loop {
+            self.discard_scanned();
             self.start = self.current;
-            if let Some(token) = self.scan_token() {
-                tokens.push(TokenWithSpan {
-                    token,
-                    span: (self.start, self.current),
-                });
+            self.start_line = self.line;
+            self.start_column = self.current - self.line_start + 1;
+            let at_end = self.is_at_end();
+            if let Some(message) = self.input_error.take() {
+                return Err(self.error(message));
             }
+            let token = if at_end {
+                Token::EOF
+            } else {
+                match self.scan_token() {
+                    Ok(Some(token)) => token,
+                    Ok(None) => continue,
+                    // A token cut short by the input ending is reported as
+                    // the reason the input ended.
+                    Err(error) => {
+                        return Err(match self.input_error.take() {
+                            Some(message) => self.error(message),
+                            None => error,
+                        });
+                    }
+                }
+            };
+            return Ok(TokenWithSpan {
+                token,
+                span: self.span(),
+                line: self.start_line,
+                column: self.start_column,
+            });
+        }
+    }
+
+    /// Drops the chars of tokens already scanned from a streaming scanner's
+    /// buffer, once there are enough of them to be worth moving the rest.
+    fn discard_scanned(&mut self) {
+        let scanned = self.current - self.base;
+        if self.reader.is_some() && scanned >= CHUNK_SIZE {
+            self.source.drain(..scanned);
+            self.byte_offsets.drain(..scanned);
+            self.base = self.current;
+        }
+    }
+
+    /// Reads from the reader until char `index` is buffered or the input
+    /// runs out.
+    #[inline]
+    fn fill(&mut self, index: usize) {
+        if index >= self.base + self.source.len() && self.reader.is_some() {
+            self.refill(index);
         }
-        tokens.push(TokenWithSpan {
-            token: Token::EOF,
-            span: (self.current, self.current),
-        });
-        tokens
     }
 
-    fn scan_token(&mut self) -> Option<Token> {
+    #[cold]
+    fn refill(&mut self, index: usize) {
+        while index >= self.base + self.source.len() {
+            let Some(reader) = &mut self.reader else {
+                return;
+            };
+            let mut chunk = [0; CHUNK_SIZE];
+            let read = match reader.read(&mut chunk) {
+                Ok(read) => read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    self.end_input(Some(format!("Failed to read source: {}", error)));
+                    return;
+                }
+            };
+            if read == 0 {
+                let truncated = !self.undecoded.is_empty();
+                self.end_input(truncated.then(|| "Source is not valid UTF-8".to_string()));
+                return;
+            }
+            self.undecoded.extend_from_slice(&chunk[..read]);
+            self.decode();
+        }
+    }
+
+    /// Moves the complete UTF-8 sequences at the front of `undecoded` into
+    /// the char buffer.
+    fn decode(&mut self) {
+        let (valid, invalid) = match std::str::from_utf8(&self.undecoded) {
+            Ok(text) => (text, false),
+            Err(error) => {
+                let text = std::str::from_utf8(&self.undecoded[..error.valid_up_to()]).unwrap();
+                (text, error.error_len().is_some())
+            }
+        };
+        let mut end = *self.byte_offsets.last().unwrap();
+        for c in valid.chars() {
+            self.source.push(c);
+            end += c.len_utf8();
+            self.byte_offsets.push(end);
+        }
+        let decoded = valid.len();
+        self.undecoded.drain(..decoded);
+        if invalid {
+            self.end_input(Some("Source is not valid UTF-8".to_string()));
+        }
+    }
+
+    fn end_input(&mut self, error: Option<String>) {
+        self.reader = None;
+        self.undecoded.clear();
+        self.input_error = error;
+    }
+
+    /// The source text of the token being scanned.
+    fn text(&self) -> String {
+        self.source[self.start - self.base..self.current - self.base]
+            .iter()
+            .collect()
+    }
+
+    /// Byte range of the token currently being scanned.
+    fn span(&self) -> (usize, usize) {
+        (
+            self.byte_offsets[self.start - self.base],
+            self.byte_offsets[self.current - self.base],
+        )
+    }
+
+    fn error(&self, message: String) -> ScanError {
+        ScanError {
+            message,
+            span: self.span(),
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
+    fn scan_token(&mut self) -> Result<Option<Token>, ScanError> {
         let c = self.advance();
-        match c {
+        let token = match c {
             '"' => {
                 let mut string = String::new();
                 while self.peek() != '"' && !self.is_at_end() {
-                    if self.peek() == '\n' {
+                    let c = self.advance();
+                    if c == '\n' {
                         self.line += 1;
+                        self.line_start = self.current;
                     }
-                    string.push(self.advance());
+                    string.push(c);
                 }
                 if self.is_at_end() {
-                    panic!("Unterminated string at line {}", self.line);
+                    return Err(self.error("Unterminated string".to_string()));
                 }
                 self.advance(); // consume closing "
                 Some(Token::StringLiteral(string))
@@ -64,6 +290,18 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.comments.is_some() {
+                        let text = self.text();
+                        let text = text.trim_end();
+                        let span = self.span();
+                        let span = (span.0, span.0 + text.len());
+                        if let Some(comments) = &mut self.comments {
+                            comments.push(Comment {
+                                text: text.to_string(),
+                                span,
+                            });
+                        }
+                    }
                     None
                 } else {
                     Some(Token::Slash)
@@ -114,6 +352,7 @@ impl Scanner {
             ' ' | '\t' | '\r' => None,
             '\n' => {
                 self.line += 1;
+                self.line_start = self.current;
                 None
             }
 
@@ -121,15 +360,28 @@ impl Scanner {
                 while self.peek().is_ascii_digit() {
                     self.advance();
                 }
-                let num_str: String = self.source[self.start..self.current].iter().collect();
-                Some(Token::Number(num_str.parse().unwrap()))
+                if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+                    self.advance(); // consume '.'
+                    while self.peek().is_ascii_digit() {
+                        self.advance();
+                    }
+                    let num_str = self.text();
+                    return Ok(Some(Token::Float(num_str.parse().unwrap())));
+                }
+                let num_str = self.text();
+                match num_str.parse() {
+                    Ok(n) => Some(Token::Number(n)),
+                    Err(_) => {
+                        return Err(self.error(format!("Integer literal {} is too large", num_str)));
+                    }
+                }
             }
 
             'a'..='z' | 'A'..='Z' | '_' => {
                 while self.peek().is_alphanumeric() || self.peek() == '_' {
                     self.advance();
                 }
-                let text: String = self.source[self.start..self.current].iter().collect();
+                let text = self.text();
                 match text.as_str() {
                     "let" => Some(Token::Let),
                     "print" => Some(Token::Print),
@@ -144,29 +396,54 @@ impl Scanner {
                     "return" => Some(Token::Return),
                     "for" => Some(Token::For),
                     "in" => Some(Token::In),
-                    _ => Some(Token::Identifier(text)),
+                    "nil" => Some(Token::Nil),
+                    _ => Some(Token::Identifier(self.identifiers.intern(&text))),
                 }
             }
 
-            _ => panic!("Unexpected character: '{}' at line {}", c, self.line),
-        }
+            _ => return Err(self.error(format!("Unexpected character '{}'", c))),
+        };
+        Ok(token)
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source[self.current];
+        self.fill(self.current);
+        let c = self.source[self.current - self.base];
         self.current += 1;
         c
     }
 
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source[self.current]
-        }
+    fn peek(&mut self) -> char {
+        self.char_at(self.current)
+    }
+
+    fn peek_next(&mut self) -> char {
+        self.char_at(self.current + 1)
+    }
+
+    /// The char at `index`, or `'\0'` past the end of the source.
+    fn char_at(&mut self, index: usize) -> char {
+        self.fill(index);
+        self.source.get(index - self.base).copied().unwrap_or('\0')
+    }
+
+    fn is_at_end(&mut self) -> bool {
+        self.fill(self.current);
+        self.current >= self.base + self.source.len()
     }
+}
 
-    fn is_at_end(&self) -> bool {
-        self.current >= self.source.len()
+impl Iterator for Scanner {
+    type Item = Result<TokenWithSpan, ScanError>;
+
+    /// The tokens of the source, ending with `Token::EOF`, interleaved with
+    /// any scan errors.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let token = self.next_token();
+        self.finished = matches!(&token, Ok(t) if t.token == Token::EOF);
+        Some(token)
     }
 }
@@ -0,0 +1,142 @@
+//! Copies of an interpreter's global scope that can be restored later.
+//!
+//! Strings, arrays and maps are copy-on-write, so sharing them with the
+//! snapshot already keeps it safe from later updates. Functions are the
+//! exception: their closures are environments, which scripts update in
+//! place. A copy therefore duplicates every environment reachable from the
+//! globals through a closure, keeping the links between them, and rebuilds
+//! only the arrays and maps that hold such functions. Userdata is shared,
+//! since the host owns it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::environment::{Environment, Value};
+
+type EnvRef = Rc<RefCell<Environment>>;
+
+/// The global variables of an interpreter at one moment, taken with
+/// `Interpreter::snapshot` and put back with `Interpreter::restore`.
+/// Restoring does not use the snapshot up, so it can be restored any number
+/// of times, into the interpreter it came from or into another.
+pub struct Snapshot {
+    globals: EnvRef,
+    /// Every environment in the copy, `globals` included, emptied on drop
+    /// because closures link them into cycles.
+    environments: Vec<EnvRef>,
+}
+
+impl Snapshot {
+    /// Copies `globals` and everything its closures reach.
+    pub(crate) fn take(globals: &EnvRef) -> Snapshot {
+        let root = Environment::new();
+        let mut copier = Copier::default();
+        copier.copy_root(globals, &root);
+        Snapshot {
+            globals: root,
+            environments: copier.copies.into_values().collect(),
+        }
+    }
+
+    /// Replaces the contents of `globals` with a fresh copy of the snapshot,
+    /// returning the other environments the copy created so the caller can
+    /// hand them to the cycle collector.
+    pub(crate) fn restore_into(&self, globals: &EnvRef) -> Vec<EnvRef> {
+        let mut copier = Copier::default();
+        copier.copy_root(&self.globals, globals);
+        copier
+            .copies
+            .into_values()
+            .filter(|env| !Rc::ptr_eq(env, globals))
+            .collect()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let contents: Vec<Environment> = self
+            .environments
+            .iter()
+            .map(|env| env.borrow_mut().take_contents())
+            .collect();
+        drop(contents);
+    }
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Snapshot({} environments)", self.environments.len())
+    }
+}
+
+#[derive(Default)]
+struct Copier {
+    /// The copy made of each environment visited so far, by address.
+    copies: HashMap<*const RefCell<Environment>, EnvRef>,
+}
+
+impl Copier {
+    /// Copies the bindings of `source` into `target`, so that closures over
+    /// `source` become closures over `target`. What `target` held before is
+    /// dropped once it is no longer borrowed.
+    fn copy_root(&mut self, source: &EnvRef, target: &EnvRef) {
+        self.copies.insert(Rc::as_ptr(source), Rc::clone(target));
+        let contents = self.contents(source);
+        let previous = std::mem::replace(&mut *target.borrow_mut(), contents);
+        drop(previous);
+    }
+
+    fn environment(&mut self, env: &EnvRef) -> EnvRef {
+        if let Some(copy) = self.copies.get(&Rc::as_ptr(env)) {
+            return Rc::clone(copy);
+        }
+        // Registered before its contents are copied, so a closure stored in
+        // the environment it captured maps back to the copy.
+        let copy = Environment::new();
+        self.copies.insert(Rc::as_ptr(env), Rc::clone(&copy));
+        let contents = self.contents(env);
+        *copy.borrow_mut() = contents;
+        copy
+    }
+
+    fn contents(&mut self, env: &EnvRef) -> Environment {
+        let env = env.borrow();
+        let enclosing = env.enclosing().map(|enclosing| self.environment(enclosing));
+        env.copy_with(enclosing, |value| self.value(value))
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        if !holds_function(value) {
+            return value.clone();
+        }
+        match value {
+            Value::Function(function) => {
+                let mut function = function.clone();
+                function.closure = self.environment(&function.closure);
+                Value::Function(function)
+            }
+            Value::Array(items) => Value::array(items.iter().map(|item| self.value(item))),
+            Value::Map(entries) => Value::map(
+                entries
+                    .iter()
+                    .map(|(key, item)| (key.clone(), self.value(item))),
+            ),
+            Value::Frozen(inner) => Value::Frozen(Box::new(self.value(inner))),
+            other => other.clone(),
+        }
+    }
+}
+
+/// Whether `value` is a function or contains one, and so cannot be shared
+/// with a copy.
+fn holds_function(value: &Value) -> bool {
+    match value {
+        Value::Function(_) => true,
+        Value::Array(items) => items.iter().any(holds_function),
+        Value::Map(entries) => entries.values().any(holds_function),
+        Value::Frozen(inner) => holds_function(inner),
+        _ => false,
+    }
+}
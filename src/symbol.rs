@@ -0,0 +1,28 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// An interned identifier. Every occurrence of a name in one scan shares a
+/// single allocation, so the parser, the AST and the environments copy a
+/// name by bumping a reference count, and names compare equal cheaply.
+pub type Symbol = Rc<str>;
+
+/// The set of names seen so far, handing out one shared `Symbol` per name.
+#[derive(Debug, Default)]
+pub struct Interner {
+    symbols: HashSet<Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return Rc::clone(symbol);
+        }
+        let symbol: Symbol = Rc::from(name);
+        self.symbols.insert(Rc::clone(&symbol));
+        symbol
+    }
+}
@@ -0,0 +1,62 @@
+//! Running the `test "name" { ... }` blocks of a script, for `ruilian test`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::{Program, Span, StmtKind};
+use crate::error::RuntimeError;
+use crate::interpreter::Interpreter;
+
+/// How one test went.
+#[derive(Debug)]
+pub struct Outcome {
+    pub name: String,
+    /// The whole `test` block.
+    pub span: Span,
+    /// The error that stopped the test, such as a failed `assert`.
+    pub result: Result<(), RuntimeError>,
+    /// Everything the test printed.
+    pub output: String,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Runs the top level of `program`, then each test at its top level in
+/// order, passing `report` the outcome of each as it finishes. Every test
+/// starts from the globals the top level left, so what one test changes
+/// is never seen by the next. If the top level fails, its error is
+/// returned and no test runs.
+///
+/// The script's output is captured into the outcomes, so the interpreter
+/// prints nothing of its own from here on.
+pub fn run_tests(
+    interpreter: &mut Interpreter,
+    program: &Program,
+    mut report: impl FnMut(Outcome),
+) -> Result<(), RuntimeError> {
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = Rc::clone(&output);
+    interpreter.set_output_callback(move |text| sink.borrow_mut().push_str(text));
+
+    interpreter.interpret(program)?;
+    let snapshot = interpreter.snapshot();
+    output.borrow_mut().clear();
+    for stmt in &program.statements {
+        let StmtKind::Test { name, body } = &stmt.kind else {
+            continue;
+        };
+        interpreter.restore(&snapshot);
+        let result = interpreter.run_block(body);
+        report(Outcome {
+            name: name.clone(),
+            span: stmt.span,
+            result,
+            output: std::mem::take(&mut *output.borrow_mut()),
+        });
+    }
+    Ok(())
+}
@@ -1,7 +1,11 @@
+use crate::symbol::Symbol;
+
 #[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Number(i64),
+    Float(f64),
     Plus,
     Minus,
     StringLiteral(String),
@@ -12,7 +16,7 @@ pub enum Token {
     LeftBracket,
     RightBracket,
     EOF,
-    Identifier(String),
+    Identifier(Symbol),
     Equals,
     Semicolon,
     Colon,
@@ -42,10 +46,55 @@ pub enum Token {
     Return,
     For,
     In,
+    Nil,
 }
 
 #[derive(Debug, Clone)]
 pub struct TokenWithSpan {
     pub token: Token,
+    /// Byte range of the token in the source.
+    pub span: (usize, usize),
+    /// 1-based line and column (in chars) where the token starts.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A `//` comment, kept by a scanner made `with_comments` for tools that
+/// reproduce the source, such as the formatter. The parser never sees
+/// comments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The comment from its `//` to the end of the line, trailing
+    /// whitespace removed.
+    pub text: String,
+    /// Byte range of `text` in the source.
     pub span: (usize, usize),
 }
+
+/// A 1-based line/column location in source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Maps a byte offset back to a line and column. Offsets past the end
+    /// clamp to the end of the source.
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let mut line = 1;
+        let mut column = 1;
+        for (i, c) in source.char_indices() {
+            if i >= offset {
+                break;
+            }
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        Position { line, column }
+    }
+}
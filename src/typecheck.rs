@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{BinOp, Expr, ExprKind, Program, Stmt, StmtKind, UnaryOp};
+use crate::diagnostic::Diagnostic;
+
+/// Flags operations that are certain to fail at runtime, such as
+/// subtracting a string or calling a number, without running the program.
+///
+/// The check is flow-insensitive: a name has a known type only if every
+/// binding of that name anywhere in the program gives it the same type, so a
+/// variable that is ever reassigned to something else is never flagged.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut checker = TypeChecker::default();
+    checker.collect(&program.statements);
+    for stmt in &program.statements {
+        checker.statement(stmt);
+    }
+    checker.diagnostics.sort_by_key(|d| d.span);
+    checker.diagnostics
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    Float,
+    String,
+    Boolean,
+    Nil,
+    Array,
+    Map,
+    /// A function taking this many parameters.
+    Function(usize),
+}
+
+impl Type {
+    fn describe(self) -> &'static str {
+        match self {
+            Type::Number => "a number",
+            Type::Float => "a float",
+            Type::String => "a string",
+            Type::Boolean => "a boolean",
+            Type::Nil => "nil",
+            Type::Array => "an array",
+            Type::Map => "a map",
+            Type::Function(_) => "a function",
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(self, Type::Number | Type::Float)
+    }
+}
+
+/// Something that gives a name its value.
+enum Source<'a> {
+    Expr(&'a Expr),
+    Known(Type),
+    /// A parameter or loop variable, whose type depends on the caller.
+    Unknown,
+}
+
+#[derive(Default)]
+struct TypeChecker<'a> {
+    bindings: HashMap<&'a str, Vec<Source<'a>>>,
+    resolved: HashMap<&'a str, Option<Type>>,
+    resolving: HashSet<&'a str>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn bind(&mut self, name: &'a str, source: Source<'a>) {
+        self.bindings.entry(name).or_default().push(source);
+    }
+
+    /// Records every binding in the program up front, so uses before a
+    /// declaration (or inside a function declared earlier) see it too.
+    fn collect(&mut self, statements: &'a [Stmt]) {
+        for stmt in statements {
+            match &stmt.kind {
+                StmtKind::Let { name, initializer } => match initializer {
+                    Some(initializer) => {
+                        self.bind(name, Source::Expr(initializer));
+                        self.collect_expr(initializer);
+                    }
+                    None => self.bind(name, Source::Known(Type::Number)),
+                },
+                StmtKind::Expr(expr) | StmtKind::Print(expr) => self.collect_expr(expr),
+                StmtKind::Return { value } => {
+                    if let Some(value) = value {
+                        self.collect_expr(value);
+                    }
+                }
+                StmtKind::Block(statements) => self.collect(statements),
+                StmtKind::Test { body, .. } | StmtKind::Bench { body, .. } => self.collect(body),
+                StmtKind::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                } => {
+                    self.collect_expr(condition);
+                    self.collect(std::slice::from_ref(then_branch));
+                    if let Some(else_branch) = else_branch {
+                        self.collect(std::slice::from_ref(else_branch));
+                    }
+                }
+                StmtKind::While { condition, body } => {
+                    self.collect_expr(condition);
+                    self.collect(std::slice::from_ref(body));
+                }
+                StmtKind::For {
+                    variable,
+                    iterable,
+                    body,
+                } => {
+                    self.bind(variable, Source::Unknown);
+                    self.collect_expr(iterable);
+                    self.collect(std::slice::from_ref(body));
+                }
+                StmtKind::Function {
+                    name, params, body, ..
+                } => {
+                    self.bind(name, Source::Known(Type::Function(params.len())));
+                    for param in params.iter() {
+                        self.bind(param, Source::Unknown);
+                    }
+                    self.collect(body);
+                }
+            }
+        }
+    }
+
+    /// Assignments can sit anywhere inside an expression, e.g. as a call
+    /// argument, so every subexpression is searched for them.
+    fn collect_expr(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Assign { name, value, .. } => self.bind(name, Source::Expr(value)),
+            ExprKind::Lambda { params, body, .. } => {
+                for param in params.iter() {
+                    self.bind(param, Source::Unknown);
+                }
+                self.collect(body);
+            }
+            _ => {}
+        }
+        for child in children(expr) {
+            self.collect_expr(child);
+        }
+    }
+
+    fn variable_type(&mut self, name: &'a str) -> Option<Type> {
+        if let Some(resolved) = self.resolved.get(name) {
+            return *resolved;
+        }
+        // A binding that refers back to itself, like `x = x + 1`, cannot
+        // pin the type down on its own.
+        if !self.resolving.insert(name) {
+            return None;
+        }
+        let sources = self.bindings.remove(name).unwrap_or_default();
+        let mut types = Vec::with_capacity(sources.len());
+        for source in &sources {
+            types.push(match source {
+                Source::Expr(expr) => self.type_of(expr),
+                Source::Known(ty) => Some(*ty),
+                Source::Unknown => None,
+            });
+        }
+        let ty = match types.split_first() {
+            Some((first, rest)) if rest.iter().all(|ty| ty == first) => *first,
+            _ => None,
+        };
+        self.bindings.insert(name, sources);
+        self.resolving.remove(name);
+        self.resolved.insert(name, ty);
+        ty
+    }
+
+    fn type_of(&mut self, expr: &'a Expr) -> Option<Type> {
+        match &expr.kind {
+            ExprKind::Number(_) => Some(Type::Number),
+            ExprKind::Float(_) => Some(Type::Float),
+            ExprKind::String(_) => Some(Type::String),
+            ExprKind::Boolean(_) => Some(Type::Boolean),
+            ExprKind::Nil => Some(Type::Nil),
+            ExprKind::Array(_) => Some(Type::Array),
+            ExprKind::Map(_) => Some(Type::Map),
+            ExprKind::Lambda { params, .. } => Some(Type::Function(params.len())),
+            ExprKind::Variable { name, .. } => self.variable_type(name),
+            ExprKind::Assign { value, .. } => self.type_of(value),
+            ExprKind::Unary {
+                operator: UnaryOp::Not,
+                ..
+            } => Some(Type::Boolean),
+            ExprKind::Unary {
+                operator: UnaryOp::Negate,
+                right,
+            } => self.type_of(right).filter(|ty| ty.is_numeric()),
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let (left, right) = (self.type_of(left), self.type_of(right));
+                match operator {
+                    BinOp::Greater
+                    | BinOp::GreaterEqual
+                    | BinOp::Less
+                    | BinOp::LessEqual
+                    | BinOp::EqualEqual
+                    | BinOp::BangEqual => Some(Type::Boolean),
+                    BinOp::Add if left == Some(Type::String) || right == Some(Type::String) => {
+                        Some(Type::String)
+                    }
+                    _ => match (left?, right?) {
+                        (Type::Number, Type::Number) => Some(Type::Number),
+                        (a, b) if a.is_numeric() && b.is_numeric() => Some(Type::Float),
+                        _ => None,
+                    },
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn error(&mut self, code: &'static str, message: String, expr: &Expr) {
+        self.diagnostics
+            .push(Diagnostic::error(message, Some(expr.span)).with_code(code));
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.expression(expr),
+            StmtKind::Let { initializer, .. } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+            }
+            StmtKind::Return { value } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StmtKind::Block(statements) => {
+                for stmt in statements {
+                    self.statement(stmt);
+                }
+            }
+            StmtKind::Function { body, .. }
+            | StmtKind::Test { body, .. }
+            | StmtKind::Bench { body, .. } => {
+                for stmt in body.iter() {
+                    self.statement(stmt);
+                }
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            StmtKind::For { iterable, body, .. } => {
+                self.expression(iterable);
+                self.statement(body);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => self.check_binary(expr, left, operator, right),
+            ExprKind::Unary {
+                operator: UnaryOp::Negate,
+                right,
+            } => {
+                if let Some(ty) = self.type_of(right).filter(|ty| !ty.is_numeric()) {
+                    self.error("E0201", format!("Cannot negate {}", ty.describe()), expr);
+                }
+            }
+            ExprKind::Call { callee, arguments } => self.check_call(expr, callee, arguments),
+            ExprKind::Lambda { body, .. } => {
+                for stmt in body.iter() {
+                    self.statement(stmt);
+                }
+            }
+            _ => {}
+        }
+        for child in children(expr) {
+            self.expression(child);
+        }
+    }
+
+    fn check_binary(&mut self, expr: &Expr, left: &'a Expr, operator: &BinOp, right: &'a Expr) {
+        let (left, right) = (self.type_of(left), self.type_of(right));
+        let verb = match operator {
+            BinOp::Add => "add",
+            BinOp::Subtract => "subtract",
+            BinOp::Multiply => "multiply",
+            BinOp::Divide => "divide",
+            BinOp::Greater | BinOp::GreaterEqual | BinOp::Less | BinOp::LessEqual => "compare",
+            BinOp::EqualEqual | BinOp::BangEqual => return,
+        };
+        let offending = match operator {
+            // Anything can be added to a string, so `+` is only wrong when
+            // both sides are known.
+            BinOp::Add => match (left, right) {
+                (Some(a), Some(b)) if !can_add(a, b) => Some((a, b)),
+                _ => None,
+            },
+            BinOp::Multiply => match (left, right) {
+                (Some(Type::String), Some(Type::String)) => Some((Type::String, Type::String)),
+                (Some(a), b) | (b, Some(a)) if !a.is_numeric() && a != Type::String => {
+                    Some((a, b.unwrap_or(a)))
+                }
+                _ => None,
+            },
+            _ => match (left, right) {
+                (Some(a), b) | (b, Some(a)) if !a.is_numeric() => Some((a, b.unwrap_or(a))),
+                _ => None,
+            },
+        };
+        if let Some((a, b)) = offending {
+            let message = if a == b {
+                format!("Cannot {} {}", verb, a.describe())
+            } else {
+                format!("Cannot {} {} and {}", verb, a.describe(), b.describe())
+            };
+            self.error("E0201", message, expr);
+        }
+    }
+
+    fn check_call(&mut self, expr: &Expr, callee: &'a Expr, arguments: &[Expr]) {
+        let name = match &callee.kind {
+            ExprKind::Variable { name, .. } => format!("'{}'", name),
+            _ => "this function".to_string(),
+        };
+        match self.type_of(callee) {
+            Some(Type::Function(arity)) if arity != arguments.len() => {
+                self.error(
+                    "E0203",
+                    format!(
+                        "{} takes {} argument(s) but {} were given",
+                        name,
+                        arity,
+                        arguments.len()
+                    ),
+                    expr,
+                );
+            }
+            Some(Type::Function(_)) | None => {}
+            Some(ty) => self.error("E0202", format!("Cannot call {}", ty.describe()), expr),
+        }
+    }
+}
+
+fn can_add(a: Type, b: Type) -> bool {
+    a == Type::String
+        || b == Type::String
+        || (a.is_numeric() && b.is_numeric())
+        || (a == Type::Array && b == Type::Array)
+}
+
+fn children(expr: &Expr) -> Vec<&Expr> {
+    match &expr.kind {
+        ExprKind::Number(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Boolean(_)
+        | ExprKind::Nil
+        | ExprKind::Variable { .. }
+        | ExprKind::Lambda { .. } => Vec::new(),
+        ExprKind::Assign { value, .. } => vec![value],
+        ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+            vec![left, right]
+        }
+        ExprKind::Unary { right, .. } => vec![right],
+        ExprKind::Call { callee, arguments } => {
+            std::iter::once(&**callee).chain(arguments).collect()
+        }
+        ExprKind::Array(elements) => elements.iter().collect(),
+        ExprKind::Map(pairs) => pairs.iter().map(|(_, value)| value).collect(),
+        ExprKind::Index { object, index } => vec![object, index],
+        ExprKind::IndexAssign {
+            object,
+            index,
+            value,
+        } => vec![object, index, value],
+        ExprKind::Dot { object, .. } => vec![object],
+        ExprKind::DotAssign { object, value, .. } => vec![object, value],
+    }
+}
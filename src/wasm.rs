@@ -0,0 +1,91 @@
+//! The API behind a browser playground.
+//!
+//! Build with `cargo build --lib --release --target wasm32-unknown-unknown`
+//! and load the module with `web/ruilian.js`, which wraps the exports below
+//! as `run(source)`. The crate has no dependencies, wasm-bindgen included,
+//! so the exports pass bytes through the module's memory by hand. Only they
+//! are wasm-specific: `run_json`, which does the work, builds everywhere.
+
+use crate::diagnostic::{Diagnostic, json_string};
+use crate::environment::Value;
+use crate::interpreter::value_to_string;
+
+/// Runs `source` with its output captured and describes the run as a JSON
+/// object:
+///
+/// ```text
+/// {"output":"3\n","result":"6","errors":[]}
+/// ```
+///
+/// `result` is the value of the script's last expression statement, or
+/// null when there is none or the script failed. `errors` holds the
+/// diagnostics as encoded by `Diagnostic::to_json`.
+pub fn run_json(source: &str) -> String {
+    let run = crate::run_source_captured(source);
+    let (result, errors) = match &run.result {
+        Ok(Value::Nil) => ("null".to_string(), Vec::new()),
+        Ok(value) => (json_string(&value_to_string(value)), Vec::new()),
+        Err(error) => ("null".to_string(), Diagnostic::from_run_error(error)),
+    };
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|diagnostic| diagnostic.to_json(source))
+        .collect();
+    format!(
+        "{{\"output\":{},\"result\":{},\"errors\":[{}]}}",
+        json_string(&run.output),
+        result,
+        errors.join(",")
+    )
+}
+
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    /// Reserves `len` bytes for the caller to copy source text into.
+    #[unsafe(no_mangle)]
+    pub extern "C" fn ruilian_alloc(len: usize) -> *mut u8 {
+        let mut buffer = vec![0u8; len].into_boxed_slice();
+        let ptr = buffer.as_mut_ptr();
+        std::mem::forget(buffer);
+        ptr
+    }
+
+    /// Frees `len` bytes from `ruilian_alloc` or `ruilian_run`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must describe a block this module handed out and
+    /// that has not been freed yet.
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn ruilian_buffer_free(ptr: *mut u8, len: usize) {
+        // SAFETY: the caller passes back a block allocated as a `Box<[u8]>`
+        // of `len` bytes, which has the layout of a `Vec<u8>` with that
+        // capacity.
+        drop(unsafe { Vec::from_raw_parts(ptr, 0, len) });
+    }
+
+    /// Runs the `len` bytes of UTF-8 source at `ptr`, freeing them, and
+    /// returns a block holding the length of the `run_json` result as a
+    /// little-endian `u32` followed by the result itself. The caller frees
+    /// it with `ruilian_buffer_free(block, 4 + length)`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must come from `ruilian_alloc(len)`, with all `len`
+    /// bytes written.
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn ruilian_run(ptr: *mut u8, len: usize) -> *mut u8 {
+        // SAFETY: the caller filled the `len` bytes it got from
+        // `ruilian_alloc(len)` and gives up the block.
+        let source = unsafe { Vec::from_raw_parts(ptr, len, len) };
+        let json = super::run_json(&String::from_utf8_lossy(&source));
+        let mut block = Vec::with_capacity(4 + json.len());
+        block.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        block.extend_from_slice(json.as_bytes());
+        // Exactly as long as the length `ruilian_buffer_free` is given.
+        let mut block = block.into_boxed_slice();
+        let ptr = block.as_mut_ptr();
+        std::mem::forget(block);
+        ptr
+    }
+}
@@ -0,0 +1,121 @@
+//! Runs the `ruilian` binary the way a shell or editor would.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Writes `source` to a fresh script file named after `name`.
+fn script(name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ruilian-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.rl", name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn ruilian(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ruilian"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn run(name: &str, source: &str) -> Output {
+    let path = script(name, source);
+    ruilian(&["run", path.to_str().unwrap()])
+}
+
+#[test]
+fn exit_codes_tell_failures_apart() {
+    let output = run("ok", "print 1;");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"1\n");
+
+    assert_eq!(run("parse", "print (;").status.code(), Some(65));
+    assert_eq!(run("runtime", "print 1 / 0;").status.code(), Some(70));
+    assert_eq!(run("exit", "exit(3); print 1;").status.code(), Some(3));
+    assert_eq!(
+        ruilian(&["run", "/nonexistent/script.rl"]).status.code(),
+        Some(66)
+    );
+    assert_eq!(ruilian(&["run", "--bogus"]).status.code(), Some(2));
+}
+
+#[test]
+fn check_reports_without_running() {
+    let clean = script("check_clean", "let x = 1; print x;");
+    let output = ruilian(&["check", clean.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let warned = script("check_warned", "let unused = 1;");
+    let output = ruilian(&["check", warned.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unused variable 'unused'"));
+
+    // Every file is checked, and nothing in them runs.
+    let unparsed = script("check_unparsed", "print 1 +;");
+    let undefined = script("check_undefined", "print 1;\nprint missing;");
+    let output = ruilian(&[
+        "check",
+        unparsed.to_str().unwrap(),
+        undefined.to_str().unwrap(),
+        clean.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(errors.matches("error[").count(), 2, "{}", errors);
+}
+
+#[test]
+fn watch_reruns_the_script_when_it_changes() {
+    use std::io::Read;
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant, SystemTime};
+
+    let path = script("watched", "print \"first\";");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ruilian"))
+        .args(["run", "--watch", path.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, chunks) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buffer = [0; 256];
+        while let Ok(read @ 1..) = stdout.read(&mut buffer) {
+            if sender.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut printed = String::new();
+    let mut wait_for = |text: &str| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !printed.contains(text) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match chunks.recv_timeout(left) {
+                Ok(chunk) => printed.push_str(&String::from_utf8_lossy(&chunk)),
+                Err(_) => return false,
+            }
+        }
+        true
+    };
+
+    let first = wait_for("first\n");
+    std::fs::write(&path, "print \"second\";").unwrap();
+    // Make the change visible even where modification times are coarse.
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(5))
+        .unwrap();
+    let second = wait_for("second\n");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(first && second, "{:?}", printed);
+    assert_eq!(printed.matches("\x1b[2J").count(), 2, "{:?}", printed);
+}
@@ -0,0 +1,27 @@
+// Loads the RuiLian wasm module and runs scripts in it:
+//
+//   const ruilian = await load("ruilian.wasm");
+//   const { output, result, errors } = ruilian.run('print "hi";');
+//
+// See src/wasm.rs for how to build the module.
+
+export async function load(url) {
+  const { instance } = await WebAssembly.instantiateStreaming(fetch(url));
+  const wasm = instance.exports;
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+
+  return {
+    run(source) {
+      const bytes = encoder.encode(source);
+      const input = wasm.ruilian_alloc(bytes.length);
+      new Uint8Array(wasm.memory.buffer, input, bytes.length).set(bytes);
+      // Frees the input; the memory may grow, so views are made afterwards.
+      const block = wasm.ruilian_run(input, bytes.length);
+      const length = new DataView(wasm.memory.buffer).getUint32(block, true);
+      const json = decoder.decode(new Uint8Array(wasm.memory.buffer, block + 4, length));
+      wasm.ruilian_buffer_free(block, 4 + length);
+      return JSON.parse(json);
+    },
+  };
+}